@@ -2,11 +2,12 @@
 #![allow(non_camel_case_types)]
 #![allow(non_snake_case)]
 
+#[allow(clippy::useless_transmute, clippy::missing_safety_doc)]
 mod bindings;
 
 pub use bindings::*;
 
-#[cfg(test)]
+#[cfg(all(test, windows))]
 mod tests {
     use super::*;
 
@@ -0,0 +1,376 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+
+use libusbk_sys::WINUSB_SETUP_PACKET;
+
+use crate::error::Error;
+use crate::overlapped::{OverlappedPool, Transfer};
+use crate::Result;
+
+/// The number of transfers that can be in flight on a handle through its futures. Together with
+/// the event that wakes the completion thread, this is as many handles as
+/// `WaitForMultipleObjects` can wait on.
+pub(crate) const MAX_TRANSFERS: usize = 63;
+
+/// Starts transfers for the futures of a [`DeviceHandle`](crate::DeviceHandle) and completes
+/// them from a background thread.
+///
+/// The thread exits once every clone has been dropped, cancelling the transfers still in flight.
+#[derive(Debug, Clone)]
+pub(crate) struct AsyncIo {
+    pool: OverlappedPool,
+    jobs: Arc<Jobs>,
+}
+
+impl AsyncIo {
+    pub(crate) fn new(pool: OverlappedPool) -> Result<Self> {
+        let (sender, rx) = mpsc::channel();
+        let thread_pool = pool.clone();
+        thread::Builder::new()
+            .name("libusbk-completion".into())
+            .spawn(move || complete(thread_pool, rx))
+            .map_err(|err| Error::from_code(err.raw_os_error().unwrap_or_default() as u32))?;
+        let jobs = Jobs {
+            sender: Some(sender),
+            pool: pool.clone(),
+        };
+        Ok(Self {
+            pool,
+            jobs: Arc::new(jobs),
+        })
+    }
+
+    pub(crate) fn read(&self, pipe_id: u8, len: usize) -> TransferFuture<Vec<u8>> {
+        self.start(self.pool.submit_read(pipe_id, vec![0; len]), |data| data)
+    }
+
+    pub(crate) fn write(&self, pipe_id: u8, data: Vec<u8>) -> TransferFuture<usize> {
+        self.start(self.pool.submit_write(pipe_id, data), |data| data.len())
+    }
+
+    pub(crate) fn control_in(
+        &self,
+        setup: WINUSB_SETUP_PACKET,
+        len: usize,
+    ) -> TransferFuture<Vec<u8>> {
+        self.start(self.pool.submit_control(setup, vec![0; len]), |data| data)
+    }
+
+    pub(crate) fn control_out(
+        &self,
+        setup: WINUSB_SETUP_PACKET,
+        data: Vec<u8>,
+    ) -> TransferFuture<usize> {
+        self.start(self.pool.submit_control(setup, data), |data| data.len())
+    }
+
+    fn start<T>(&self, transfer: Result<Transfer>, map: fn(Vec<u8>) -> T) -> TransferFuture<T> {
+        let mut future = TransferFuture::new(map);
+        match transfer {
+            Ok(transfer) => {
+                let job = Job {
+                    transfer: Some(transfer),
+                    shared: future.shared.clone(),
+                };
+                self.jobs.send(job);
+                future.pool = Some(self.pool.clone());
+            }
+            Err(err) => future.shared.lock().unwrap().result = Some(Err(err)),
+        }
+        future
+    }
+}
+
+/// A transfer started from a [`DeviceHandle`](crate::DeviceHandle) that resolves once it has
+/// completed.
+///
+/// The future does not borrow the handle and can be awaited from any executor. Dropping it
+/// before it resolves cancels the transfer.
+#[must_use = "futures do nothing unless polled, and dropping one cancels its transfer"]
+pub struct TransferFuture<T> {
+    shared: Arc<Mutex<Shared>>,
+    map: fn(Vec<u8>) -> T,
+    /// The pool of a transfer in flight, to wake the completion thread when it is abandoned.
+    pool: Option<OverlappedPool>,
+}
+
+impl<T> TransferFuture<T> {
+    fn new(map: fn(Vec<u8>) -> T) -> Self {
+        Self {
+            shared: Arc::new(Mutex::new(Shared::default())),
+            map,
+            pool: None,
+        }
+    }
+
+    /// Creates a future that resolves to `err` immediately.
+    pub(crate) fn failed(err: Error, map: fn(Vec<u8>) -> T) -> Self {
+        let future = Self::new(map);
+        future.shared.lock().unwrap().result = Some(Err(err));
+        future
+    }
+}
+
+impl<T> Future for TransferFuture<T> {
+    type Output = Result<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<T>> {
+        let mut shared = self.shared.lock().unwrap();
+        match shared.result.take() {
+            Some(result) => Poll::Ready(result.map(self.map)),
+            None => {
+                shared.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<T> Drop for TransferFuture<T> {
+    fn drop(&mut self) {
+        let mut shared = self.shared.lock().unwrap();
+        shared.abandoned = true;
+        if let (None, Some(pool)) = (&shared.result, &self.pool) {
+            pool.wake();
+        }
+    }
+}
+
+impl<T> fmt::Debug for TransferFuture<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TransferFuture")
+            .field("shared", &self.shared)
+            .finish()
+    }
+}
+
+/// The sending side of the completion thread's queue.
+#[derive(Debug)]
+struct Jobs {
+    sender: Option<Sender<Job>>,
+    pool: OverlappedPool,
+}
+
+impl Jobs {
+    /// Queues a job and wakes the thread to wait on its transfer.
+    fn send(&self, job: Job) {
+        // The thread only exits after the sender is gone, so this cannot fail.
+        let _ = self.sender.as_ref().unwrap().send(job);
+        self.pool.wake();
+    }
+}
+
+impl Drop for Jobs {
+    /// Disconnects the queue and wakes the thread so it notices.
+    fn drop(&mut self) {
+        self.sender = None;
+        self.pool.wake();
+    }
+}
+
+/// State shared between a future and the completion thread.
+#[derive(Debug, Default)]
+struct Shared {
+    /// The bytes transferred, once the transfer has completed.
+    result: Option<Result<Vec<u8>>>,
+    waker: Option<Waker>,
+    /// Set when the future is dropped, so the thread cancels the transfer.
+    abandoned: bool,
+}
+
+#[derive(Debug)]
+struct Job {
+    transfer: Option<Transfer>,
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl Job {
+    /// Hands the result to the future if the transfer has completed. Returns `true` once the
+    /// job is done with.
+    fn poll(&mut self) -> bool {
+        let mut shared = self.shared.lock().unwrap();
+        if shared.abandoned {
+            // Dropping the transfer cancels it.
+            return true;
+        }
+        if !self.transfer.as_ref().is_some_and(Transfer::is_complete) {
+            return false;
+        }
+        let mut transfer = self.transfer.take().unwrap();
+        let result = transfer.wait().map(|len| into_data(transfer, len));
+        self.finish(&mut shared, result);
+        true
+    }
+
+    fn cancel(mut self) {
+        if let Some(mut transfer) = self.transfer.take() {
+            let result = transfer.cancel().map(|len| into_data(transfer, len));
+            let mut shared = self.shared.lock().unwrap();
+            self.finish(&mut shared, result);
+        }
+    }
+
+    fn finish(&self, shared: &mut Shared, result: Result<Vec<u8>>) {
+        shared.result = Some(result);
+        if let Some(waker) = shared.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+fn into_data(transfer: Transfer, len: u32) -> Vec<u8> {
+    let mut data = transfer.into_buffer();
+    data.truncate(len as usize);
+    data
+}
+
+/// The body of the completion thread.
+fn complete(pool: OverlappedPool, jobs: Receiver<Job>) {
+    let mut pending = VecDeque::new();
+    loop {
+        if pending.is_empty() {
+            match jobs.recv() {
+                Ok(job) => pending.push_back(job),
+                Err(_) => return,
+            }
+        }
+        loop {
+            match jobs.try_recv() {
+                Ok(job) => pending.push_back(job),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    pending.into_iter().for_each(Job::cancel);
+                    return;
+                }
+            }
+        }
+
+        pending.retain_mut(|job| !job.poll());
+        if !pending.is_empty() {
+            // Returns when a transfer completes, a job is queued, a future is dropped or the
+            // queue is disconnected.
+            let _ = pool.wait_any();
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::sync::Arc;
+    use std::task::Wake;
+    use std::thread::Thread;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::backend::sim::tests::open;
+    use crate::backend::{SimControl, SimDevice, SimOp};
+    use crate::{request_type, Direction, Recipient, RequestType};
+
+    struct ThreadWaker(Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    /// Runs a future to completion on the current thread.
+    pub(crate) fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = std::pin::pin!(future);
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+            thread::park();
+        }
+    }
+
+    #[test]
+    fn closing_the_handle_cancels_pending_futures() {
+        let device = SimDevice::new(0x1234, 0x5678);
+        let handle = open(&device);
+
+        let abandoned = handle.read_bulk(0x81, 8);
+        let pending = handle.read_bulk(0x81, 8);
+        drop(abandoned);
+        drop(handle);
+        assert!(matches!(block_on(pending), Err(Error::Cancelled(_))));
+
+        // The completion thread has let go of the pool and its transfers.
+        while device.open_handles() > 0 {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn overlapping_futures_complete_independently() {
+        let device = SimDevice::new(0x1234, 0x5678);
+        device.set_blocking_reads(0x81, true);
+        let handle = open(&device);
+
+        let abandoned = handle.read_bulk(0x81, 8);
+        let pending = handle.read_bulk(0x81, 8);
+        thread::sleep(Duration::from_millis(10));
+        drop(abandoned);
+
+        // Submitting while another transfer is outstanding must not wait for it.
+        assert_eq!(block_on(handle.write_bulk(0x02, &[1, 2])), Ok(2));
+        device.push_read(0x81, &[3, 4, 5]);
+        assert_eq!(block_on(pending), Ok(vec![3, 4, 5]));
+    }
+
+    #[test]
+    fn futures_complete_transfers() {
+        let device = SimDevice::new(0x1234, 0x5678);
+        let handle = open(&device);
+        let vendor_in = request_type(Direction::In, RequestType::Vendor, Recipient::Device);
+        let vendor_out = request_type(Direction::Out, RequestType::Vendor, Recipient::Device);
+
+        let read = handle.read_bulk(0x81, 64);
+        let write = handle.write_bulk(0x01, &[1, 2]);
+        device.push_read(0x81, &[3, 4, 5]);
+        assert_eq!(block_on(read).unwrap(), [3, 4, 5]);
+        assert_eq!(block_on(write).unwrap(), 2);
+        assert_eq!(device.take_writes(0x01), vec![vec![1, 2]]);
+
+        device.push_control_in(&[6, 7, 8]);
+        let data = block_on(handle.control_in(vendor_in, 0x10, 1, 2, 2)).unwrap();
+        assert_eq!(data, [6, 7]);
+        assert_eq!(
+            block_on(handle.control_out(vendor_out, 0x11, 3, 4, &[9])).unwrap(),
+            1
+        );
+        assert_eq!(
+            device.control_log()[1],
+            SimControl {
+                request_type: vendor_out,
+                request: 0x11,
+                value: 3,
+                index: 4,
+                data: vec![9],
+            }
+        );
+
+        assert!(matches!(
+            block_on(handle.read_bulk(0x01, 8)),
+            Err(Error::InvalidParam(_))
+        ));
+        assert!(matches!(
+            block_on(handle.control_out(vendor_in, 0x11, 0, 0, &[])),
+            Err(Error::InvalidParam(_))
+        ));
+        device.inject_error(SimOp::WritePipe, 31);
+        assert_eq!(
+            block_on(handle.write_bulk(0x01, &[0])),
+            Err(Error::Pipe(31))
+        );
+    }
+}
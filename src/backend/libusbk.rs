@@ -0,0 +1,1234 @@
+use std::ffi::c_void;
+use std::mem;
+use std::ptr::NonNull;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use libusbk_sys::{
+    HotK_Free, HotK_Init, IsoK_Free, IsoK_GetPacket, IsoK_Init, IsoK_SetPacket,
+    IsochK_CalcPacketInformation, IsochK_Free, IsochK_GetPacket, IsochK_Init, IsochK_SetPacket,
+    LibK_GetContext, LibK_LoadDriverAPI, LibK_SetContext, LstK_Count, LstK_FindByVidPid, LstK_Free,
+    LstK_Init, LstK_InitEx, LstK_MoveNext, LstK_MoveReset, OvlK_Acquire, OvlK_Free,
+    OvlK_GetEventHandle, OvlK_Init, OvlK_IsComplete, OvlK_Release, OvlK_Wait, OvlK_WaitOldest,
+    OvlK_WaitOrCancel, StmK_Free, StmK_Init, StmK_Read, StmK_Start, StmK_Stop, StmK_Write,
+    _KISO_FLAG_KISO_FLAG_SET_START_FRAME, _KLIB_HANDLE_TYPE_KLIB_HANDLE_TYPE_HOTK,
+    _KLIB_HANDLE_TYPE_KLIB_HANDLE_TYPE_STMK, _KLST_SYNC_FLAG_KLST_SYNC_FLAG_ADDED,
+    _KLST_SYNC_FLAG_KLST_SYNC_FLAG_REMOVED, _KOVL_POOL_FLAG_KOVL_POOL_FLAG_NONE,
+    _KOVL_WAIT_FLAG_KOVL_WAIT_FLAG_NONE, _USB_SUPERSPEED_ENDPOINT_COMPANION_DESCRIPTOR, BOOL,
+    HANDLE, INT, KHOT_HANDLE, KHOT_PARAMS, KISOCH_HANDLE, KISOCH_PACKET_INFORMATION, KISO_PACKET,
+    KLIB_USER_CONTEXT, KLST_DEVINFO, KLST_DEVINFO_HANDLE, KLST_FLAG, KLST_PATTERN_MATCH,
+    KLST_SYNC_FLAG, KOVL_HANDLE, KSTM_CALLBACK, KUSB_DRIVER_API, KUSB_PROPERTY, LPOVERLAPPED,
+    PKISO_CONTEXT, PKSTM_INFO, PKSTM_XFER_CONTEXT, USB_INTERFACE_DESCRIPTOR,
+    WINUSB_PIPE_INFORMATION, WINUSB_PIPE_INFORMATION_EX, WINUSB_SETUP_PACKET,
+};
+
+use super::{
+    Backend, DeviceHandleBackend, DeviceListBackend, DeviceWatch, OverlappedPoolBackend,
+    PendingTransfer, StreamBackend,
+};
+use crate::error::{last_error, try_unsafe, Error, ERROR_IO_PENDING, ERROR_NOT_SUPPORTED};
+use crate::fields::{ENDPOINT_DIR_MASK, ENDPOINT_IN};
+use crate::iso::IsoPacket;
+use crate::stream::{StreamBuilder, StreamCallbacks};
+use crate::{DriverId, Result};
+
+type KListHandle = NonNull<c_void>;
+type UsbkHandle = NonNull<c_void>;
+
+/// The native `libusbK` backend.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LibUsbK;
+
+impl Backend for LibUsbK {
+    fn device_list(
+        &self,
+        flags: KLST_FLAG,
+        pattern: Option<&KLST_PATTERN_MATCH>,
+    ) -> Result<Box<dyn DeviceListBackend>> {
+        let mut context = mem::MaybeUninit::<*mut c_void>::uninit();
+
+        match pattern {
+            Some(pattern) => {
+                let mut pattern = *pattern;
+                try_unsafe!(LstK_InitEx(context.as_mut_ptr(), flags, &mut pattern));
+            }
+            None => try_unsafe!(LstK_Init(context.as_mut_ptr(), flags)),
+        }
+
+        Ok(Box::new(LibUsbKList {
+            inner: unsafe { NonNull::new_unchecked(context.assume_init()) },
+            cursor: Mutex::new(()),
+        }))
+    }
+
+    fn open(&self, info: &KLST_DEVINFO) -> Result<Box<dyn DeviceHandleBackend>> {
+        let mut handle = mem::MaybeUninit::<*mut c_void>::uninit();
+
+        let mut dev: KUSB_DRIVER_API = unsafe { mem::zeroed() };
+        // TODO: this may not be desirable always
+        try_unsafe!(LibK_LoadDriverAPI(&mut dev, info.DriverID));
+
+        // Only valid because `info` is an element of a device list, see `Backend::open`.
+        let info = info as *const KLST_DEVINFO as KLST_DEVINFO_HANDLE;
+        try_unsafe!(dev.Init.unwrap()(handle.as_mut_ptr(), info));
+
+        let handle = unsafe { NonNull::new(handle.assume_init()).unwrap() };
+        Ok(Box::new(LibUsbKHandle {
+            raw: Arc::new(RawHandle {
+                dev,
+                handle,
+                driver_id: unsafe { (*info).DriverID },
+                _parent: None,
+            }),
+        }))
+    }
+
+    fn watch(&self, pattern: &KLST_PATTERN_MATCH) -> Result<Box<dyn DeviceWatch>> {
+        let mut params = KHOT_PARAMS {
+            PatternMatch: *pattern,
+            OnHotPlug: Some(on_watch_hotplug),
+            ..Default::default()
+        };
+        let mut handle = std::ptr::null_mut();
+        try_unsafe!(HotK_Init(&mut handle, &mut params));
+
+        // Events reported before the context is set are dropped, but none are expected until
+        // the caller acts on the device.
+        let watch = LibUsbKWatch {
+            handle,
+            counts: Box::default(),
+        };
+        try_unsafe!(LibK_SetContext(
+            watch.handle,
+            _KLIB_HANDLE_TYPE_KLIB_HANDLE_TYPE_HOTK,
+            &*watch.counts as *const WatchCounts as KLIB_USER_CONTEXT,
+        ));
+        Ok(Box::new(watch))
+    }
+}
+
+#[derive(Debug)]
+struct LibUsbKWatch {
+    handle: KHOT_HANDLE,
+    /// Registered with `LibK_SetContext`, so it must not move while the handle is alive.
+    counts: Box<WatchCounts>,
+}
+
+#[derive(Debug, Default)]
+struct WatchCounts {
+    arrivals: AtomicU32,
+    removals: AtomicU32,
+}
+
+unsafe impl Send for LibUsbKWatch {}
+
+impl Drop for LibUsbKWatch {
+    fn drop(&mut self) {
+        unsafe { HotK_Free(self.handle) };
+    }
+}
+
+impl DeviceWatch for LibUsbKWatch {
+    fn arrivals(&self) -> u32 {
+        self.counts.arrivals.load(Ordering::SeqCst)
+    }
+
+    fn removals(&self) -> u32 {
+        self.counts.removals.load(Ordering::SeqCst)
+    }
+}
+
+unsafe extern "C" fn on_watch_hotplug(
+    handle: KHOT_HANDLE,
+    _info: KLST_DEVINFO_HANDLE,
+    sync_flag: KLST_SYNC_FLAG,
+) {
+    let context = LibK_GetContext(handle, _KLIB_HANDLE_TYPE_KLIB_HANDLE_TYPE_HOTK);
+    let Some(counts) = (context as *const WatchCounts).as_ref() else {
+        return;
+    };
+    match sync_flag {
+        _KLST_SYNC_FLAG_KLST_SYNC_FLAG_ADDED => counts.arrivals.fetch_add(1, Ordering::SeqCst),
+        _KLST_SYNC_FLAG_KLST_SYNC_FLAG_REMOVED => counts.removals.fetch_add(1, Ordering::SeqCst),
+        _ => return,
+    };
+}
+
+#[derive(Debug)]
+struct LibUsbKList {
+    inner: KListHandle,
+    /// Guards the enumeration cursor stored in the list handle.
+    cursor: Mutex<()>,
+}
+
+unsafe impl Send for LibUsbKList {}
+unsafe impl Sync for LibUsbKList {}
+
+impl Drop for LibUsbKList {
+    fn drop(&mut self) {
+        unsafe { LstK_Free(self.inner.as_ptr()) };
+    }
+}
+
+impl DeviceListBackend for LibUsbKList {
+    fn count(&self) -> Result<u32> {
+        let mut count = 0;
+        try_unsafe!(LstK_Count(self.inner.as_ptr(), &mut count));
+        Ok(count)
+    }
+
+    fn devices(&self) -> Vec<KLST_DEVINFO_HANDLE> {
+        let _cursor = self.cursor.lock().unwrap();
+        let mut devices = Vec::new();
+        let mut info = std::ptr::null_mut();
+
+        unsafe { LstK_MoveReset(self.inner.as_ptr()) };
+        while unsafe { LstK_MoveNext(self.inner.as_ptr(), &mut info) } != 0 {
+            devices.push(info);
+        }
+        devices
+    }
+
+    fn find_by_vid_pid(&self, vid: i32, pid: i32) -> Result<KLST_DEVINFO_HANDLE> {
+        let mut info = std::ptr::null_mut();
+        try_unsafe!(LstK_FindByVidPid(self.inner.as_ptr(), vid, pid, &mut info));
+        Ok(info)
+    }
+}
+
+#[derive(Debug)]
+struct LibUsbKHandle {
+    raw: Arc<RawHandle>,
+}
+
+/// An open `libusbK` handle, shared with the overlapped pools created from it.
+#[derive(Debug)]
+struct RawHandle {
+    dev: KUSB_DRIVER_API,
+    handle: UsbkHandle,
+    driver_id: i32,
+    /// The handle this one was derived from, which must be freed after it.
+    _parent: Option<Arc<RawHandle>>,
+}
+
+unsafe impl Send for RawHandle {}
+unsafe impl Sync for RawHandle {}
+
+impl Drop for RawHandle {
+    fn drop(&mut self) {
+        unsafe { self.dev.Free.unwrap()(self.handle.as_ptr()) };
+    }
+}
+
+impl DeviceHandleBackend for LibUsbKHandle {
+    fn get_configuration(&mut self) -> Result<u8> {
+        let mut value = 0;
+        try_unsafe!(self.raw.dev.GetConfiguration.unwrap()(
+            self.raw.handle.as_ptr(),
+            &mut value
+        ));
+        Ok(value)
+    }
+
+    fn set_configuration(&mut self, value: u8) -> Result<()> {
+        try_unsafe!(self.raw.dev.SetConfiguration.unwrap()(
+            self.raw.handle.as_ptr(),
+            value
+        ));
+        Ok(())
+    }
+
+    fn claim_interface(&mut self, num_or_index: u8, is_index: bool) -> Result<()> {
+        try_unsafe!(self.raw.dev.ClaimInterface.unwrap()(
+            self.raw.handle.as_ptr(),
+            num_or_index,
+            is_index.into()
+        ));
+        Ok(())
+    }
+
+    fn release_interface(&mut self, num_or_index: u8, is_index: bool) -> Result<()> {
+        try_unsafe!(self.raw.dev.ReleaseInterface.unwrap()(
+            self.raw.handle.as_ptr(),
+            num_or_index,
+            is_index.into()
+        ));
+        Ok(())
+    }
+
+    fn select_interface(&mut self, num_or_index: u8, is_index: bool) -> Result<()> {
+        try_unsafe!(self.raw.dev.SelectInterface.unwrap()(
+            self.raw.handle.as_ptr(),
+            num_or_index,
+            is_index.into()
+        ));
+        Ok(())
+    }
+
+    fn set_alt_interface(
+        &mut self,
+        num_or_index: u8,
+        is_index: bool,
+        alt_setting: u8,
+    ) -> Result<()> {
+        try_unsafe!(self.raw.dev.SetAltInterface.unwrap()(
+            self.raw.handle.as_ptr(),
+            num_or_index,
+            is_index.into(),
+            alt_setting
+        ));
+        Ok(())
+    }
+
+    fn get_alt_interface(&mut self, num_or_index: u8, is_index: bool) -> Result<u8> {
+        let mut alt_setting = 0;
+        try_unsafe!(self.raw.dev.GetAltInterface.unwrap()(
+            self.raw.handle.as_ptr(),
+            num_or_index,
+            is_index.into(),
+            &mut alt_setting
+        ));
+        Ok(alt_setting)
+    }
+
+    fn query_interface_settings(
+        &mut self,
+        alt_setting_index: u8,
+    ) -> Result<USB_INTERFACE_DESCRIPTOR> {
+        let mut desc = USB_INTERFACE_DESCRIPTOR::default();
+        try_unsafe!(self.raw.dev.QueryInterfaceSettings.unwrap()(
+            self.raw.handle.as_ptr(),
+            alt_setting_index,
+            &mut desc,
+        ));
+        Ok(desc)
+    }
+
+    /// Falls back to `QueryPipe` for drivers without `QueryPipeEx`, leaving
+    /// `MaximumBytesPerInterval` at zero.
+    fn query_pipe(
+        &mut self,
+        alt_setting_index: u8,
+        pipe_index: u8,
+    ) -> Result<WINUSB_PIPE_INFORMATION_EX> {
+        let mut info = WINUSB_PIPE_INFORMATION_EX::default();
+        let queried = unsafe {
+            self.raw.dev.QueryPipeEx.unwrap()(
+                self.raw.handle.as_ptr(),
+                alt_setting_index,
+                pipe_index,
+                &mut info,
+            )
+        };
+        match queried {
+            0 => match last_error() {
+                Error::NotSupported(_) => {}
+                err => return Err(err),
+            },
+            _ => return Ok(info),
+        }
+
+        let mut basic = WINUSB_PIPE_INFORMATION::default();
+        try_unsafe!(self.raw.dev.QueryPipe.unwrap()(
+            self.raw.handle.as_ptr(),
+            alt_setting_index,
+            pipe_index,
+            &mut basic,
+        ));
+        Ok(WINUSB_PIPE_INFORMATION_EX {
+            PipeType: basic.PipeType,
+            PipeId: basic.PipeId,
+            MaximumPacketSize: basic.MaximumPacketSize,
+            Interval: basic.Interval,
+            MaximumBytesPerInterval: 0,
+        })
+    }
+
+    fn superspeed_pipe_companion(
+        &mut self,
+        alt_setting_index: u8,
+        pipe_index: u8,
+    ) -> Result<_USB_SUPERSPEED_ENDPOINT_COMPANION_DESCRIPTOR> {
+        let mut desc = _USB_SUPERSPEED_ENDPOINT_COMPANION_DESCRIPTOR::default();
+        try_unsafe!(self.raw.dev.GetSuperSpeedPipeCompanionDescriptor.unwrap()(
+            self.raw.handle.as_ptr(),
+            alt_setting_index,
+            pipe_index,
+            &mut desc,
+        ));
+        Ok(desc)
+    }
+
+    fn query_device_information(
+        &mut self,
+        information_type: u32,
+        buffer: &mut [u8],
+    ) -> Result<u32> {
+        let mut len = buffer.len() as u32;
+        try_unsafe!(self.raw.dev.QueryDeviceInformation.unwrap()(
+            self.raw.handle.as_ptr(),
+            information_type,
+            &mut len,
+            buffer.as_mut_ptr(),
+        ));
+        Ok(len)
+    }
+
+    fn read_pipe(&mut self, pipe_id: u8, buffer: &mut [u8]) -> Result<u32> {
+        let mut transferred: u32 = 0;
+        try_unsafe!(self.raw.dev.ReadPipe.unwrap()(
+            self.raw.handle.as_ptr(),
+            pipe_id,
+            buffer.as_mut_ptr(),
+            buffer.len() as u32,
+            &mut transferred,
+            std::ptr::null_mut(),
+        ));
+        Ok(transferred)
+    }
+
+    fn write_pipe(&mut self, pipe_id: u8, buffer: &[u8]) -> Result<u32> {
+        let mut transferred: u32 = 0;
+        try_unsafe!(self.raw.dev.WritePipe.unwrap()(
+            self.raw.handle.as_ptr(),
+            pipe_id,
+            buffer.as_ptr() as *mut u8,
+            buffer.len() as u32,
+            &mut transferred,
+            std::ptr::null_mut()
+        ));
+        Ok(transferred)
+    }
+
+    fn read_control(&mut self, setup: WINUSB_SETUP_PACKET, buffer: &mut [u8]) -> Result<u32> {
+        let mut transferred: u32 = 0;
+        try_unsafe!(self.raw.dev.ControlTransfer.unwrap()(
+            self.raw.handle.as_ptr(),
+            setup,
+            buffer.as_mut_ptr(),
+            buffer.len() as u32,
+            &mut transferred,
+            std::ptr::null_mut(),
+        ));
+        Ok(transferred)
+    }
+
+    fn write_control(&mut self, setup: WINUSB_SETUP_PACKET, buffer: &[u8]) -> Result<u32> {
+        let mut transferred: u32 = 0;
+        try_unsafe!(self.raw.dev.ControlTransfer.unwrap()(
+            self.raw.handle.as_ptr(),
+            setup,
+            buffer.as_ptr() as *mut u8,
+            buffer.len() as u32,
+            &mut transferred,
+            std::ptr::null_mut(),
+        ));
+        Ok(transferred)
+    }
+
+    fn set_pipe_policy(&mut self, pipe_id: u8, policy_type: u32, value: &[u8]) -> Result<()> {
+        try_unsafe!(self.raw.dev.SetPipePolicy.unwrap()(
+            self.raw.handle.as_ptr(),
+            pipe_id,
+            policy_type,
+            value.len() as u32,
+            value.as_ptr() as *mut c_void,
+        ));
+        Ok(())
+    }
+
+    fn get_pipe_policy(&mut self, pipe_id: u8, policy_type: u32, value: &mut [u8]) -> Result<u32> {
+        let mut len = value.len() as u32;
+        try_unsafe!(self.raw.dev.GetPipePolicy.unwrap()(
+            self.raw.handle.as_ptr(),
+            pipe_id,
+            policy_type,
+            &mut len,
+            value.as_mut_ptr() as *mut c_void,
+        ));
+        Ok(len)
+    }
+
+    fn set_power_policy(&mut self, policy_type: u32, value: &[u8]) -> Result<()> {
+        try_unsafe!(self.raw.dev.SetPowerPolicy.unwrap()(
+            self.raw.handle.as_ptr(),
+            policy_type,
+            value.len() as u32,
+            value.as_ptr() as *mut c_void,
+        ));
+        Ok(())
+    }
+
+    fn get_power_policy(&mut self, policy_type: u32, value: &mut [u8]) -> Result<u32> {
+        let mut len = value.len() as u32;
+        try_unsafe!(self.raw.dev.GetPowerPolicy.unwrap()(
+            self.raw.handle.as_ptr(),
+            policy_type,
+            &mut len,
+            value.as_mut_ptr() as *mut c_void,
+        ));
+        Ok(len)
+    }
+
+    fn reset_pipe(&mut self, pipe_id: u8) -> Result<()> {
+        try_unsafe!(self.raw.dev.ResetPipe.unwrap()(
+            self.raw.handle.as_ptr(),
+            pipe_id
+        ));
+        Ok(())
+    }
+
+    fn abort_pipe(&mut self, pipe_id: u8) -> Result<()> {
+        try_unsafe!(self.raw.dev.AbortPipe.unwrap()(
+            self.raw.handle.as_ptr(),
+            pipe_id
+        ));
+        Ok(())
+    }
+
+    fn flush_pipe(&mut self, pipe_id: u8) -> Result<()> {
+        try_unsafe!(self.raw.dev.FlushPipe.unwrap()(
+            self.raw.handle.as_ptr(),
+            pipe_id
+        ));
+        Ok(())
+    }
+
+    fn get_descriptor(
+        &mut self,
+        descriptor_type: u8,
+        index: u8,
+        language_id: u16,
+        buffer: &mut [u8],
+    ) -> Result<u32> {
+        let mut transferred: u32 = 0;
+        try_unsafe!(self.raw.dev.GetDescriptor.unwrap()(
+            self.raw.handle.as_ptr(),
+            descriptor_type,
+            index,
+            language_id,
+            buffer.as_mut_ptr(),
+            buffer.len() as u32,
+            &mut transferred,
+        ));
+        Ok(transferred)
+    }
+
+    /// WinUSB only supports isochronous transfers through `IsochK`; the other drivers take an
+    /// `IsoK` context.
+    fn iso_transfer(
+        &mut self,
+        pipe_id: u8,
+        buffer: &mut [u8],
+        packets: &mut [IsoPacket],
+        start_frame: Option<u32>,
+        timeout: Duration,
+    ) -> Result<Option<u32>> {
+        let pool = self.pool(1)?;
+        if self.raw.driver_id == DriverId::WinUsb as i32 {
+            unsafe { isoch_transfer(&pool, pipe_id, buffer, packets, start_frame, timeout) }
+        } else {
+            unsafe { iso_transfer(&pool, pipe_id, buffer, packets, start_frame, timeout) }
+        }
+    }
+
+    fn current_frame_number(&mut self) -> Result<u32> {
+        let mut frame = 0;
+        try_unsafe!(self.raw.dev.GetCurrentFrameNumber.unwrap()(
+            self.raw.handle.as_ptr(),
+            &mut frame,
+        ));
+        Ok(frame)
+    }
+
+    fn iso_packet_information(
+        &self,
+        high_speed: bool,
+        pipe: &WINUSB_PIPE_INFORMATION_EX,
+    ) -> Result<KISOCH_PACKET_INFORMATION> {
+        let mut pipe = *pipe;
+        let mut info = KISOCH_PACKET_INFORMATION::default();
+        try_unsafe!(IsochK_CalcPacketInformation(
+            high_speed.into(),
+            &mut pipe,
+            &mut info,
+        ));
+        Ok(info)
+    }
+
+    fn reset_device(&mut self) -> Result<()> {
+        try_unsafe!(self.raw.dev.ResetDevice.unwrap()(self.raw.handle.as_ptr()));
+        Ok(())
+    }
+
+    fn associated_interface(&self, index: u8) -> Result<Box<dyn DeviceHandleBackend>> {
+        let mut handle = std::ptr::null_mut();
+        try_unsafe!(self.raw.dev.GetAssociatedInterface.unwrap()(
+            self.raw.handle.as_ptr(),
+            index,
+            &mut handle,
+        ));
+        Ok(Box::new(self.derived(handle)))
+    }
+
+    fn try_clone(&self) -> Result<Box<dyn DeviceHandleBackend>> {
+        let mut handle = std::ptr::null_mut();
+        try_unsafe!(self.raw.dev.Clone.unwrap()(
+            self.raw.handle.as_ptr(),
+            &mut handle
+        ));
+        Ok(Box::new(self.derived(handle)))
+    }
+
+    fn raw_handle(&self) -> Option<NonNull<c_void>> {
+        Some(self.raw.handle)
+    }
+
+    fn get_property(&self, property: KUSB_PROPERTY, value: &mut [u8]) -> Result<u32> {
+        let mut len = value.len() as u32;
+        try_unsafe!(self.raw.dev.GetProperty.unwrap()(
+            self.raw.handle.as_ptr(),
+            property,
+            &mut len,
+            value.as_mut_ptr() as *mut c_void,
+        ));
+        Ok(len)
+    }
+
+    fn overlapped_pool(&self, max_transfers: usize) -> Result<Arc<dyn OverlappedPoolBackend>> {
+        Ok(Arc::new(self.pool(max_transfers)?))
+    }
+
+    fn stream(&self, pipe_id: u8, builder: &StreamBuilder) -> Result<Box<dyn StreamBackend>> {
+        let mut context = Box::new(StreamContext {
+            native: KSTM_CALLBACK {
+                Error: Some(on_stream_error),
+                Submit: Some(on_stream_submit),
+                Complete: Some(on_stream_complete),
+                Started: Some(on_stream_started),
+                Stopped: Some(on_stream_stopped),
+                ..Default::default()
+            },
+            callbacks: builder.callbacks.clone(),
+        });
+        let count = |n: usize| i32::try_from(n).unwrap_or(i32::MAX);
+
+        let mut stream = std::ptr::null_mut();
+        try_unsafe!(StmK_Init(
+            &mut stream,
+            self.raw.handle.as_ptr(),
+            pipe_id,
+            count(builder.max_transfer_size),
+            count(builder.max_pending_transfers),
+            count(builder.max_pending_io),
+            &mut context.native,
+            builder.flags(),
+        ));
+        let stream = LibUsbKStream {
+            _raw: self.raw.clone(),
+            stream: NonNull::new(stream).unwrap(),
+            context,
+        };
+        try_unsafe!(LibK_SetContext(
+            stream.stream.as_ptr(),
+            _KLIB_HANDLE_TYPE_KLIB_HANDLE_TYPE_STMK,
+            &stream.context.callbacks as *const StreamCallbacks as KLIB_USER_CONTEXT,
+        ));
+        Ok(Box::new(stream))
+    }
+}
+
+impl LibUsbKHandle {
+    /// Wraps a handle obtained through this one.
+    fn derived(&self, handle: *mut c_void) -> LibUsbKHandle {
+        LibUsbKHandle {
+            raw: Arc::new(RawHandle {
+                dev: self.raw.dev,
+                handle: NonNull::new(handle).unwrap(),
+                driver_id: self.raw.driver_id,
+                _parent: Some(self.raw.clone()),
+            }),
+        }
+    }
+
+    fn pool(&self, max_transfers: usize) -> Result<LibUsbKPool> {
+        let wake = WakeEvent::new()?;
+        let mut pool = std::ptr::null_mut();
+        try_unsafe!(OvlK_Init(
+            &mut pool,
+            self.raw.handle.as_ptr(),
+            i32::try_from(max_transfers).unwrap_or(i32::MAX),
+            _KOVL_POOL_FLAG_KOVL_POOL_FLAG_NONE,
+        ));
+        Ok(LibUsbKPool {
+            inner: Arc::new(PoolInner {
+                raw: self.raw.clone(),
+                pool: NonNull::new(pool).unwrap(),
+                events: Mutex::new(Vec::new()),
+                wake,
+            }),
+        })
+    }
+}
+
+/// Frees an `IsoK` context once the transfer using it is gone.
+struct IsoContext(PKISO_CONTEXT);
+
+impl Drop for IsoContext {
+    fn drop(&mut self) {
+        unsafe { IsoK_Free(self.0) };
+    }
+}
+
+/// Frees an `IsochK` handle once the transfer using it is gone.
+struct IsochHandle(KISOCH_HANDLE);
+
+impl Drop for IsochHandle {
+    fn drop(&mut self) {
+        unsafe { IsochK_Free(self.0) };
+    }
+}
+
+/// Runs an isochronous transfer with `IsoReadPipe` or `IsoWritePipe`.
+unsafe fn iso_transfer(
+    pool: &LibUsbKPool,
+    pipe_id: u8,
+    buffer: &mut [u8],
+    packets: &mut [IsoPacket],
+    start_frame: Option<u32>,
+    timeout: Duration,
+) -> Result<Option<u32>> {
+    let mut context = std::ptr::null_mut();
+    try_unsafe!(IsoK_Init(
+        &mut context,
+        packets.len() as INT,
+        start_frame.unwrap_or_default() as INT,
+    ));
+    let context = IsoContext(context);
+    if start_frame.is_some() {
+        std::ptr::addr_of_mut!((*context.0).Flags)
+            .write_unaligned(_KISO_FLAG_KISO_FLAG_SET_START_FRAME);
+    }
+    for (index, packet) in packets.iter().enumerate() {
+        let mut native = KISO_PACKET {
+            Offset: packet.offset,
+            Length: 0,
+            Status: 0,
+        };
+        try_unsafe!(IsoK_SetPacket(context.0, index as INT, &mut native));
+    }
+
+    let read = pipe_id & ENDPOINT_DIR_MASK == ENDPOINT_IN;
+    let mut transfer = pool.submit(|raw, ovl| {
+        let submit = if read {
+            raw.dev.IsoReadPipe
+        } else {
+            raw.dev.IsoWritePipe
+        };
+        submit.unwrap()(
+            raw.handle.as_ptr(),
+            pipe_id,
+            buffer.as_mut_ptr(),
+            buffer.len() as u32,
+            ovl,
+            context.0,
+        )
+    })?;
+    wait_for(&mut *transfer, timeout)?;
+
+    for (index, packet) in packets.iter_mut().enumerate() {
+        let mut native = KISO_PACKET::default();
+        try_unsafe!(IsoK_GetPacket(context.0, index as INT, &mut native));
+        packet.length = native.Length.into();
+        packet.status = native.Status.into();
+    }
+    Ok(Some(
+        std::ptr::addr_of!((*context.0).StartFrame).read_unaligned(),
+    ))
+}
+
+/// Runs an isochronous transfer with `IsochReadPipe` or `IsochWritePipe`. The start frame is
+/// only known if one was requested.
+unsafe fn isoch_transfer(
+    pool: &LibUsbKPool,
+    pipe_id: u8,
+    buffer: &mut [u8],
+    packets: &mut [IsoPacket],
+    start_frame: Option<u32>,
+    timeout: Duration,
+) -> Result<Option<u32>> {
+    let mut isoch = std::ptr::null_mut();
+    try_unsafe!(IsochK_Init(
+        &mut isoch,
+        pool.inner.raw.handle.as_ptr(),
+        pipe_id,
+        packets.len() as u32,
+        buffer.as_mut_ptr(),
+        buffer.len() as u32,
+    ));
+    let isoch = IsochHandle(isoch);
+    for (index, packet) in packets.iter().enumerate() {
+        try_unsafe!(IsochK_SetPacket(
+            isoch.0,
+            index as u32,
+            packet.offset,
+            packet.size,
+            0
+        ));
+    }
+
+    // Updated by the driver until the transfer completes. Without one the transfer starts as
+    // soon as possible.
+    let mut frame = start_frame.unwrap_or_default();
+    let frame_ptr = match start_frame {
+        Some(_) => &mut frame as *mut u32,
+        None => std::ptr::null_mut(),
+    };
+    let read = pipe_id & ENDPOINT_DIR_MASK == ENDPOINT_IN;
+    let mut transfer = pool.submit(|raw, ovl| {
+        let submit = if read {
+            raw.dev.IsochReadPipe
+        } else {
+            raw.dev.IsochWritePipe
+        };
+        submit.unwrap()(
+            isoch.0,
+            buffer.len() as u32,
+            frame_ptr,
+            packets.len() as u32,
+            ovl,
+        )
+    })?;
+    wait_for(&mut *transfer, timeout)?;
+
+    for (index, packet) in packets.iter_mut().enumerate() {
+        let mut offset = 0;
+        try_unsafe!(IsochK_GetPacket(
+            isoch.0,
+            index as u32,
+            &mut offset,
+            &mut packet.length,
+            &mut packet.status,
+        ));
+    }
+    Ok(start_frame)
+}
+
+/// Waits for a synchronous transfer, where a `timeout` of zero waits indefinitely. The transfer
+/// has completed when this returns, so its buffer can be released.
+fn wait_for(transfer: &mut dyn PendingTransfer, timeout: Duration) -> Result<u32> {
+    if timeout.is_zero() {
+        return transfer.wait(None);
+    }
+    let result = transfer.wait_or_cancel(timeout);
+    // A cancellation that timed out may still be in progress.
+    if matches!(result, Err(Error::Timeout(_))) {
+        let _ = transfer.wait(None);
+    }
+    result
+}
+
+#[derive(Debug)]
+struct LibUsbKStream {
+    _raw: Arc<RawHandle>,
+    stream: NonNull<c_void>,
+    /// Referenced by the stream until it is freed.
+    context: Box<StreamContext>,
+}
+
+unsafe impl Send for LibUsbKStream {}
+
+impl Drop for LibUsbKStream {
+    fn drop(&mut self) {
+        unsafe { StmK_Free(self.stream.as_ptr()) };
+    }
+}
+
+impl StreamBackend for LibUsbKStream {
+    fn start(&mut self) -> Result<()> {
+        try_unsafe!(StmK_Start(self.stream.as_ptr()));
+        Ok(())
+    }
+
+    fn stop(&mut self, timeout: Duration) -> Result<()> {
+        try_unsafe!(StmK_Stop(self.stream.as_ptr(), timeout_ms(Some(timeout))));
+        Ok(())
+    }
+
+    fn read(&mut self, buffer: &mut [u8]) -> Result<u32> {
+        let mut transferred = 0;
+        try_unsafe!(StmK_Read(
+            self.stream.as_ptr(),
+            buffer.as_mut_ptr(),
+            0,
+            i32::try_from(buffer.len()).unwrap_or(i32::MAX),
+            &mut transferred,
+        ));
+        Ok(transferred)
+    }
+
+    fn write(&mut self, buffer: &[u8]) -> Result<u32> {
+        let mut transferred = 0;
+        try_unsafe!(StmK_Write(
+            self.stream.as_ptr(),
+            buffer.as_ptr() as *mut u8,
+            0,
+            i32::try_from(buffer.len()).unwrap_or(i32::MAX),
+            &mut transferred,
+        ));
+        Ok(transferred)
+    }
+}
+
+/// The callback table passed to `StmK_Init`, and the closures its functions forward to.
+struct StreamContext {
+    native: KSTM_CALLBACK,
+    callbacks: StreamCallbacks,
+}
+
+impl std::fmt::Debug for StreamContext {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("StreamContext").finish_non_exhaustive()
+    }
+}
+
+/// Returns the closures registered for the stream with `LibK_SetContext`.
+unsafe fn stream_callbacks<'a>(info: PKSTM_INFO) -> &'a StreamCallbacks {
+    let context = LibK_GetContext(
+        (*info).StreamHandle,
+        _KLIB_HANDLE_TYPE_KLIB_HANDLE_TYPE_STMK,
+    );
+    &*(context as *const StreamCallbacks)
+}
+
+unsafe extern "C" fn on_stream_error(
+    info: PKSTM_INFO,
+    _xfer: PKSTM_XFER_CONTEXT,
+    index: INT,
+    error_code: INT,
+) -> INT {
+    stream_callbacks(info).error(index as usize, Error::from_code(error_code as u32));
+    0
+}
+
+/// Starts a transfer the way `libusbK` does when no submit callback is given.
+unsafe extern "C" fn on_stream_submit(
+    info: PKSTM_INFO,
+    xfer: PKSTM_XFER_CONTEXT,
+    index: INT,
+    ovl: LPOVERLAPPED,
+) -> INT {
+    let callbacks = stream_callbacks(info);
+    let info = &*info;
+    let xfer = &*xfer;
+    let read = info.PipeID & ENDPOINT_DIR_MASK == ENDPOINT_IN;
+    let len = if read {
+        xfer.BufferSize
+    } else {
+        xfer.TransferLength
+    };
+    callbacks.submit(index as usize, len as usize);
+
+    let api = &info.DriverAPI;
+    let submit = if read { api.ReadPipe } else { api.WritePipe };
+    let started = submit.unwrap()(
+        info.UsbHandle,
+        info.PipeID,
+        xfer.Buffer,
+        len as u32,
+        std::ptr::null_mut(),
+        ovl,
+    );
+    match started {
+        0 => match last_error().code() {
+            ERROR_IO_PENDING => 0,
+            code => code as INT,
+        },
+        _ => 0,
+    }
+}
+
+unsafe extern "C" fn on_stream_complete(
+    info: PKSTM_INFO,
+    xfer: PKSTM_XFER_CONTEXT,
+    index: INT,
+    error_code: INT,
+) -> INT {
+    let result = match error_code {
+        0 => {
+            let xfer = &*xfer;
+            Ok(std::slice::from_raw_parts(
+                xfer.Buffer,
+                xfer.TransferLength as usize,
+            ))
+        }
+        code => Err(Error::from_code(code as u32)),
+    };
+    stream_callbacks(info).complete(index as usize, result);
+    0
+}
+
+unsafe extern "C" fn on_stream_started(
+    info: PKSTM_INFO,
+    _xfer: PKSTM_XFER_CONTEXT,
+    index: INT,
+) -> INT {
+    stream_callbacks(info).started(index as usize);
+    0
+}
+
+unsafe extern "C" fn on_stream_stopped(
+    info: PKSTM_INFO,
+    _xfer: PKSTM_XFER_CONTEXT,
+    index: INT,
+) -> INT {
+    stream_callbacks(info).stopped(index as usize);
+    0
+}
+
+#[derive(Debug)]
+struct LibUsbKPool {
+    inner: Arc<PoolInner>,
+}
+
+#[derive(Debug)]
+struct PoolInner {
+    raw: Arc<RawHandle>,
+    pool: NonNull<c_void>,
+    /// The event handles of the acquired contexts, oldest first, for `wait_any`.
+    events: Mutex<Vec<(KOVL_HANDLE, HANDLE)>>,
+    wake: WakeEvent,
+}
+
+unsafe impl Send for PoolInner {}
+unsafe impl Sync for PoolInner {}
+
+impl Drop for PoolInner {
+    fn drop(&mut self) {
+        unsafe { OvlK_Free(self.pool.as_ptr()) };
+    }
+}
+
+impl LibUsbKPool {
+    /// Acquires an overlapped context and starts a transfer with it.
+    fn submit(
+        &self,
+        start: impl FnOnce(&RawHandle, LPOVERLAPPED) -> BOOL,
+    ) -> Result<Box<dyn PendingTransfer>> {
+        let mut ovl = std::ptr::null_mut();
+        try_unsafe!(OvlK_Acquire(&mut ovl, self.inner.pool.as_ptr()));
+        // Releases the context again if the transfer cannot be started.
+        let transfer = LibUsbKTransfer {
+            pool: self.inner.clone(),
+            ovl: NonNull::new(ovl).unwrap(),
+        };
+        let event = unsafe { OvlK_GetEventHandle(ovl) };
+        self.inner.events.lock().unwrap().push((ovl, event));
+
+        if start(&self.inner.raw, ovl as LPOVERLAPPED) == 0 {
+            let err = last_error();
+            if err.code() != ERROR_IO_PENDING {
+                return Err(err);
+            }
+        }
+        Ok(Box::new(transfer))
+    }
+}
+
+impl OverlappedPoolBackend for LibUsbKPool {
+    unsafe fn submit_read(
+        &self,
+        pipe_id: u8,
+        buffer: *mut u8,
+        len: usize,
+    ) -> Result<Box<dyn PendingTransfer>> {
+        self.submit(|raw, ovl| {
+            raw.dev.ReadPipe.unwrap()(
+                raw.handle.as_ptr(),
+                pipe_id,
+                buffer,
+                len as u32,
+                std::ptr::null_mut(),
+                ovl,
+            )
+        })
+    }
+
+    unsafe fn submit_write(
+        &self,
+        pipe_id: u8,
+        buffer: *const u8,
+        len: usize,
+    ) -> Result<Box<dyn PendingTransfer>> {
+        self.submit(|raw, ovl| {
+            raw.dev.WritePipe.unwrap()(
+                raw.handle.as_ptr(),
+                pipe_id,
+                buffer as *mut u8,
+                len as u32,
+                std::ptr::null_mut(),
+                ovl,
+            )
+        })
+    }
+
+    unsafe fn submit_control(
+        &self,
+        setup: WINUSB_SETUP_PACKET,
+        buffer: *mut u8,
+        len: usize,
+    ) -> Result<Box<dyn PendingTransfer>> {
+        self.submit(|raw, ovl| {
+            raw.dev.ControlTransfer.unwrap()(
+                raw.handle.as_ptr(),
+                setup,
+                buffer,
+                len as u32,
+                std::ptr::null_mut(),
+                ovl,
+            )
+        })
+    }
+
+    fn wait_oldest(&self, timeout: Option<Duration>) -> Result<()> {
+        let mut ovl = std::ptr::null_mut();
+        let mut transferred = 0;
+        try_unsafe!(OvlK_WaitOldest(
+            self.inner.pool.as_ptr(),
+            &mut ovl,
+            timeout_ms(timeout),
+            _KOVL_WAIT_FLAG_KOVL_WAIT_FLAG_NONE,
+            &mut transferred,
+        ));
+        Ok(())
+    }
+
+    /// Waits on the wake event and the events of the oldest transfers that fit into one call to
+    /// `WaitForMultipleObjects`.
+    fn wait_any(&self) -> Result<()> {
+        let mut handles = vec![self.inner.wake.0];
+        {
+            // Submitting and dropping transfers lock the list too, so it must not stay locked
+            // while waiting.
+            let events = self.inner.events.lock().unwrap();
+            let events = events.iter().take(MAXIMUM_WAIT_OBJECTS - 1);
+            handles.extend(events.map(|&(_, event)| event));
+        }
+        wait_for_any(&handles)
+    }
+
+    fn wake(&self) {
+        self.inner.wake.set();
+    }
+}
+
+/// The most handles `WaitForMultipleObjects` can wait on.
+const MAXIMUM_WAIT_OBJECTS: usize = 64;
+
+/// An auto-reset event that interrupts `wait_any`.
+#[derive(Debug)]
+struct WakeEvent(HANDLE);
+
+#[cfg(windows)]
+impl WakeEvent {
+    fn new() -> Result<Self> {
+        use winapi::um::synchapi::CreateEventW;
+
+        let event = unsafe { CreateEventW(std::ptr::null_mut(), 0, 0, std::ptr::null()) };
+        match event.is_null() {
+            true => Err(last_error()),
+            false => Ok(Self(event as HANDLE)),
+        }
+    }
+
+    fn set(&self) {
+        unsafe { winapi::um::synchapi::SetEvent(self.0 as _) };
+    }
+}
+
+#[cfg(windows)]
+impl Drop for WakeEvent {
+    fn drop(&mut self) {
+        unsafe { winapi::um::handleapi::CloseHandle(self.0 as _) };
+    }
+}
+
+/// Waits until any of `handles` is signaled.
+#[cfg(windows)]
+fn wait_for_any(handles: &[HANDLE]) -> Result<()> {
+    use winapi::um::synchapi::WaitForMultipleObjects;
+    use winapi::um::winbase::{INFINITE, WAIT_FAILED};
+
+    let count = handles.len() as u32;
+    match unsafe { WaitForMultipleObjects(count, handles.as_ptr() as _, 0, INFINITE) } {
+        WAIT_FAILED => Err(last_error()),
+        _ => Ok(()),
+    }
+}
+
+// libusbK only exists on Windows, so these are never reached elsewhere.
+#[cfg(not(windows))]
+impl WakeEvent {
+    fn new() -> Result<Self> {
+        Err(Error::from_code(ERROR_NOT_SUPPORTED))
+    }
+
+    fn set(&self) {}
+}
+
+#[cfg(not(windows))]
+fn wait_for_any(_handles: &[HANDLE]) -> Result<()> {
+    Err(Error::from_code(ERROR_NOT_SUPPORTED))
+}
+
+#[derive(Debug)]
+struct LibUsbKTransfer {
+    pool: Arc<PoolInner>,
+    ovl: NonNull<c_void>,
+}
+
+unsafe impl Send for LibUsbKTransfer {}
+
+impl Drop for LibUsbKTransfer {
+    fn drop(&mut self) {
+        let mut events = self.pool.events.lock().unwrap();
+        events.retain(|&(ovl, _)| ovl != self.ovl.as_ptr());
+        unsafe { OvlK_Release(self.ovl.as_ptr()) };
+    }
+}
+
+impl PendingTransfer for LibUsbKTransfer {
+    fn wait(&mut self, timeout: Option<Duration>) -> Result<u32> {
+        let mut transferred = 0;
+        try_unsafe!(OvlK_Wait(
+            self.ovl.as_ptr(),
+            timeout_ms(timeout),
+            _KOVL_WAIT_FLAG_KOVL_WAIT_FLAG_NONE,
+            &mut transferred,
+        ));
+        Ok(transferred)
+    }
+
+    fn wait_or_cancel(&mut self, timeout: Duration) -> Result<u32> {
+        let mut transferred = 0;
+        try_unsafe!(OvlK_WaitOrCancel(
+            self.ovl.as_ptr(),
+            timeout_ms(Some(timeout)),
+            &mut transferred,
+        ));
+        Ok(transferred)
+    }
+
+    fn is_complete(&self) -> bool {
+        unsafe { OvlK_IsComplete(self.ovl.as_ptr()) != 0 }
+    }
+}
+
+/// Converts a timeout for the `OvlK_Wait*` functions, where -1 waits indefinitely.
+fn timeout_ms(timeout: Option<Duration>) -> i32 {
+    match timeout {
+        Some(timeout) => i32::try_from(timeout.as_millis()).unwrap_or(i32::MAX),
+        None => -1,
+    }
+}
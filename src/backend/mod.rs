@@ -0,0 +1,296 @@
+use std::ffi::c_void;
+use std::fmt::Debug;
+use std::ptr::NonNull;
+use std::sync::Arc;
+use std::time::Duration;
+
+use libusbk_sys::{
+    _USB_SUPERSPEED_ENDPOINT_COMPANION_DESCRIPTOR, KISOCH_PACKET_INFORMATION, KLST_DEVINFO,
+    KLST_DEVINFO_HANDLE, KLST_FLAG, KLST_PATTERN_MATCH, KUSB_PROPERTY, USB_INTERFACE_DESCRIPTOR,
+    WINUSB_PIPE_INFORMATION_EX, WINUSB_SETUP_PACKET,
+};
+
+use crate::iso::IsoPacket;
+use crate::stream::StreamBuilder;
+use crate::Result;
+
+pub use self::libusbk::LibUsbK;
+#[cfg(any(test, feature = "sim"))]
+pub use self::sim::{SimBackend, SimConfigBuilder, SimControl, SimDevice, SimOp};
+
+mod libusbk;
+#[cfg(any(test, feature = "sim"))]
+pub(crate) mod sim;
+
+/// A driver backend that the safe API is built on.
+///
+/// [`LibUsbK`] is used unless a backend is passed explicitly, e.g. through
+/// [`DeviceList::with_backend`](crate::DeviceList::with_backend).
+pub trait Backend: Debug + Send + Sync {
+    /// Creates a snapshot of the devices currently known to the backend.
+    ///
+    /// `flags` and `pattern` have the meaning they have for `LstK_InitEx`; `pattern` fields that
+    /// are empty match every device.
+    fn device_list(
+        &self,
+        flags: KLST_FLAG,
+        pattern: Option<&KLST_PATTERN_MATCH>,
+    ) -> Result<Box<dyn DeviceListBackend>>;
+
+    /// Opens the device described by `info`, which was returned from one of this backend's lists.
+    ///
+    /// `info` must be the element of the list itself, not a copy of it: `libusbK` takes it as a
+    /// `KLST_DEVINFO_HANDLE`.
+    fn open(&self, info: &KLST_DEVINFO) -> Result<Box<dyn DeviceHandleBackend>>;
+
+    /// Starts counting the arrivals and removals of the devices matching `pattern`, as `HotK`
+    /// reports them. Devices that are already connected do not count as arrivals.
+    fn watch(&self, pattern: &KLST_PATTERN_MATCH) -> Result<Box<dyn DeviceWatch>>;
+}
+
+/// Hotplug events of the devices matching a pattern, counted since the watch was started.
+///
+/// The events stop being counted when the value is dropped.
+pub trait DeviceWatch: Debug + Send {
+    fn arrivals(&self) -> u32;
+
+    fn removals(&self) -> u32;
+}
+
+/// A list of devices owned by a [`Backend`].
+///
+/// Device info pointers handed out by the list must stay valid until the list is dropped.
+pub trait DeviceListBackend: Debug + Send + Sync {
+    fn count(&self) -> Result<u32>;
+
+    /// Returns every device in the list, in enumeration order.
+    fn devices(&self) -> Vec<KLST_DEVINFO_HANDLE>;
+
+    fn find_by_vid_pid(&self, vid: i32, pid: i32) -> Result<KLST_DEVINFO_HANDLE>;
+}
+
+/// An open device, as seen by a [`Backend`].
+///
+/// The device is closed when the value is dropped.
+pub trait DeviceHandleBackend: Debug + Send {
+    /// Returns the `bConfigurationValue` of the active configuration.
+    fn get_configuration(&mut self) -> Result<u8>;
+
+    fn set_configuration(&mut self, value: u8) -> Result<()>;
+
+    fn claim_interface(&mut self, num_or_index: u8, is_index: bool) -> Result<()>;
+
+    fn release_interface(&mut self, num_or_index: u8, is_index: bool) -> Result<()>;
+
+    /// Makes an interface the one used by functions acting on the current interface.
+    fn select_interface(&mut self, num_or_index: u8, is_index: bool) -> Result<()>;
+
+    fn set_alt_interface(
+        &mut self,
+        num_or_index: u8,
+        is_index: bool,
+        alt_setting: u8,
+    ) -> Result<()>;
+
+    fn get_alt_interface(&mut self, num_or_index: u8, is_index: bool) -> Result<u8>;
+
+    /// Reads the descriptor of the alternate setting at `alt_setting_index` of the current
+    /// interface.
+    fn query_interface_settings(
+        &mut self,
+        alt_setting_index: u8,
+    ) -> Result<USB_INTERFACE_DESCRIPTOR>;
+
+    /// Describes the pipe at `pipe_index` of an alternate setting of the current interface.
+    fn query_pipe(
+        &mut self,
+        alt_setting_index: u8,
+        pipe_index: u8,
+    ) -> Result<WINUSB_PIPE_INFORMATION_EX>;
+
+    /// Reads the SuperSpeed endpoint companion descriptor of the pipe at `pipe_index` of an
+    /// alternate setting of the current interface.
+    fn superspeed_pipe_companion(
+        &mut self,
+        alt_setting_index: u8,
+        pipe_index: u8,
+    ) -> Result<_USB_SUPERSPEED_ENDPOINT_COMPANION_DESCRIPTOR>;
+
+    /// Reads device information of the given type into `buffer`, returning the number of bytes
+    /// written.
+    fn query_device_information(&mut self, information_type: u32, buffer: &mut [u8])
+        -> Result<u32>;
+
+    fn read_pipe(&mut self, pipe_id: u8, buffer: &mut [u8]) -> Result<u32>;
+
+    fn write_pipe(&mut self, pipe_id: u8, buffer: &[u8]) -> Result<u32>;
+
+    fn read_control(&mut self, setup: WINUSB_SETUP_PACKET, buffer: &mut [u8]) -> Result<u32>;
+
+    fn write_control(&mut self, setup: WINUSB_SETUP_PACKET, buffer: &[u8]) -> Result<u32>;
+
+    fn set_pipe_policy(&mut self, pipe_id: u8, policy_type: u32, value: &[u8]) -> Result<()>;
+
+    /// Reads a pipe policy into `value`, returning the number of bytes written.
+    fn get_pipe_policy(&mut self, pipe_id: u8, policy_type: u32, value: &mut [u8]) -> Result<u32>;
+
+    fn set_power_policy(&mut self, policy_type: u32, value: &[u8]) -> Result<()>;
+
+    /// Reads a power policy into `value`, returning the number of bytes written.
+    fn get_power_policy(&mut self, policy_type: u32, value: &mut [u8]) -> Result<u32>;
+
+    /// Clears the stall condition and data toggle of a pipe, cancelling its pending transfers.
+    fn reset_pipe(&mut self, pipe_id: u8) -> Result<()>;
+
+    /// Cancels the transfers pending on a pipe, which fail with `ERROR_OPERATION_ABORTED`.
+    fn abort_pipe(&mut self, pipe_id: u8) -> Result<()>;
+
+    /// Discards the data the driver buffered for a pipe.
+    fn flush_pipe(&mut self, pipe_id: u8) -> Result<()>;
+
+    fn get_descriptor(
+        &mut self,
+        descriptor_type: u8,
+        index: u8,
+        language_id: u16,
+        buffer: &mut [u8],
+    ) -> Result<u32>;
+
+    /// Runs an isochronous transfer on a pipe, filling in the length and status of every
+    /// packet, and returns the frame it started in if the driver reports it.
+    ///
+    /// The transfer starts in `start_frame`, or as soon as possible for `None`. It is cancelled
+    /// if it does not complete within `timeout`; a `timeout` of zero waits indefinitely.
+    fn iso_transfer(
+        &mut self,
+        pipe_id: u8,
+        buffer: &mut [u8],
+        packets: &mut [IsoPacket],
+        start_frame: Option<u32>,
+        timeout: Duration,
+    ) -> Result<Option<u32>>;
+
+    fn current_frame_number(&mut self) -> Result<u32>;
+
+    /// Computes how an isochronous pipe is scheduled, as `IsochK_CalcPacketInformation` does.
+    fn iso_packet_information(
+        &self,
+        high_speed: bool,
+        pipe: &WINUSB_PIPE_INFORMATION_EX,
+    ) -> Result<KISOCH_PACKET_INFORMATION>;
+
+    /// Resets the port the device is attached to. The device re-enumerates afterwards, which
+    /// invalidates the handle.
+    fn reset_device(&mut self) -> Result<()>;
+
+    /// Opens the interface associated with this handle's at `index`, counting from zero for the
+    /// next interface. The returned handle keeps this one open.
+    fn associated_interface(&self, index: u8) -> Result<Box<dyn DeviceHandleBackend>>;
+
+    /// Opens another handle to the same device and interface.
+    fn try_clone(&self) -> Result<Box<dyn DeviceHandleBackend>>;
+
+    /// The underlying `libusbK` handle, if the backend has one.
+    fn raw_handle(&self) -> Option<NonNull<c_void>>;
+
+    /// Reads a `KUSB_PROPERTY` of the handle into `value`, returning the number of bytes
+    /// written.
+    fn get_property(&self, property: KUSB_PROPERTY, value: &mut [u8]) -> Result<u32>;
+
+    /// Creates a pool of overlapped contexts for up to `max_transfers` transfers in flight.
+    fn overlapped_pool(&self, max_transfers: usize) -> Result<Arc<dyn OverlappedPoolBackend>>;
+
+    /// Creates a stopped stream on a pipe, configured by `builder`.
+    fn stream(&self, pipe_id: u8, builder: &StreamBuilder) -> Result<Box<dyn StreamBackend>>;
+}
+
+/// A pool of overlapped contexts for asynchronous transfers on an open device.
+///
+/// The pool keeps the device open for as long as it or any of its transfers exists.
+pub trait OverlappedPoolBackend: Debug + Send + Sync {
+    /// Starts reading from a pipe into `buffer`.
+    ///
+    /// # Safety
+    ///
+    /// `buffer` must be valid for writes of `len` bytes and must not be accessed until the
+    /// returned transfer has completed.
+    unsafe fn submit_read(
+        &self,
+        pipe_id: u8,
+        buffer: *mut u8,
+        len: usize,
+    ) -> Result<Box<dyn PendingTransfer>>;
+
+    /// Starts writing `buffer` to a pipe.
+    ///
+    /// # Safety
+    ///
+    /// `buffer` must be valid for reads of `len` bytes and must not be modified until the
+    /// returned transfer has completed.
+    unsafe fn submit_write(
+        &self,
+        pipe_id: u8,
+        buffer: *const u8,
+        len: usize,
+    ) -> Result<Box<dyn PendingTransfer>>;
+
+    /// Starts a control transfer. For an inbound request the data is read into `buffer`,
+    /// otherwise `buffer` holds the data to send.
+    ///
+    /// # Safety
+    ///
+    /// `buffer` must be valid for reads and writes of `len` bytes and must not be accessed until
+    /// the returned transfer has completed.
+    unsafe fn submit_control(
+        &self,
+        setup: WINUSB_SETUP_PACKET,
+        buffer: *mut u8,
+        len: usize,
+    ) -> Result<Box<dyn PendingTransfer>>;
+
+    /// Waits until the oldest transfer of the pool that has not been released completes, or
+    /// indefinitely if `timeout` is `None`.
+    fn wait_oldest(&self, timeout: Option<Duration>) -> Result<()>;
+
+    /// Waits until any transfer of the pool that has not been released completes, or until
+    /// [`wake`](Self::wake) is called. Returns at once while a completed transfer is still held.
+    fn wait_any(&self) -> Result<()>;
+
+    /// Makes the current or, if there is none, the next call to [`wait_any`](Self::wait_any)
+    /// return.
+    fn wake(&self);
+}
+
+/// A transfer started from an [`OverlappedPoolBackend`].
+///
+/// Dropping it returns its overlapped context to the pool, so it must have completed by then.
+pub trait PendingTransfer: Debug + Send {
+    /// Waits for the transfer to complete, or indefinitely if `timeout` is `None`, and returns
+    /// the number of bytes transferred.
+    fn wait(&mut self, timeout: Option<Duration>) -> Result<u32>;
+
+    /// Like [`wait`](Self::wait), but cancels the transfer if it does not complete in time and
+    /// waits for the cancellation to finish.
+    fn wait_or_cancel(&mut self, timeout: Duration) -> Result<u32>;
+
+    fn is_complete(&self) -> bool;
+}
+
+/// A ring of transfers on one pipe, as created by `StmK_Init`.
+///
+/// The stream keeps the device open until it is dropped.
+pub trait StreamBackend: Debug + Send {
+    fn start(&mut self) -> Result<()>;
+
+    /// Stops the stream, cancelling the transfers still in flight after `timeout`.
+    fn stop(&mut self, timeout: Duration) -> Result<()>;
+
+    fn read(&mut self, buffer: &mut [u8]) -> Result<u32>;
+
+    fn write(&mut self, buffer: &[u8]) -> Result<u32>;
+}
+
+/// The backend used when none is given.
+pub(crate) fn default() -> Arc<dyn Backend> {
+    Arc::new(LibUsbK)
+}
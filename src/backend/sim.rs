@@ -0,0 +1,1577 @@
+#![cfg_attr(not(feature = "sim"), allow(dead_code))]
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::ffi::c_void;
+use std::ptr::NonNull;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use libusbk_sys::{
+    _KLST_FLAG_KLST_FLAG_INCLUDE_DISCONNECT, _USB_SUPERSPEED_ENDPOINT_COMPANION_DESCRIPTOR, CHAR,
+    KISOCH_PACKET_INFORMATION, KLST_DEVINFO, KLST_DEVINFO_HANDLE, KLST_FLAG, KLST_PATTERN_MATCH,
+    KUSB_PROPERTY, ULONG, USB_INTERFACE_DESCRIPTOR, WINUSB_PIPE_INFORMATION_EX,
+    WINUSB_SETUP_PACKET,
+};
+
+use super::{
+    Backend, DeviceHandleBackend, DeviceListBackend, DeviceWatch, OverlappedPoolBackend,
+    PendingTransfer, StreamBackend,
+};
+use crate::config_descriptor::{ConfigDescriptor, InterfaceDescriptor};
+use crate::device::c_str;
+use crate::error::{
+    Error, ERROR_DEVICE_NOT_CONNECTED, ERROR_GEN_FAILURE, ERROR_INVALID_PARAMETER, ERROR_NOT_FOUND,
+    ERROR_NOT_SUPPORTED, ERROR_NO_MORE_ITEMS, ERROR_OPERATION_ABORTED, ERROR_SEM_TIMEOUT,
+    WAIT_TIMEOUT,
+};
+use crate::fields::{
+    DESCRIPTOR_TYPE_CONFIG, DESCRIPTOR_TYPE_DEVICE, DESCRIPTOR_TYPE_STRING,
+    DEVICE_INFORMATION_SPEED, ENDPOINT_DIR_MASK, ENDPOINT_IN, FEATURE_DEVICE_REMOTE_WAKEUP,
+    FEATURE_ENDPOINT_HALT, REQUEST_CLEAR_FEATURE, REQUEST_GET_DESCRIPTOR, REQUEST_SET_FEATURE,
+    SPEED_HIGH,
+};
+use crate::iso::IsoPacket;
+use crate::stream::{StreamBuilder, StreamCallbacks};
+use crate::{DriverId, Result};
+
+static NEXT_DEVICE: AtomicUsize = AtomicUsize::new(1);
+
+/// An in-process backend serving scripted [`SimDevice`]s, for exercising the safe API without
+/// hardware.
+#[derive(Debug, Default, Clone)]
+pub struct SimBackend {
+    devices: Arc<Mutex<Vec<SimDevice>>>,
+}
+
+impl SimBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Plugs `device` into the backend. It shows up in lists created afterwards.
+    pub fn add_device(&self, device: &SimDevice) {
+        self.devices.lock().unwrap().push(device.clone());
+    }
+
+    /// Unplugs `device`. Handles that are already open keep working.
+    pub fn remove_device(&self, device: &SimDevice) {
+        self.devices
+            .lock()
+            .unwrap()
+            .retain(|d| !Arc::ptr_eq(&d.state, &device.state));
+    }
+}
+
+impl Backend for SimBackend {
+    /// Lists the added devices. `KLST_FLAG_INCLUDE_RAWGUID` is accepted but has no effect.
+    fn device_list(
+        &self,
+        flags: KLST_FLAG,
+        pattern: Option<&KLST_PATTERN_MATCH>,
+    ) -> Result<Box<dyn DeviceListBackend>> {
+        let include_disconnected = flags & _KLST_FLAG_KLST_FLAG_INCLUDE_DISCONNECT != 0;
+        let devices = self
+            .devices
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|d| d.state().info)
+            .filter(|info| include_disconnected || info.Connected != 0)
+            .filter(|info| pattern.is_none_or(|p| matches_pattern(info, p)))
+            .collect();
+        Ok(Box::new(SimList { devices }))
+    }
+
+    fn open(&self, info: &KLST_DEVINFO) -> Result<Box<dyn DeviceHandleBackend>> {
+        let device = self
+            .devices
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|d| {
+                let state = d.state();
+                state.info.DevicePath == info.DevicePath && state.info.Connected != 0
+            })
+            .cloned()
+            .ok_or(Error::from_code(ERROR_DEVICE_NOT_CONNECTED))?;
+
+        let mut state = device.state();
+        state.take_error(SimOp::Open)?;
+        state.open_handles += 1;
+        drop(state);
+
+        Ok(Box::new(SimHandle {
+            device,
+            interface_index: 0,
+        }))
+    }
+
+    /// Counts the connection changes of the matching devices that were added so far.
+    fn watch(&self, pattern: &KLST_PATTERN_MATCH) -> Result<Box<dyn DeviceWatch>> {
+        let devices = self
+            .devices
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|d| matches_pattern(&d.state().info, pattern))
+            .map(|d| {
+                let state = d.state();
+                (d.clone(), state.arrivals, state.removals)
+            })
+            .collect();
+        Ok(Box::new(SimWatch { devices }))
+    }
+}
+
+/// The devices a [`SimWatch`] counts events of, with their counts when the watch started.
+#[derive(Debug)]
+struct SimWatch {
+    devices: Vec<(SimDevice, u32, u32)>,
+}
+
+impl DeviceWatch for SimWatch {
+    fn arrivals(&self) -> u32 {
+        let arrivals = |(d, start, _): &(SimDevice, u32, u32)| d.state().arrivals - start;
+        self.devices.iter().map(arrivals).sum()
+    }
+
+    fn removals(&self) -> u32 {
+        let removals = |(d, _, start): &(SimDevice, u32, u32)| d.state().removals - start;
+        self.devices.iter().map(removals).sum()
+    }
+}
+
+/// Operations on a [`SimDevice`] that can be made to fail with [`SimDevice::inject_error`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum SimOp {
+    Open,
+    GetConfiguration,
+    SetConfiguration,
+    ClaimInterface,
+    ReleaseInterface,
+    SelectInterface,
+    SetAltInterface,
+    GetAltInterface,
+    QueryInterfaceSettings,
+    QueryPipe,
+    GetSuperSpeedPipeCompanion,
+    QueryDeviceInformation,
+    ReadPipe,
+    WritePipe,
+    ControlTransfer,
+    SetPipePolicy,
+    GetPipePolicy,
+    SetPowerPolicy,
+    GetPowerPolicy,
+    ResetPipe,
+    AbortPipe,
+    FlushPipe,
+    GetDescriptor,
+    ResetDevice,
+}
+
+/// A control transfer received by a [`SimDevice`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SimControl {
+    pub request_type: u8,
+    pub request: u8,
+    pub value: u16,
+    pub index: u16,
+    /// The data sent by the host, empty for inbound transfers.
+    pub data: Vec<u8>,
+}
+
+/// Builds a configuration descriptor for a [`SimDevice`], filling in its total length and the
+/// number of interfaces and endpoints.
+#[derive(Debug, Clone)]
+pub struct SimConfigBuilder {
+    descriptor: Vec<u8>,
+    /// The offset of the interface descriptor that endpoints are added to.
+    interface: Option<usize>,
+    interface_numbers: HashSet<u8>,
+}
+
+impl SimConfigBuilder {
+    /// Starts a self-powered configuration with the `bConfigurationValue` `value`.
+    pub fn new(value: u8) -> Self {
+        Self {
+            descriptor: vec![0x09, 0x02, 0x00, 0x00, 0x00, value, 0x00, 0x80, 0x32],
+            interface: None,
+            interface_numbers: HashSet::new(),
+        }
+    }
+
+    /// Adds an alternate setting of a vendor-specific interface. Endpoints added afterwards
+    /// belong to it.
+    pub fn interface(&mut self, number: u8, alt_setting: u8) -> &mut Self {
+        self.interface = Some(self.descriptor.len());
+        self.interface_numbers.insert(number);
+        self.descriptor.extend([
+            0x09,
+            0x04,
+            number,
+            alt_setting,
+            0x00,
+            0xFF,
+            0x00,
+            0x00,
+            0x00,
+        ]);
+        self
+    }
+
+    /// Adds an endpoint to the last added interface.
+    pub fn endpoint(
+        &mut self,
+        address: u8,
+        attributes: u8,
+        max_packet_size: u16,
+        interval: u8,
+    ) -> &mut Self {
+        let interface = self.interface.expect("endpoint added before any interface");
+        self.descriptor[interface + 4] += 1;
+        let [size_lo, size_hi] = max_packet_size.to_le_bytes();
+        self.descriptor
+            .extend([0x07, 0x05, address, attributes, size_lo, size_hi, interval]);
+        self
+    }
+
+    /// Adds a SuperSpeed endpoint companion descriptor for the last added endpoint.
+    pub fn superspeed_companion(
+        &mut self,
+        max_burst: u8,
+        attributes: u8,
+        bytes_per_interval: u16,
+    ) -> &mut Self {
+        let [bytes_lo, bytes_hi] = bytes_per_interval.to_le_bytes();
+        self.descriptor
+            .extend([0x06, 0x30, max_burst, attributes, bytes_lo, bytes_hi]);
+        self
+    }
+
+    /// Returns the descriptor with `wTotalLength` and `bNumInterfaces` filled in.
+    pub fn build(&self) -> Vec<u8> {
+        let mut descriptor = self.descriptor.clone();
+        let total_length = (descriptor.len() as u16).to_le_bytes();
+        descriptor[2..4].copy_from_slice(&total_length);
+        descriptor[4] = self.interface_numbers.len() as u8;
+        descriptor
+    }
+}
+
+/// A scripted device served by a [`SimBackend`].
+///
+/// Clones share the same device, so a test can keep one to queue data and inspect what the
+/// safe API did with it.
+#[derive(Debug, Clone)]
+pub struct SimDevice {
+    state: Arc<Mutex<SimState>>,
+}
+
+#[derive(Debug)]
+struct SimState {
+    info: KLST_DEVINFO,
+    interfaces: Vec<u8>,
+    /// The `bConfigurationValue` of the active configuration, zero if unconfigured.
+    configuration: u8,
+    claimed: HashSet<u8>,
+    selected: Option<u8>,
+    /// The number of alternate settings of interfaces that have more than one.
+    alt_setting_counts: HashMap<u8, u8>,
+    alt_settings: HashMap<u8, u8>,
+    open_handles: usize,
+    reads: HashMap<u8, VecDeque<Vec<u8>>>,
+    /// Pipes whose reads wait for data instead of timing out.
+    blocking: HashSet<u8>,
+    halted: HashSet<u8>,
+    /// The number of times each pipe was aborted, so blocked reads notice an abort.
+    aborts: HashMap<u8, usize>,
+    writes: HashMap<u8, Vec<Vec<u8>>>,
+    control_in: VecDeque<Vec<u8>>,
+    control_log: Vec<SimControl>,
+    pipe_policies: HashMap<(u8, u32), Vec<u8>>,
+    power_policies: HashMap<u32, Vec<u8>>,
+    remote_wakeup: bool,
+    descriptors: HashMap<(u8, u8, u16), Vec<u8>>,
+    instance_id: String,
+    errors: HashMap<SimOp, VecDeque<u32>>,
+    frame_number: u32,
+    /// The speed reported for `DEVICE_SPEED`.
+    speed: u8,
+    resets: usize,
+    /// How long the device stays connected after a reset before it drops off the bus.
+    removal_delay: Duration,
+    /// How long the device stays disconnected after a reset, or `None` if it never comes back.
+    reset_delay: Option<Duration>,
+    /// How often the device came back after a reset.
+    reconnects: usize,
+    /// How often the device was connected and disconnected, for watches.
+    arrivals: u32,
+    removals: u32,
+}
+
+impl SimDevice {
+    /// Creates a connected libusbK device with a single interface numbered 0, and a USB 2.0
+    /// device descriptor and configuration descriptor describing it.
+    pub fn new(vendor_id: u16, product_id: u16) -> Self {
+        let id = NEXT_DEVICE.fetch_add(1, Ordering::Relaxed);
+
+        let mut info = KLST_DEVINFO::default();
+        info.Common.Vid = vendor_id.into();
+        info.Common.Pid = product_id.into();
+        info.Common.MI = -1;
+        info.DriverID = DriverId::LibUsbK as i32;
+        info.Connected = 1;
+        set_string(&mut info.Service, "libusbK");
+        set_string(
+            &mut info.DevicePath,
+            &format!(r"\\?\sim#vid_{vendor_id:04x}&pid_{product_id:04x}#{id}"),
+        );
+
+        let device = Self {
+            state: Arc::new(Mutex::new(SimState {
+                info,
+                instance_id: String::new(),
+                interfaces: Vec::new(),
+                configuration: 1,
+                claimed: HashSet::new(),
+                selected: None,
+                alt_setting_counts: HashMap::new(),
+                alt_settings: HashMap::new(),
+                open_handles: 0,
+                reads: HashMap::new(),
+                blocking: HashSet::new(),
+                halted: HashSet::new(),
+                aborts: HashMap::new(),
+                writes: HashMap::new(),
+                control_in: VecDeque::new(),
+                control_log: Vec::new(),
+                pipe_policies: HashMap::new(),
+                power_policies: HashMap::new(),
+                remote_wakeup: false,
+                descriptors: HashMap::new(),
+                errors: HashMap::new(),
+                frame_number: 0,
+                speed: SPEED_HIGH,
+                resets: 0,
+                removal_delay: Duration::ZERO,
+                reset_delay: Some(Duration::from_millis(10)),
+                reconnects: 0,
+                arrivals: 0,
+                removals: 0,
+            })),
+        };
+        device.set_instance_id(&id.to_string());
+
+        let [vid_lo, vid_hi] = vendor_id.to_le_bytes();
+        let [pid_lo, pid_hi] = product_id.to_le_bytes();
+        device.set_descriptor(
+            DESCRIPTOR_TYPE_DEVICE,
+            0,
+            0,
+            &[
+                0x12, 0x01, 0x00, 0x02, 0x00, 0x00, 0x00, 0x40, vid_lo, vid_hi, pid_lo, pid_hi,
+                0x00, 0x01, 0x00, 0x00, 0x00, 0x01,
+            ],
+        );
+        device.set_interfaces(&[0]);
+        device
+    }
+
+    /// Sets the serial number, which also becomes the instance part of the device ID.
+    pub fn set_serial_number(&self, serial_number: &str) {
+        set_string(&mut self.state().info.SerialNumber, serial_number);
+        self.set_instance_id(serial_number);
+    }
+
+    pub fn set_manufacturer(&self, manufacturer: &str) {
+        set_string(&mut self.state().info.Mfg, manufacturer);
+    }
+
+    pub fn set_device_description(&self, description: &str) {
+        set_string(&mut self.state().info.DeviceDesc, description);
+    }
+
+    pub fn set_interface_guid(&self, guid: &str) {
+        set_string(&mut self.state().info.DeviceInterfaceGUID, guid);
+    }
+
+    pub fn set_class_guid(&self, guid: &str) {
+        set_string(&mut self.state().info.ClassGUID, guid);
+    }
+
+    /// Marks the device as unplugged but still installed. Disconnected devices are only listed
+    /// with `KLST_FLAG_INCLUDE_DISCONNECT` and cannot be opened.
+    pub fn set_connected(&self, connected: bool) {
+        self.state().set_connected(connected);
+    }
+
+    pub fn set_driver_id(&self, driver_id: DriverId) {
+        self.state().info.DriverID = driver_id as i32;
+    }
+
+    /// Makes the device a function of a composite device, with `MI_xx` in its device ID.
+    pub fn set_interface_number(&self, interface_number: u8) {
+        self.state().info.Common.MI = interface_number.into();
+        let instance_id = self.state().instance_id.clone();
+        self.set_instance_id(&instance_id);
+    }
+
+    /// Sets the interface numbers, in index order, that can be claimed.
+    ///
+    /// This also replaces configuration descriptor 0 with one listing the interfaces, without
+    /// endpoints. Set a [configuration](Self::set_config) afterwards to describe the endpoints.
+    pub fn set_interfaces(&self, interfaces: &[u8]) {
+        let mut config = SimConfigBuilder::new(1);
+        for &number in interfaces {
+            config.interface(number, 0);
+        }
+        self.state().interfaces = interfaces.to_vec();
+        self.set_config(0, &config);
+    }
+
+    /// Sets configuration descriptor `index`.
+    pub fn set_config(&self, index: u8, config: &SimConfigBuilder) {
+        self.set_descriptor(DESCRIPTOR_TYPE_CONFIG, index, 0, &config.build());
+    }
+
+    /// Sets `bcdUSB` in the device descriptor.
+    pub fn set_usb_version(&self, bcd_usb: u16) {
+        self.update_device_descriptor(|desc| desc[2..4].copy_from_slice(&bcd_usb.to_le_bytes()));
+    }
+
+    /// Sets `bNumConfigurations` in the device descriptor.
+    pub fn set_num_configurations(&self, count: u8) {
+        self.update_device_descriptor(|desc| desc[17] = count);
+    }
+
+    fn update_device_descriptor(&self, update: impl FnOnce(&mut [u8])) {
+        let mut state = self.state();
+        let desc = state
+            .descriptors
+            .get_mut(&(DESCRIPTOR_TYPE_DEVICE, 0, 0))
+            .expect("the device descriptor was removed");
+        update(desc);
+    }
+
+    /// Sets the number of alternate settings of an interface. Interfaces have a single one
+    /// unless set otherwise.
+    pub fn set_alt_setting_count(&self, interface_number: u8, count: u8) {
+        self.state()
+            .alt_setting_counts
+            .insert(interface_number, count);
+    }
+
+    /// Sets the descriptor returned for GET_DESCRIPTOR requests with the given type, index and
+    /// language ID.
+    pub fn set_descriptor(&self, descriptor_type: u8, index: u8, language_id: u16, data: &[u8]) {
+        self.state()
+            .descriptors
+            .insert((descriptor_type, index, language_id), data.to_vec());
+    }
+
+    /// Sets the languages listed in string descriptor zero.
+    pub fn set_languages(&self, language_ids: &[u16]) {
+        let data: Vec<u8> = language_ids
+            .iter()
+            .flat_map(|id| id.to_le_bytes())
+            .collect();
+        self.set_descriptor(DESCRIPTOR_TYPE_STRING, 0, 0, &string_descriptor(&data));
+    }
+
+    /// Sets the string descriptor at `index` for a language.
+    pub fn set_string(&self, language_id: u16, index: u8, value: &str) {
+        let data: Vec<u8> = value.encode_utf16().flat_map(|c| c.to_le_bytes()).collect();
+        self.set_descriptor(
+            DESCRIPTOR_TYPE_STRING,
+            index,
+            language_id,
+            &string_descriptor(&data),
+        );
+    }
+
+    /// Queues one transfer worth of data to be returned by a read on `pipe_id`.
+    ///
+    /// Reads from a pipe with nothing queued fail with a timeout, unless the pipe was set to
+    /// [block](Self::set_blocking_reads).
+    pub fn push_read(&self, pipe_id: u8, data: &[u8]) {
+        self.state()
+            .reads
+            .entry(pipe_id)
+            .or_default()
+            .push_back(data.to_vec());
+    }
+
+    /// Makes reads on `pipe_id` with nothing queued wait for data until the pipe is aborted,
+    /// instead of failing with a timeout.
+    pub fn set_blocking_reads(&self, pipe_id: u8, blocking: bool) {
+        let mut state = self.state();
+        match blocking {
+            true => state.blocking.insert(pipe_id),
+            false => state.blocking.remove(&pipe_id),
+        };
+    }
+
+    /// Stalls or un-stalls an endpoint. Transfers on a stalled endpoint fail with
+    /// [`Error::Pipe`] until the pipe is reset or the halt is cleared with a CLEAR_FEATURE
+    /// request.
+    pub fn set_halted(&self, pipe_id: u8, halted: bool) {
+        let mut state = self.state();
+        match halted {
+            true => state.halted.insert(pipe_id),
+            false => state.halted.remove(&pipe_id),
+        };
+    }
+
+    pub fn is_halted(&self, pipe_id: u8) -> bool {
+        self.state().halted.contains(&pipe_id)
+    }
+
+    /// Returns and clears the transfers written to `pipe_id`.
+    pub fn take_writes(&self, pipe_id: u8) -> Vec<Vec<u8>> {
+        self.state().writes.remove(&pipe_id).unwrap_or_default()
+    }
+
+    /// Queues the data returned by the next inbound control transfer.
+    ///
+    /// Standard GET_DESCRIPTOR requests are answered from the descriptors set with
+    /// [`set_descriptor`](Self::set_descriptor) instead. Other inbound control transfers with
+    /// nothing queued are stalled.
+    pub fn push_control_in(&self, data: &[u8]) {
+        self.state().control_in.push_back(data.to_vec());
+    }
+
+    /// Returns all control transfers received so far.
+    pub fn control_log(&self) -> Vec<SimControl> {
+        self.state().control_log.clone()
+    }
+
+    /// Returns the last value set for a pipe policy, if any.
+    pub fn pipe_policy(&self, pipe_id: u8, policy_type: u32) -> Option<Vec<u8>> {
+        self.state()
+            .pipe_policies
+            .get(&(pipe_id, policy_type))
+            .cloned()
+    }
+
+    /// Returns the last value set for a power policy, if any.
+    pub fn power_policy(&self, policy_type: u32) -> Option<Vec<u8>> {
+        self.state().power_policies.get(&policy_type).cloned()
+    }
+
+    /// Whether remote wakeup was enabled with a SET_FEATURE request and not cleared since.
+    pub fn remote_wakeup(&self) -> bool {
+        self.state().remote_wakeup
+    }
+
+    /// Makes the next `op` fail with the Win32 error `code`.
+    ///
+    /// Errors for the same operation are returned in the order they were injected.
+    pub fn inject_error(&self, op: SimOp, code: u32) {
+        self.state().errors.entry(op).or_default().push_back(code);
+    }
+
+    /// Sets the speed the driver reports for the device: 1 for low, 2 for full and 3 for high
+    /// speed, which is the default. Higher values are reported as is.
+    pub fn set_speed(&self, speed: u8) {
+        self.state().speed = speed;
+    }
+
+    /// Sets the current USB frame number. Every isochronous packet advances it by one.
+    pub fn set_frame_number(&self, frame_number: u32) {
+        self.state().frame_number = frame_number;
+    }
+
+    /// Sets how long the device stays disconnected after a port reset before it reappears, or
+    /// `None` to keep it disconnected. The default is 10 ms.
+    pub fn set_reset_delay(&self, delay: Option<Duration>) {
+        self.state().reset_delay = delay;
+    }
+
+    /// Sets how long the device can still be found and opened after a port reset before it
+    /// disconnects, like a real device that has not dropped off the bus yet. The default is
+    /// zero, disconnecting it during the reset.
+    pub fn set_removal_delay(&self, delay: Duration) {
+        self.state().removal_delay = delay;
+    }
+
+    /// The number of port resets the device received.
+    pub fn reset_count(&self) -> usize {
+        self.state().resets
+    }
+
+    /// The number of times the device came back after a port reset.
+    pub fn reconnect_count(&self) -> usize {
+        self.state().reconnects
+    }
+
+    /// The `bConfigurationValue` of the active configuration. Devices start in configuration 1.
+    pub fn configuration(&self) -> u8 {
+        self.state().configuration
+    }
+
+    /// The interface numbers currently claimed, in ascending order.
+    pub fn claimed_interfaces(&self) -> Vec<u8> {
+        let mut claimed: Vec<u8> = self.state().claimed.iter().copied().collect();
+        claimed.sort_unstable();
+        claimed
+    }
+
+    /// The interface number last selected, or claimed, through any handle.
+    pub fn selected_interface(&self) -> Option<u8> {
+        self.state().selected
+    }
+
+    /// The number of handles to the device that have not been dropped.
+    pub fn open_handles(&self) -> usize {
+        self.state().open_handles
+    }
+
+    fn set_instance_id(&self, instance_id: &str) {
+        let mut state = self.state();
+        state.instance_id = instance_id.to_owned();
+        let info = &mut state.info;
+        let mut device_id = format!(
+            r"USB\VID_{:04X}&PID_{:04X}",
+            info.Common.Vid, info.Common.Pid
+        );
+        if info.Common.MI >= 0 {
+            device_id += &format!("&MI_{:02X}", info.Common.MI);
+        }
+        device_id += &format!(r"\{instance_id}");
+        set_string(&mut info.DeviceID, &device_id);
+        set_string(&mut info.Common.InstanceID, &device_id);
+    }
+
+    fn state(&self) -> MutexGuard<'_, SimState> {
+        self.state.lock().unwrap()
+    }
+}
+
+impl SimState {
+    /// Connects or disconnects the device, counting the change for watches.
+    fn set_connected(&mut self, connected: bool) {
+        match (self.info.Connected != 0, connected) {
+            (false, true) => self.arrivals += 1,
+            (true, false) => self.removals += 1,
+            _ => {}
+        }
+        self.info.Connected = connected.into();
+    }
+
+    fn take_error(&mut self, op: SimOp) -> Result<()> {
+        match self.errors.get_mut(&op).and_then(VecDeque::pop_front) {
+            Some(code) => Err(Error::from_code(code)),
+            None => Ok(()),
+        }
+    }
+
+    fn descriptor(&self, descriptor_type: u8, index: u8, language_id: u16) -> Result<Vec<u8>> {
+        self.descriptors
+            .get(&(descriptor_type, index, language_id))
+            .cloned()
+            .ok_or(Error::from_code(ERROR_GEN_FAILURE))
+    }
+
+    fn check_halted(&self, pipe_id: u8) -> Result<()> {
+        match self.halted.contains(&pipe_id) {
+            true => Err(Error::from_code(ERROR_GEN_FAILURE)),
+            false => Ok(()),
+        }
+    }
+
+    fn abort_count(&self, pipe_id: u8) -> usize {
+        self.aborts.get(&pipe_id).copied().unwrap_or_default()
+    }
+
+    fn next_read(&mut self, pipe_id: u8) -> Option<Vec<u8>> {
+        self.reads.get_mut(&pipe_id).and_then(VecDeque::pop_front)
+    }
+
+    fn write(&mut self, pipe_id: u8, data: &[u8]) -> u32 {
+        self.writes.entry(pipe_id).or_default().push(data.to_vec());
+        data.len() as u32
+    }
+
+    /// Answers GET_DESCRIPTOR from the descriptor table and other requests from `control_in`.
+    fn read_control(&mut self, setup: WINUSB_SETUP_PACKET, buffer: &mut [u8]) -> Result<u32> {
+        self.take_error(SimOp::ControlTransfer)?;
+        self.control_log.push(SimControl::new(setup, &[]));
+        let data = if setup.RequestType == 0x80 && setup.Request == REQUEST_GET_DESCRIPTOR {
+            let [index, descriptor_type] = setup.Value.to_le_bytes();
+            self.descriptor(descriptor_type, index, setup.Index)?
+        } else {
+            self.control_in
+                .pop_front()
+                .ok_or(Error::from_code(ERROR_GEN_FAILURE))?
+        };
+        let len = buffer.len().min(setup.Length as usize);
+        Ok(copy_into(&mut buffer[..len], &data))
+    }
+
+    /// A standard CLEAR_FEATURE(ENDPOINT_HALT) request un-stalls the endpoint, and
+    /// SET_FEATURE/CLEAR_FEATURE(DEVICE_REMOTE_WAKEUP) requests toggle remote wakeup.
+    fn write_control(&mut self, setup: WINUSB_SETUP_PACKET, buffer: &[u8]) -> Result<u32> {
+        self.take_error(SimOp::ControlTransfer)?;
+        self.control_log.push(SimControl::new(setup, buffer));
+        match (setup.RequestType, setup.Request, setup.Value) {
+            (0x02, REQUEST_CLEAR_FEATURE, FEATURE_ENDPOINT_HALT) => {
+                self.halted.remove(&(setup.Index as u8));
+            }
+            (0x00, REQUEST_SET_FEATURE, FEATURE_DEVICE_REMOTE_WAKEUP) => self.remote_wakeup = true,
+            (0x00, REQUEST_CLEAR_FEATURE, FEATURE_DEVICE_REMOTE_WAKEUP) => {
+                self.remote_wakeup = false
+            }
+            _ => {}
+        }
+        Ok(buffer.len() as u32)
+    }
+
+    /// Parses the configuration descriptor whose `bConfigurationValue` is `value`.
+    fn config(&self, value: u8) -> Result<ConfigDescriptor> {
+        let data = self
+            .descriptors
+            .iter()
+            .find(|((descriptor_type, _, _), data)| {
+                *descriptor_type == DESCRIPTOR_TYPE_CONFIG && data.get(5) == Some(&value)
+            })
+            .map(|(_, data)| data)
+            .ok_or(Error::from_code(ERROR_INVALID_PARAMETER))?;
+        ConfigDescriptor::from_bytes(data)
+    }
+
+    fn interface_number(&self, num_or_index: u8, is_index: bool) -> Result<u8> {
+        let found = if is_index {
+            self.interfaces.get(num_or_index as usize).copied()
+        } else {
+            self.interfaces.iter().copied().find(|&i| i == num_or_index)
+        };
+        found.ok_or(Error::from_code(ERROR_NOT_FOUND))
+    }
+}
+
+#[derive(Debug)]
+struct SimList {
+    devices: Vec<KLST_DEVINFO>,
+}
+
+impl DeviceListBackend for SimList {
+    fn count(&self) -> Result<u32> {
+        Ok(self.devices.len() as u32)
+    }
+
+    fn devices(&self) -> Vec<KLST_DEVINFO_HANDLE> {
+        self.devices
+            .iter()
+            .map(|info| info as *const KLST_DEVINFO as KLST_DEVINFO_HANDLE)
+            .collect()
+    }
+
+    fn find_by_vid_pid(&self, vid: i32, pid: i32) -> Result<KLST_DEVINFO_HANDLE> {
+        self.devices
+            .iter()
+            .find(|info| info.Common.Vid == vid && info.Common.Pid == pid)
+            .map(|info| info as *const KLST_DEVINFO as KLST_DEVINFO_HANDLE)
+            .ok_or(Error::from_code(ERROR_NOT_FOUND))
+    }
+}
+
+#[derive(Debug)]
+struct SimHandle {
+    device: SimDevice,
+    /// The index of the interface the handle was opened for.
+    interface_index: usize,
+}
+
+impl Drop for SimHandle {
+    fn drop(&mut self) {
+        self.device.state().open_handles -= 1;
+    }
+}
+
+impl SimHandle {
+    /// Looks up an alternate setting of the selected interface, or of the handle's own interface
+    /// if none was selected, in the active configuration.
+    fn alt_setting(&self, state: &SimState, alt_setting_index: u8) -> Result<InterfaceDescriptor> {
+        let number = state
+            .selected
+            .or(state.interfaces.get(self.interface_index).copied())
+            .ok_or(Error::from_code(ERROR_NOT_FOUND))?;
+        let config = state.config(state.configuration)?;
+        let setting = config
+            .interfaces()
+            .find(|interface| interface.number() == number)
+            .and_then(|interface| interface.descriptors().nth(alt_setting_index.into()))
+            .cloned();
+        setting.ok_or(Error::from_code(ERROR_NO_MORE_ITEMS))
+    }
+}
+
+impl DeviceHandleBackend for SimHandle {
+    fn get_configuration(&mut self) -> Result<u8> {
+        let mut state = self.device.state();
+        state.take_error(SimOp::GetConfiguration)?;
+        Ok(state.configuration)
+    }
+
+    /// Accepts zero and the values of the configuration descriptors that were set, and
+    /// releases every interface.
+    fn set_configuration(&mut self, value: u8) -> Result<()> {
+        let mut state = self.device.state();
+        state.take_error(SimOp::SetConfiguration)?;
+        if value != 0 {
+            state.config(value)?;
+        }
+        state.configuration = value;
+        state.claimed.clear();
+        state.selected = None;
+        state.alt_settings.clear();
+        Ok(())
+    }
+
+    fn claim_interface(&mut self, num_or_index: u8, is_index: bool) -> Result<()> {
+        let mut state = self.device.state();
+        state.take_error(SimOp::ClaimInterface)?;
+        let number = state.interface_number(num_or_index, is_index)?;
+        state.claimed.insert(number);
+        state.selected = Some(number);
+        Ok(())
+    }
+
+    fn release_interface(&mut self, num_or_index: u8, is_index: bool) -> Result<()> {
+        let mut state = self.device.state();
+        state.take_error(SimOp::ReleaseInterface)?;
+        let number = state.interface_number(num_or_index, is_index)?;
+        match state.claimed.remove(&number) {
+            true => Ok(()),
+            false => Err(Error::from_code(ERROR_INVALID_PARAMETER)),
+        }
+    }
+
+    fn select_interface(&mut self, num_or_index: u8, is_index: bool) -> Result<()> {
+        let mut state = self.device.state();
+        state.take_error(SimOp::SelectInterface)?;
+        state.selected = Some(state.interface_number(num_or_index, is_index)?);
+        Ok(())
+    }
+
+    fn set_alt_interface(
+        &mut self,
+        num_or_index: u8,
+        is_index: bool,
+        alt_setting: u8,
+    ) -> Result<()> {
+        let mut state = self.device.state();
+        state.take_error(SimOp::SetAltInterface)?;
+        let number = state.interface_number(num_or_index, is_index)?;
+        let count = state.alt_setting_counts.get(&number).copied().unwrap_or(1);
+        if alt_setting >= count {
+            return Err(Error::from_code(ERROR_NOT_FOUND));
+        }
+        state.alt_settings.insert(number, alt_setting);
+        Ok(())
+    }
+
+    fn get_alt_interface(&mut self, num_or_index: u8, is_index: bool) -> Result<u8> {
+        let mut state = self.device.state();
+        state.take_error(SimOp::GetAltInterface)?;
+        let number = state.interface_number(num_or_index, is_index)?;
+        Ok(state.alt_settings.get(&number).copied().unwrap_or_default())
+    }
+
+    fn query_interface_settings(
+        &mut self,
+        alt_setting_index: u8,
+    ) -> Result<USB_INTERFACE_DESCRIPTOR> {
+        let mut state = self.device.state();
+        state.take_error(SimOp::QueryInterfaceSettings)?;
+        Ok(self.alt_setting(&state, alt_setting_index)?.to_native())
+    }
+
+    fn query_pipe(
+        &mut self,
+        alt_setting_index: u8,
+        pipe_index: u8,
+    ) -> Result<WINUSB_PIPE_INFORMATION_EX> {
+        let mut state = self.device.state();
+        state.take_error(SimOp::QueryPipe)?;
+        let setting = self.alt_setting(&state, alt_setting_index)?;
+        let endpoint = setting
+            .endpoint_descriptors()
+            .nth(pipe_index.into())
+            .ok_or(Error::from_code(ERROR_NO_MORE_ITEMS))?;
+
+        let max_packet_size = endpoint.max_packet_size();
+        let max_bytes_per_interval = match endpoint.superspeed_companion() {
+            Some(companion) => companion.bytes_per_interval().into(),
+            None => {
+                u32::from(max_packet_size & 0x7FF) * (u32::from(max_packet_size >> 11 & 0x03) + 1)
+            }
+        };
+        Ok(WINUSB_PIPE_INFORMATION_EX {
+            PipeType: (endpoint.transfer_type() as u8).into(),
+            PipeId: endpoint.address(),
+            MaximumPacketSize: max_packet_size & 0x7FF,
+            Interval: endpoint.interval(),
+            MaximumBytesPerInterval: max_bytes_per_interval as ULONG,
+        })
+    }
+
+    /// Fails with `ERROR_NOT_FOUND` for endpoints without a companion descriptor.
+    fn superspeed_pipe_companion(
+        &mut self,
+        alt_setting_index: u8,
+        pipe_index: u8,
+    ) -> Result<_USB_SUPERSPEED_ENDPOINT_COMPANION_DESCRIPTOR> {
+        let mut state = self.device.state();
+        state.take_error(SimOp::GetSuperSpeedPipeCompanion)?;
+        let setting = self.alt_setting(&state, alt_setting_index)?;
+        let endpoint = setting
+            .endpoint_descriptors()
+            .nth(pipe_index.into())
+            .ok_or(Error::from_code(ERROR_NO_MORE_ITEMS))?;
+        let companion = endpoint
+            .superspeed_companion()
+            .ok_or(Error::from_code(ERROR_NOT_FOUND))?;
+        Ok(companion.to_native())
+    }
+
+    /// Only `DEVICE_SPEED` is supported.
+    fn query_device_information(
+        &mut self,
+        information_type: u32,
+        buffer: &mut [u8],
+    ) -> Result<u32> {
+        let mut state = self.device.state();
+        state.take_error(SimOp::QueryDeviceInformation)?;
+        match information_type {
+            DEVICE_INFORMATION_SPEED => Ok(copy_into(buffer, &[state.speed])),
+            _ => Err(Error::from_code(ERROR_INVALID_PARAMETER)),
+        }
+    }
+
+    fn read_pipe(&mut self, pipe_id: u8, buffer: &mut [u8]) -> Result<u32> {
+        let mut state = self.device.state();
+        state.take_error(SimOp::ReadPipe)?;
+        state.check_halted(pipe_id)?;
+        let aborts = state.abort_count(pipe_id);
+        loop {
+            if let Some(data) = state.next_read(pipe_id) {
+                return Ok(copy_into(buffer, &data));
+            }
+            if !state.blocking.contains(&pipe_id) {
+                return Err(Error::from_code(ERROR_SEM_TIMEOUT));
+            }
+            if state.abort_count(pipe_id) != aborts {
+                return Err(Error::from_code(ERROR_OPERATION_ABORTED));
+            }
+            drop(state);
+            thread::sleep(Duration::from_millis(1));
+            state = self.device.state();
+        }
+    }
+
+    fn write_pipe(&mut self, pipe_id: u8, buffer: &[u8]) -> Result<u32> {
+        let mut state = self.device.state();
+        state.take_error(SimOp::WritePipe)?;
+        state.check_halted(pipe_id)?;
+        Ok(state.write(pipe_id, buffer))
+    }
+
+    fn read_control(&mut self, setup: WINUSB_SETUP_PACKET, buffer: &mut [u8]) -> Result<u32> {
+        self.device.state().read_control(setup, buffer)
+    }
+
+    fn write_control(&mut self, setup: WINUSB_SETUP_PACKET, buffer: &[u8]) -> Result<u32> {
+        self.device.state().write_control(setup, buffer)
+    }
+
+    fn set_pipe_policy(&mut self, pipe_id: u8, policy_type: u32, value: &[u8]) -> Result<()> {
+        let mut state = self.device.state();
+        state.take_error(SimOp::SetPipePolicy)?;
+        state
+            .pipe_policies
+            .insert((pipe_id, policy_type), value.to_vec());
+        Ok(())
+    }
+
+    /// Policies that were never set read as zero.
+    fn get_pipe_policy(&mut self, pipe_id: u8, policy_type: u32, value: &mut [u8]) -> Result<u32> {
+        let mut state = self.device.state();
+        state.take_error(SimOp::GetPipePolicy)?;
+        match state.pipe_policies.get(&(pipe_id, policy_type)) {
+            Some(data) => Ok(copy_into(value, data)),
+            None => {
+                value.fill(0);
+                Ok(value.len() as u32)
+            }
+        }
+    }
+
+    fn set_power_policy(&mut self, policy_type: u32, value: &[u8]) -> Result<()> {
+        let mut state = self.device.state();
+        state.take_error(SimOp::SetPowerPolicy)?;
+        state.power_policies.insert(policy_type, value.to_vec());
+        Ok(())
+    }
+
+    /// Policies that were never set read as zero.
+    fn get_power_policy(&mut self, policy_type: u32, value: &mut [u8]) -> Result<u32> {
+        let mut state = self.device.state();
+        state.take_error(SimOp::GetPowerPolicy)?;
+        match state.power_policies.get(&policy_type) {
+            Some(data) => Ok(copy_into(value, data)),
+            None => {
+                value.fill(0);
+                Ok(value.len() as u32)
+            }
+        }
+    }
+
+    fn reset_pipe(&mut self, pipe_id: u8) -> Result<()> {
+        let mut state = self.device.state();
+        state.take_error(SimOp::ResetPipe)?;
+        state.halted.remove(&pipe_id);
+        *state.aborts.entry(pipe_id).or_default() += 1;
+        Ok(())
+    }
+
+    fn abort_pipe(&mut self, pipe_id: u8) -> Result<()> {
+        let mut state = self.device.state();
+        state.take_error(SimOp::AbortPipe)?;
+        *state.aborts.entry(pipe_id).or_default() += 1;
+        Ok(())
+    }
+
+    /// Discards the reads queued on the pipe.
+    fn flush_pipe(&mut self, pipe_id: u8) -> Result<()> {
+        let mut state = self.device.state();
+        state.take_error(SimOp::FlushPipe)?;
+        state.reads.remove(&pipe_id);
+        Ok(())
+    }
+
+    fn get_descriptor(
+        &mut self,
+        descriptor_type: u8,
+        index: u8,
+        language_id: u16,
+        buffer: &mut [u8],
+    ) -> Result<u32> {
+        let mut state = self.device.state();
+        state.take_error(SimOp::GetDescriptor)?;
+        let data = state.descriptor(descriptor_type, index, language_id)?;
+        Ok(copy_into(buffer, &data))
+    }
+
+    /// Each packet of a read takes one queued read, truncated to the packet; packets with
+    /// nothing queued come back empty. Each packet of a write is recorded as one write.
+    fn iso_transfer(
+        &mut self,
+        pipe_id: u8,
+        buffer: &mut [u8],
+        packets: &mut [IsoPacket],
+        start_frame: Option<u32>,
+        _timeout: Duration,
+    ) -> Result<Option<u32>> {
+        let read = pipe_id & ENDPOINT_DIR_MASK == ENDPOINT_IN;
+        let mut state = self.device.state();
+        state.take_error(if read {
+            SimOp::ReadPipe
+        } else {
+            SimOp::WritePipe
+        })?;
+
+        let start_frame = start_frame.unwrap_or(state.frame_number);
+        for packet in packets.iter_mut() {
+            let region = &mut buffer[packet.offset as usize..][..packet.size as usize];
+            packet.length = match read {
+                true => state
+                    .next_read(pipe_id)
+                    .map_or(0, |data| copy_into(region, &data)),
+                false => state.write(pipe_id, region),
+            };
+            packet.status = 0;
+        }
+        state.frame_number = start_frame.wrapping_add(packets.len() as u32);
+        Ok(Some(start_frame))
+    }
+
+    fn current_frame_number(&mut self) -> Result<u32> {
+        Ok(self.device.state().frame_number)
+    }
+
+    /// Schedules full speed pipes once per frame and high speed pipes with an `Interval` of 1
+    /// to 4 every 1 to 8 microframes. Longer high speed intervals fail with
+    /// `ERROR_NOT_SUPPORTED`.
+    fn iso_packet_information(
+        &self,
+        high_speed: bool,
+        pipe: &WINUSB_PIPE_INFORMATION_EX,
+    ) -> Result<KISOCH_PACKET_INFORMATION> {
+        let packets_per_frame = match (high_speed, pipe.Interval) {
+            (false, _) => 1,
+            (true, interval @ 1..=4) => 8 >> (interval - 1),
+            (true, _) => return Err(Error::from_code(ERROR_NOT_SUPPORTED)),
+        };
+        Ok(KISOCH_PACKET_INFORMATION {
+            PacketsPerFrame: packets_per_frame,
+            PollingPeriodMicroseconds: 1000 / packets_per_frame,
+            BytesPerMillisecond: pipe.MaximumBytesPerInterval as u32 * packets_per_frame,
+        })
+    }
+
+    /// Releases the interfaces of the device and disconnects it after the
+    /// [removal delay](SimDevice::set_removal_delay), then reconnects it from another thread
+    /// after the [reset delay](SimDevice::set_reset_delay) unless it is reset again first.
+    fn reset_device(&mut self) -> Result<()> {
+        let mut state = self.device.state();
+        state.take_error(SimOp::ResetDevice)?;
+        state.resets += 1;
+        if state.removal_delay.is_zero() {
+            state.set_connected(false);
+        }
+        // Windows selects the first configuration again when the device re-enumerates.
+        state.configuration = 1;
+        state.claimed.clear();
+        state.selected = None;
+        state.alt_settings.clear();
+
+        let device = self.device.clone();
+        let reset = state.resets;
+        let (removal_delay, reset_delay) = (state.removal_delay, state.reset_delay);
+        thread::spawn(move || {
+            // A later reset decides on its own when the device drops off and comes back.
+            thread::sleep(removal_delay);
+            let mut state = device.state();
+            if state.resets != reset {
+                return;
+            }
+            state.set_connected(false);
+            drop(state);
+
+            let Some(delay) = reset_delay else { return };
+            thread::sleep(delay);
+            let mut state = device.state();
+            if state.resets == reset {
+                state.set_connected(true);
+                state.reconnects += 1;
+            }
+        });
+        Ok(())
+    }
+
+    /// Handles for associated interfaces count as open handles to the device. They fail with
+    /// `ERROR_NO_MORE_ITEMS` past the last interface.
+    fn associated_interface(&self, index: u8) -> Result<Box<dyn DeviceHandleBackend>> {
+        let mut state = self.device.state();
+        let interface_index = self.interface_index + 1 + usize::from(index);
+        if interface_index >= state.interfaces.len() {
+            return Err(Error::from_code(ERROR_NO_MORE_ITEMS));
+        }
+        state.open_handles += 1;
+        Ok(Box::new(SimHandle {
+            device: self.device.clone(),
+            interface_index,
+        }))
+    }
+
+    fn try_clone(&self) -> Result<Box<dyn DeviceHandleBackend>> {
+        self.device.state().open_handles += 1;
+        Ok(Box::new(SimHandle {
+            device: self.device.clone(),
+            interface_index: self.interface_index,
+        }))
+    }
+
+    fn raw_handle(&self) -> Option<NonNull<c_void>> {
+        None
+    }
+
+    /// Simulated devices have no device file, so no property is supported.
+    fn get_property(&self, _property: KUSB_PROPERTY, _value: &mut [u8]) -> Result<u32> {
+        Err(Error::from_code(ERROR_NOT_SUPPORTED))
+    }
+
+    fn overlapped_pool(&self, max_transfers: usize) -> Result<Arc<dyn OverlappedPoolBackend>> {
+        self.device.state().open_handles += 1;
+        Ok(Arc::new(SimPool {
+            device: self.device.clone(),
+            open: Arc::new(SimOpen(self.device.clone())),
+            max_transfers,
+            transfers: Mutex::new(Vec::new()),
+            woken: AtomicBool::new(false),
+        }))
+    }
+
+    fn stream(&self, pipe_id: u8, builder: &StreamBuilder) -> Result<Box<dyn StreamBackend>> {
+        self.device.state().open_handles += 1;
+        Ok(Box::new(SimStream {
+            open: SimOpen(self.device.clone()),
+            pipe_id,
+            max_transfer_size: builder.max_transfer_size,
+            max_pending_transfers: builder.max_pending_transfers,
+            callbacks: builder.callbacks.clone(),
+            next_index: 0,
+            received: VecDeque::new(),
+        }))
+    }
+}
+
+/// Every transfer is submitted and completed within the read or write that needs it. Reads
+/// time out when the device has no data queued.
+#[derive(Debug)]
+struct SimStream {
+    open: SimOpen,
+    pipe_id: u8,
+    max_transfer_size: usize,
+    max_pending_transfers: usize,
+    callbacks: StreamCallbacks,
+    next_index: usize,
+    /// Data from the last completed read transfer that has not been read yet.
+    received: VecDeque<u8>,
+}
+
+impl SimStream {
+    /// Returns the transfer context to use next, or reports an injected error for it.
+    fn next_transfer(&mut self, op: SimOp) -> Result<usize> {
+        let index = self.next_index;
+        let injected = self.open.0.state().take_error(op);
+        if let Err(err) = injected {
+            self.callbacks.error(index, err);
+            return Err(err);
+        }
+        self.next_index = (index + 1) % self.max_pending_transfers;
+        Ok(index)
+    }
+}
+
+impl StreamBackend for SimStream {
+    fn start(&mut self) -> Result<()> {
+        (0..self.max_pending_transfers).for_each(|index| self.callbacks.started(index));
+        Ok(())
+    }
+
+    fn stop(&mut self, _timeout: Duration) -> Result<()> {
+        (0..self.max_pending_transfers).for_each(|index| self.callbacks.stopped(index));
+        Ok(())
+    }
+
+    fn read(&mut self, buffer: &mut [u8]) -> Result<u32> {
+        if self.received.is_empty() {
+            let index = self.next_transfer(SimOp::ReadPipe)?;
+            let mut data = self
+                .open
+                .0
+                .state()
+                .next_read(self.pipe_id)
+                .ok_or(Error::from_code(WAIT_TIMEOUT))?;
+
+            data.truncate(self.max_transfer_size);
+            self.callbacks.submit(index, self.max_transfer_size);
+            self.callbacks.complete(index, Ok(&data));
+            self.received.extend(data);
+        }
+        let len = buffer.len().min(self.received.len());
+        for (dst, src) in buffer.iter_mut().zip(self.received.drain(..len)) {
+            *dst = src;
+        }
+        Ok(len as u32)
+    }
+
+    fn write(&mut self, buffer: &[u8]) -> Result<u32> {
+        for chunk in buffer.chunks(self.max_transfer_size) {
+            let index = self.next_transfer(SimOp::WritePipe)?;
+            self.open.0.state().write(self.pipe_id, chunk);
+
+            self.callbacks.submit(index, chunk.len());
+            self.callbacks.complete(index, Ok(chunk));
+        }
+        Ok(buffer.len() as u32)
+    }
+}
+
+/// Pipe transfers complete as soon as the device has data for them, checked whenever they are
+/// waited on. Writes complete immediately.
+#[derive(Debug)]
+struct SimPool {
+    device: SimDevice,
+    open: Arc<SimOpen>,
+    max_transfers: usize,
+    /// Transfers in submission order, for `wait_oldest`.
+    transfers: Mutex<Vec<Weak<Mutex<SimIo>>>>,
+    /// Set by `wake` until `wait_any` sees it.
+    woken: AtomicBool,
+}
+
+/// Counts as an open handle to the device until the pool and all its transfers are dropped.
+#[derive(Debug)]
+struct SimOpen(SimDevice);
+
+impl Drop for SimOpen {
+    fn drop(&mut self) {
+        self.0.state().open_handles -= 1;
+    }
+}
+
+#[derive(Debug)]
+enum SimIo {
+    Read {
+        pipe_id: u8,
+        buffer: *mut u8,
+        len: usize,
+    },
+    Done(Result<u32>),
+}
+
+// SAFETY: the buffer is owned by the transfer and only accessed under the lock.
+unsafe impl Send for SimIo {}
+
+impl SimIo {
+    /// Completes a pending read if the device has data for it.
+    fn poll(&mut self, state: &mut SimState) -> Option<Result<u32>> {
+        if let SimIo::Read {
+            pipe_id,
+            buffer,
+            len,
+        } = *self
+        {
+            let data = state.next_read(pipe_id)?;
+            // SAFETY: guaranteed by the caller of `submit_read`.
+            let buffer = unsafe { std::slice::from_raw_parts_mut(buffer, len) };
+            *self = SimIo::Done(Ok(copy_into(buffer, &data)));
+        }
+        match self {
+            SimIo::Done(result) => Some(*result),
+            SimIo::Read { .. } => None,
+        }
+    }
+}
+
+impl SimPool {
+    fn submit(&self, io: SimIo) -> Result<Box<dyn PendingTransfer>> {
+        let mut transfers = self.transfers.lock().unwrap();
+        transfers.retain(|t| t.strong_count() > 0);
+        if transfers.len() >= self.max_transfers {
+            return Err(Error::from_code(ERROR_NO_MORE_ITEMS));
+        }
+
+        let io = Arc::new(Mutex::new(io));
+        transfers.push(Arc::downgrade(&io));
+        Ok(Box::new(SimTransfer {
+            device: self.device.clone(),
+            _open: self.open.clone(),
+            io,
+        }))
+    }
+}
+
+impl OverlappedPoolBackend for SimPool {
+    unsafe fn submit_read(
+        &self,
+        pipe_id: u8,
+        buffer: *mut u8,
+        len: usize,
+    ) -> Result<Box<dyn PendingTransfer>> {
+        self.device.state().take_error(SimOp::ReadPipe)?;
+        self.submit(SimIo::Read {
+            pipe_id,
+            buffer,
+            len,
+        })
+    }
+
+    unsafe fn submit_write(
+        &self,
+        pipe_id: u8,
+        buffer: *const u8,
+        len: usize,
+    ) -> Result<Box<dyn PendingTransfer>> {
+        let mut state = self.device.state();
+        state.take_error(SimOp::WritePipe)?;
+        let data = std::slice::from_raw_parts(buffer, len);
+        let written = state.write(pipe_id, data);
+        drop(state);
+        self.submit(SimIo::Done(Ok(written)))
+    }
+
+    /// Control transfers complete immediately.
+    unsafe fn submit_control(
+        &self,
+        setup: WINUSB_SETUP_PACKET,
+        buffer: *mut u8,
+        len: usize,
+    ) -> Result<Box<dyn PendingTransfer>> {
+        let buffer = std::slice::from_raw_parts_mut(buffer, len);
+        let result = match setup.RequestType & 0x80 {
+            0 => self.device.state().write_control(setup, buffer),
+            _ => self.device.state().read_control(setup, buffer),
+        };
+        self.submit(SimIo::Done(Ok(result?)))
+    }
+
+    fn wait_oldest(&self, timeout: Option<Duration>) -> Result<()> {
+        let oldest = self
+            .transfers
+            .lock()
+            .unwrap()
+            .iter()
+            .find_map(Weak::upgrade)
+            .ok_or(Error::from_code(ERROR_NO_MORE_ITEMS))?;
+        SimTransfer {
+            device: self.device.clone(),
+            _open: self.open.clone(),
+            io: oldest,
+        }
+        .wait(timeout)
+        .map(drop)
+    }
+
+    fn wait_any(&self) -> Result<()> {
+        loop {
+            if self.woken.swap(false, Ordering::SeqCst) {
+                return Ok(());
+            }
+            let transfers: Vec<_> = self
+                .transfers
+                .lock()
+                .unwrap()
+                .iter()
+                .filter_map(Weak::upgrade)
+                .collect();
+            if transfers
+                .iter()
+                .any(|io| io.lock().unwrap().poll(&mut self.device.state()).is_some())
+            {
+                return Ok(());
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    fn wake(&self) {
+        self.woken.store(true, Ordering::SeqCst);
+    }
+}
+
+#[derive(Debug)]
+struct SimTransfer {
+    device: SimDevice,
+    _open: Arc<SimOpen>,
+    io: Arc<Mutex<SimIo>>,
+}
+
+impl PendingTransfer for SimTransfer {
+    fn wait(&mut self, timeout: Option<Duration>) -> Result<u32> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            if let Some(result) = self.io.lock().unwrap().poll(&mut self.device.state()) {
+                return result;
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Err(Error::from_code(WAIT_TIMEOUT));
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    fn wait_or_cancel(&mut self, timeout: Duration) -> Result<u32> {
+        match self.wait(Some(timeout)) {
+            Err(Error::Timeout(_)) => {
+                let cancelled = Err(Error::from_code(ERROR_OPERATION_ABORTED));
+                *self.io.lock().unwrap() = SimIo::Done(cancelled);
+                cancelled
+            }
+            result => result,
+        }
+    }
+
+    fn is_complete(&self) -> bool {
+        self.io
+            .lock()
+            .unwrap()
+            .poll(&mut self.device.state())
+            .is_some()
+    }
+}
+
+impl SimControl {
+    fn new(setup: WINUSB_SETUP_PACKET, data: &[u8]) -> Self {
+        Self {
+            request_type: setup.RequestType,
+            request: setup.Request,
+            value: setup.Value,
+            index: setup.Index,
+            data: data.to_vec(),
+        }
+    }
+}
+
+fn copy_into(buffer: &mut [u8], data: &[u8]) -> u32 {
+    let len = buffer.len().min(data.len());
+    buffer[..len].copy_from_slice(&data[..len]);
+    len as u32
+}
+
+fn string_descriptor(data: &[u8]) -> Vec<u8> {
+    let mut desc = vec![(data.len() + 2) as u8, DESCRIPTOR_TYPE_STRING];
+    desc.extend_from_slice(data);
+    desc
+}
+
+fn matches_pattern(info: &KLST_DEVINFO, pattern: &KLST_PATTERN_MATCH) -> bool {
+    [
+        (&pattern.DeviceID, &info.DeviceID),
+        (&pattern.DeviceInterfaceGUID, &info.DeviceInterfaceGUID),
+        (&pattern.ClassGUID, &info.ClassGUID),
+    ]
+    .into_iter()
+    .all(|(pattern, value)| {
+        let pattern = c_str(pattern);
+        pattern.is_empty() || wildcard_match(pattern, c_str(value))
+    })
+}
+
+/// Matches `value` against a case-insensitive pattern in which `*` matches any run of bytes and
+/// `?` any single byte.
+fn wildcard_match(pattern: &[u8], value: &[u8]) -> bool {
+    match (pattern.split_first(), value.split_first()) {
+        (None, _) => value.is_empty(),
+        (Some((b'*', rest)), _) => {
+            (0..=value.len()).any(|skip| wildcard_match(rest, &value[skip..]))
+        }
+        (Some(_), None) => false,
+        (Some((&p, rest)), Some((&v, value))) => {
+            (p == b'?' || p.eq_ignore_ascii_case(&v)) && wildcard_match(rest, value)
+        }
+    }
+}
+
+fn set_string(dst: &mut [CHAR], src: &str) {
+    let len = src.len().min(dst.len() - 1);
+    for (d, s) in dst.iter_mut().zip(&src.as_bytes()[..len]) {
+        *d = *s as CHAR;
+    }
+    dst[len..].fill(0);
+}
+
+/// Helpers for tests of the modules built on top of the backend.
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{DeviceHandle, DeviceList};
+
+    /// Plugs `device` into a backend of its own and opens it.
+    pub(crate) fn open(device: &SimDevice) -> DeviceHandle {
+        let backend = SimBackend::new();
+        backend.add_device(device);
+        let list = DeviceList::with_backend(Arc::new(backend)).unwrap();
+        let device = list.iter().next().unwrap();
+        device.open().unwrap()
+    }
+}
@@ -0,0 +1,223 @@
+use crate::config_descriptor::{header, Descriptors};
+use crate::error::{Error, ERROR_INVALID_DATA};
+use crate::fields::{DESCRIPTOR_TYPE_BOS, DESCRIPTOR_TYPE_DEVICE_CAPABILITY};
+
+pub(crate) const BOS_DESCRIPTOR_LEN: usize = 5;
+const DEVICE_CAPABILITY_LEN: usize = 3;
+
+const CAPABILITY_USB_2_0_EXTENSION: u8 = 0x02;
+const CAPABILITY_SUPERSPEED_USB: u8 = 0x03;
+const CAPABILITY_CONTAINER_ID: u8 = 0x04;
+
+/// A Binary device Object Store, describing device-level capabilities.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BosDescriptor {
+    raw: [u8; BOS_DESCRIPTOR_LEN],
+    capabilities: Vec<BosCapability>,
+}
+
+/// A device capability from a [`BosDescriptor`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BosCapability {
+    /// USB 2.0 Extension, describing Link Power Management support.
+    Usb2Extension(Usb2Extension),
+    /// SuperSpeed USB device capability.
+    SuperSpeed(SuperSpeedCapability),
+    /// A 128-bit ID that is unique to the device instance across all its operating modes.
+    ContainerId([u8; 16]),
+    /// A capability this crate does not decode, with the bytes following `bDevCapabilityType`.
+    Other { capability_type: u8, data: Vec<u8> },
+}
+
+/// The USB 2.0 Extension device capability.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Usb2Extension {
+    attributes: u32,
+}
+
+/// The SuperSpeed USB device capability.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SuperSpeedCapability {
+    raw: [u8; 10],
+}
+
+impl BosDescriptor {
+    /// Parses a BOS descriptor and its device capabilities, as returned by GET_DESCRIPTOR(BOS).
+    pub fn from_bytes(data: &[u8]) -> crate::Result<Self> {
+        let raw = header(data, DESCRIPTOR_TYPE_BOS, BOS_DESCRIPTOR_LEN)?;
+        let total = u16::from_le_bytes([raw[2], raw[3]]) as usize;
+        if total < raw[0] as usize || total > data.len() {
+            return Err(Error::from_code(ERROR_INVALID_DATA));
+        }
+
+        let capabilities = Descriptors::new(&data[raw[0] as usize..total])
+            .filter(|desc| !matches!(desc, Ok(d) if d[1] != DESCRIPTOR_TYPE_DEVICE_CAPABILITY))
+            .map(|desc| BosCapability::from_bytes(desc?))
+            .collect::<crate::Result<_>>()?;
+
+        Ok(Self { raw, capabilities })
+    }
+
+    /// Returns the total length in bytes of the BOS descriptor and its capabilities.
+    pub fn total_length(&self) -> u16 {
+        u16::from_le_bytes([self.raw[2], self.raw[3]])
+    }
+
+    /// Returns the number of device capabilities the device reports.
+    pub fn num_device_caps(&self) -> u8 {
+        self.raw[4]
+    }
+
+    /// Returns an iterator over the device capabilities.
+    pub fn capabilities(&self) -> impl Iterator<Item = &BosCapability> {
+        self.capabilities.iter()
+    }
+}
+
+impl BosCapability {
+    fn from_bytes(data: &[u8]) -> crate::Result<Self> {
+        let raw: [u8; DEVICE_CAPABILITY_LEN] = header(
+            data,
+            DESCRIPTOR_TYPE_DEVICE_CAPABILITY,
+            DEVICE_CAPABILITY_LEN,
+        )?;
+        let body = &data[DEVICE_CAPABILITY_LEN..];
+
+        let capability = match raw[2] {
+            CAPABILITY_USB_2_0_EXTENSION if body.len() >= 4 => {
+                BosCapability::Usb2Extension(Usb2Extension {
+                    attributes: u32::from_le_bytes([body[0], body[1], body[2], body[3]]),
+                })
+            }
+            CAPABILITY_SUPERSPEED_USB if data.len() >= 10 => {
+                let mut raw = [0; 10];
+                raw.copy_from_slice(&data[..10]);
+                BosCapability::SuperSpeed(SuperSpeedCapability { raw })
+            }
+            CAPABILITY_CONTAINER_ID if body.len() >= 17 => {
+                let mut id = [0; 16];
+                id.copy_from_slice(&body[1..17]);
+                BosCapability::ContainerId(id)
+            }
+            CAPABILITY_USB_2_0_EXTENSION | CAPABILITY_SUPERSPEED_USB | CAPABILITY_CONTAINER_ID => {
+                return Err(Error::from_code(ERROR_INVALID_DATA));
+            }
+            capability_type => BosCapability::Other {
+                capability_type,
+                data: body.to_vec(),
+            },
+        };
+        Ok(capability)
+    }
+}
+
+impl Usb2Extension {
+    /// Returns the raw `bmAttributes` field.
+    pub fn attributes(&self) -> u32 {
+        self.attributes
+    }
+
+    /// Indicates if the device supports the Link Power Management protocol.
+    pub fn lpm_supported(&self) -> bool {
+        self.attributes & 0x02 != 0
+    }
+}
+
+impl SuperSpeedCapability {
+    /// Returns the raw `bmAttributes` field.
+    pub fn attributes(&self) -> u8 {
+        self.raw[3]
+    }
+
+    /// Indicates if the device can generate Latency Tolerance Messages.
+    pub fn ltm_capable(&self) -> bool {
+        self.raw[3] & 0x02 != 0
+    }
+
+    /// Returns the `wSpeedsSupported` bitmap: bit 0 low speed, 1 full speed, 2 high speed and
+    /// 3 SuperSpeed.
+    pub fn speeds_supported(&self) -> u16 {
+        u16::from_le_bytes([self.raw[4], self.raw[5]])
+    }
+
+    /// Returns the lowest speed at which all functionality of the device is available.
+    pub fn functionality_support(&self) -> u8 {
+        self.raw[6]
+    }
+
+    /// Returns the U1 device exit latency, in microseconds.
+    pub fn u1_exit_latency(&self) -> u8 {
+        self.raw[7]
+    }
+
+    /// Returns the U2 device exit latency, in microseconds.
+    pub fn u2_exit_latency(&self) -> u16 {
+        u16::from_le_bytes([self.raw[8], self.raw[9]])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Captured from a USB 3.0 flash drive.
+    const BOS: [u8; 42] = [
+        0x05, 0x0F, 0x2A, 0x00, 0x03, // BOS
+        0x07, 0x10, 0x02, 0x02, 0x00, 0x00, 0x00, // USB 2.0 extension
+        0x0A, 0x10, 0x03, 0x00, 0x0E, 0x00, 0x01, 0x0A, 0xFF, 0x07, // SuperSpeed
+        0x14, 0x10, 0x04, 0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A, 0x0B,
+        0x0C, 0x0D, 0x0E, 0x0F, 0x10, // container ID
+    ];
+
+    #[test]
+    fn parses_capabilities() {
+        let bos = BosDescriptor::from_bytes(&BOS).unwrap();
+        assert_eq!(bos.total_length(), 42);
+        assert_eq!(bos.num_device_caps(), 3);
+
+        let caps: Vec<_> = bos.capabilities().collect();
+        assert_eq!(caps.len(), 3);
+
+        match caps[0] {
+            BosCapability::Usb2Extension(ext) => assert!(ext.lpm_supported()),
+            other => panic!("unexpected capability {other:?}"),
+        }
+        match caps[1] {
+            BosCapability::SuperSpeed(ss) => {
+                assert!(!ss.ltm_capable());
+                assert_eq!(ss.speeds_supported(), 0x0E);
+                assert_eq!(ss.functionality_support(), 1);
+                assert_eq!(ss.u1_exit_latency(), 0x0A);
+                assert_eq!(ss.u2_exit_latency(), 0x07FF);
+            }
+            other => panic!("unexpected capability {other:?}"),
+        }
+        match caps[2] {
+            BosCapability::ContainerId(id) => assert_eq!(id[0], 0x01),
+            other => panic!("unexpected capability {other:?}"),
+        }
+    }
+
+    #[test]
+    fn keeps_unknown_capabilities() {
+        let bos = [0x05, 0x0F, 0x0A, 0x00, 0x01, 0x05, 0x10, 0x0A, 0xAB, 0xCD];
+        let bos = BosDescriptor::from_bytes(&bos).unwrap();
+        assert_eq!(
+            bos.capabilities().next(),
+            Some(&BosCapability::Other {
+                capability_type: 0x0A,
+                data: vec![0xAB, 0xCD],
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_bos_descriptor() {
+        assert!(BosDescriptor::from_bytes(&BOS[..20]).is_err());
+
+        let mut short_container_id = BOS;
+        short_container_id[22] = 0x10;
+        short_container_id[2] = 0x26;
+        assert!(BosDescriptor::from_bytes(&short_container_id[..38]).is_err());
+    }
+}
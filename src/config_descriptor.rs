@@ -0,0 +1,729 @@
+use std::fmt;
+
+#[cfg(any(test, feature = "sim"))]
+use libusbk_sys::_USB_SUPERSPEED_ENDPOINT_COMPANION_DESCRIPTOR__bindgen_ty_1;
+use libusbk_sys::{_USB_SUPERSPEED_ENDPOINT_COMPANION_DESCRIPTOR, USB_INTERFACE_DESCRIPTOR};
+
+use crate::device_descriptor::string_index;
+use crate::error::{Error, ERROR_INVALID_DATA};
+use crate::fields::{
+    Direction, SyncType, TransferType, UsageType, DESCRIPTOR_TYPE_CONFIG, DESCRIPTOR_TYPE_ENDPOINT,
+    DESCRIPTOR_TYPE_INTERFACE, DESCRIPTOR_TYPE_SS_ENDPOINT_COMPANION, ENDPOINT_DIR_MASK,
+    ENDPOINT_IN,
+};
+
+pub(crate) const CONFIG_DESCRIPTOR_LEN: usize = 9;
+const INTERFACE_DESCRIPTOR_LEN: usize = 9;
+const ENDPOINT_DESCRIPTOR_LEN: usize = 7;
+const AUDIO_ENDPOINT_DESCRIPTOR_LEN: usize = 9;
+const SS_ENDPOINT_COMPANION_LEN: usize = 6;
+
+/// Describes a configuration.
+#[derive(Clone, PartialEq, Eq)]
+pub struct ConfigDescriptor {
+    raw: [u8; CONFIG_DESCRIPTOR_LEN],
+    interfaces: Vec<Interface>,
+    extra: Vec<u8>,
+}
+
+/// A device interface.
+///
+/// An interface can have several descriptors, each describing an alternate setting.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Interface {
+    number: u8,
+    descriptors: Vec<InterfaceDescriptor>,
+}
+
+/// Describes an alternate setting for an interface.
+#[derive(Clone, PartialEq, Eq)]
+pub struct InterfaceDescriptor {
+    raw: [u8; INTERFACE_DESCRIPTOR_LEN],
+    endpoints: Vec<EndpointDescriptor>,
+    extra: Vec<u8>,
+}
+
+/// Describes an endpoint.
+#[derive(Clone, PartialEq, Eq)]
+pub struct EndpointDescriptor {
+    raw: [u8; AUDIO_ENDPOINT_DESCRIPTOR_LEN],
+    companion: Option<SuperSpeedEndpointCompanion>,
+    extra: Vec<u8>,
+}
+
+/// Describes the SuperSpeed capabilities of an endpoint.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct SuperSpeedEndpointCompanion {
+    raw: [u8; SS_ENDPOINT_COMPANION_LEN],
+}
+
+impl ConfigDescriptor {
+    /// Parses a configuration descriptor and everything following it, as returned by
+    /// GET_DESCRIPTOR(CONFIGURATION).
+    ///
+    /// Class-specific descriptors are attached to the endpoint they follow, or to the
+    /// interface or configuration if no endpoint precedes them.
+    pub fn from_bytes(data: &[u8]) -> crate::Result<Self> {
+        let raw = header(data, DESCRIPTOR_TYPE_CONFIG, CONFIG_DESCRIPTOR_LEN)?;
+        let total = u16::from_le_bytes([raw[2], raw[3]]) as usize;
+        if total < raw[0] as usize || total > data.len() {
+            return Err(Error::from_code(ERROR_INVALID_DATA));
+        }
+
+        let mut config = Self {
+            raw,
+            interfaces: Vec::new(),
+            extra: Vec::new(),
+        };
+
+        for desc in Descriptors::new(&data[raw[0] as usize..total]) {
+            let desc = desc?;
+            match desc[1] {
+                DESCRIPTOR_TYPE_INTERFACE => {
+                    let interface = InterfaceDescriptor {
+                        raw: header(desc, DESCRIPTOR_TYPE_INTERFACE, INTERFACE_DESCRIPTOR_LEN)?,
+                        endpoints: Vec::new(),
+                        extra: Vec::new(),
+                    };
+                    let number = interface.interface_number();
+                    match config.interfaces.iter_mut().find(|i| i.number == number) {
+                        Some(i) => i.descriptors.push(interface),
+                        None => config.interfaces.push(Interface {
+                            number,
+                            descriptors: vec![interface],
+                        }),
+                    }
+                }
+                DESCRIPTOR_TYPE_ENDPOINT => {
+                    let endpoint = EndpointDescriptor::from_bytes(desc)?;
+                    config
+                        .current_interface()
+                        .ok_or(Error::from_code(ERROR_INVALID_DATA))?
+                        .endpoints
+                        .push(endpoint);
+                }
+                DESCRIPTOR_TYPE_SS_ENDPOINT_COMPANION if config.current_endpoint().is_some() => {
+                    let companion = SuperSpeedEndpointCompanion::from_bytes(desc)?;
+                    config.current_endpoint().unwrap().companion = Some(companion);
+                }
+                _ => {
+                    let extra = match config.current_interface() {
+                        Some(i) => match i.endpoints.last_mut() {
+                            Some(e) => &mut e.extra,
+                            None => &mut i.extra,
+                        },
+                        None => &mut config.extra,
+                    };
+                    extra.extend_from_slice(desc);
+                }
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// Returns the size of the descriptor in bytes
+    pub fn length(&self) -> u8 {
+        self.raw[0]
+    }
+
+    /// Returns the total length in bytes of data returned for this configuration: all
+    /// interfaces and endpoints
+    pub fn total_length(&self) -> u16 {
+        u16::from_le_bytes([self.raw[2], self.raw[3]])
+    }
+
+    /// Returns the descriptor type
+    pub fn descriptor_type(&self) -> u8 {
+        self.raw[1]
+    }
+
+    /// Returns the configuration number.
+    pub fn number(&self) -> u8 {
+        self.raw[5]
+    }
+
+    /// Returns the device's maximum power consumption (in milliamps) in this configuration.
+    pub fn max_power(&self) -> u16 {
+        u16::from(self.raw[8]) * 2
+    }
+
+    /// Indicates if the device is self-powered in this configuration.
+    pub fn self_powered(&self) -> bool {
+        self.raw[7] & 0x40 != 0
+    }
+
+    /// Indicates if the device has remote wakeup capability in this configuration.
+    pub fn remote_wakeup(&self) -> bool {
+        self.raw[7] & 0x20 != 0
+    }
+
+    /// Returns the index of the string descriptor that describes the configuration.
+    pub fn description_string_index(&self) -> Option<u8> {
+        string_index(self.raw[6])
+    }
+
+    /// Returns the number of interfaces for this configuration.
+    pub fn num_interfaces(&self) -> u8 {
+        self.raw[4]
+    }
+
+    /// Returns an iterator over the interfaces in this configuration.
+    pub fn interfaces(&self) -> impl Iterator<Item = &Interface> {
+        self.interfaces.iter()
+    }
+
+    /// Returns the unknown descriptors that follow the configuration descriptor.
+    pub fn extra(&self) -> &[u8] {
+        &self.extra
+    }
+
+    fn current_interface(&mut self) -> Option<&mut InterfaceDescriptor> {
+        self.interfaces
+            .last_mut()
+            .and_then(|i| i.descriptors.last_mut())
+    }
+
+    fn current_endpoint(&mut self) -> Option<&mut EndpointDescriptor> {
+        self.current_interface()
+            .and_then(|i| i.endpoints.last_mut())
+    }
+}
+
+impl fmt::Debug for ConfigDescriptor {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug = fmt.debug_struct("ConfigDescriptor");
+
+        debug.field("bLength", &self.length());
+        debug.field("bDescriptorType", &self.descriptor_type());
+        debug.field("wTotalLength", &self.total_length());
+        debug.field("bNumInterfaces", &self.num_interfaces());
+        debug.field("bConfigurationValue", &self.number());
+        debug.field("iConfiguration", &self.raw[6]);
+        debug.field("bmAttributes", &self.raw[7]);
+        debug.field("bMaxPower", &self.raw[8]);
+        debug.field("extra", &self.extra);
+        debug.field("interfaces", &self.interfaces);
+
+        debug.finish()
+    }
+}
+
+impl Interface {
+    /// Returns the interface's number.
+    pub fn number(&self) -> u8 {
+        self.number
+    }
+
+    /// Returns an iterator over the interface's alternate settings.
+    pub fn descriptors(&self) -> impl Iterator<Item = &InterfaceDescriptor> {
+        self.descriptors.iter()
+    }
+}
+
+impl InterfaceDescriptor {
+    /// Wraps a descriptor returned by `QueryInterfaceSettings`, which comes without endpoints.
+    pub(crate) fn from_native(desc: &USB_INTERFACE_DESCRIPTOR) -> Self {
+        Self {
+            raw: [
+                desc.bLength,
+                desc.bDescriptorType,
+                desc.bInterfaceNumber,
+                desc.bAlternateSetting,
+                desc.bNumEndpoints,
+                desc.bInterfaceClass,
+                desc.bInterfaceSubClass,
+                desc.bInterfaceProtocol,
+                desc.iInterface,
+            ],
+            endpoints: Vec::new(),
+            extra: Vec::new(),
+        }
+    }
+
+    #[cfg(any(test, feature = "sim"))]
+    pub(crate) fn to_native(&self) -> USB_INTERFACE_DESCRIPTOR {
+        USB_INTERFACE_DESCRIPTOR {
+            bLength: self.raw[0],
+            bDescriptorType: self.raw[1],
+            bInterfaceNumber: self.raw[2],
+            bAlternateSetting: self.raw[3],
+            bNumEndpoints: self.raw[4],
+            bInterfaceClass: self.raw[5],
+            bInterfaceSubClass: self.raw[6],
+            bInterfaceProtocol: self.raw[7],
+            iInterface: self.raw[8],
+        }
+    }
+
+    /// Returns the size of the descriptor in bytes
+    pub fn length(&self) -> u8 {
+        self.raw[0]
+    }
+
+    /// Returns the descriptor type
+    pub fn descriptor_type(&self) -> u8 {
+        self.raw[1]
+    }
+
+    /// Returns the interface's number.
+    pub fn interface_number(&self) -> u8 {
+        self.raw[2]
+    }
+
+    /// Returns the alternate setting number.
+    pub fn setting_number(&self) -> u8 {
+        self.raw[3]
+    }
+
+    /// Returns the interface's class code.
+    pub fn class_code(&self) -> u8 {
+        self.raw[5]
+    }
+
+    /// Returns the interface's sub class code.
+    pub fn sub_class_code(&self) -> u8 {
+        self.raw[6]
+    }
+
+    /// Returns the interface's protocol code.
+    pub fn protocol_code(&self) -> u8 {
+        self.raw[7]
+    }
+
+    /// Returns the index of the string descriptor that describes the interface.
+    pub fn description_string_index(&self) -> Option<u8> {
+        string_index(self.raw[8])
+    }
+
+    /// Returns the number of endpoints belonging to this interface.
+    pub fn num_endpoints(&self) -> u8 {
+        self.raw[4]
+    }
+
+    /// Returns an iterator over the interface's endpoint descriptors.
+    pub fn endpoint_descriptors(&self) -> impl Iterator<Item = &EndpointDescriptor> {
+        self.endpoints.iter()
+    }
+
+    /// Returns the unknown descriptors that follow the interface descriptor, such as
+    /// class-specific descriptors.
+    pub fn extra(&self) -> &[u8] {
+        &self.extra
+    }
+}
+
+impl fmt::Debug for InterfaceDescriptor {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug = fmt.debug_struct("InterfaceDescriptor");
+
+        debug.field("bLength", &self.length());
+        debug.field("bDescriptorType", &self.descriptor_type());
+        debug.field("bInterfaceNumber", &self.interface_number());
+        debug.field("bAlternateSetting", &self.setting_number());
+        debug.field("bNumEndpoints", &self.num_endpoints());
+        debug.field("bInterfaceClass", &self.class_code());
+        debug.field("bInterfaceSubClass", &self.sub_class_code());
+        debug.field("bInterfaceProtocol", &self.protocol_code());
+        debug.field("iInterface", &self.raw[8]);
+        debug.field("extra", &self.extra);
+        debug.field("endpoints", &self.endpoints);
+
+        debug.finish()
+    }
+}
+
+impl EndpointDescriptor {
+    fn from_bytes(data: &[u8]) -> crate::Result<Self> {
+        header::<ENDPOINT_DESCRIPTOR_LEN>(data, DESCRIPTOR_TYPE_ENDPOINT, ENDPOINT_DESCRIPTOR_LEN)?;
+
+        let mut raw = [0; AUDIO_ENDPOINT_DESCRIPTOR_LEN];
+        let len = data.len().min(AUDIO_ENDPOINT_DESCRIPTOR_LEN);
+        raw[..len].copy_from_slice(&data[..len]);
+        Ok(Self {
+            raw,
+            companion: None,
+            extra: Vec::new(),
+        })
+    }
+
+    /// Returns the size of the descriptor in bytes
+    pub fn length(&self) -> u8 {
+        self.raw[0]
+    }
+
+    /// Returns the descriptor type
+    pub fn descriptor_type(&self) -> u8 {
+        self.raw[1]
+    }
+
+    /// Returns the endpoint's address.
+    pub fn address(&self) -> u8 {
+        self.raw[2]
+    }
+
+    /// Returns the endpoint number.
+    pub fn number(&self) -> u8 {
+        self.raw[2] & 0x0F
+    }
+
+    /// Returns the endpoint's direction.
+    pub fn direction(&self) -> Direction {
+        match self.raw[2] & ENDPOINT_DIR_MASK {
+            ENDPOINT_IN => Direction::In,
+            _ => Direction::Out,
+        }
+    }
+
+    /// Returns the endpoint's transfer type.
+    pub fn transfer_type(&self) -> TransferType {
+        match self.raw[3] & 0x03 {
+            0 => TransferType::Control,
+            1 => TransferType::Isochronous,
+            2 => TransferType::Bulk,
+            _ => TransferType::Interrupt,
+        }
+    }
+
+    /// Returns the endpoint's synchronisation mode.
+    ///
+    /// The return value of this method is only valid for isochronous endpoints.
+    pub fn sync_type(&self) -> SyncType {
+        match (self.raw[3] >> 2) & 0x03 {
+            0 => SyncType::NoSync,
+            1 => SyncType::Asynchronous,
+            2 => SyncType::Adaptive,
+            _ => SyncType::Synchronous,
+        }
+    }
+
+    /// Returns the endpoint's usage type.
+    ///
+    /// The return value of this method is only valid for isochronous endpoints.
+    pub fn usage_type(&self) -> UsageType {
+        match (self.raw[3] >> 4) & 0x03 {
+            0 => UsageType::Data,
+            1 => UsageType::Feedback,
+            2 => UsageType::FeedbackData,
+            _ => UsageType::Reserved,
+        }
+    }
+
+    /// Returns the endpoint's maximum packet size.
+    pub fn max_packet_size(&self) -> u16 {
+        u16::from_le_bytes([self.raw[4], self.raw[5]])
+    }
+
+    /// Returns the endpoint's polling interval.
+    pub fn interval(&self) -> u8 {
+        self.raw[6]
+    }
+
+    /// For audio devices only: return the rate at which synchronization feedback is provided.
+    pub fn refresh(&self) -> Option<u8> {
+        self.audio_field(7)
+    }
+
+    /// For audio devices only: return the address if the synch endpoint.
+    pub fn synch_address(&self) -> Option<u8> {
+        self.audio_field(8)
+    }
+
+    /// Returns the SuperSpeed endpoint companion descriptor, if the endpoint has one.
+    pub fn superspeed_companion(&self) -> Option<&SuperSpeedEndpointCompanion> {
+        self.companion.as_ref()
+    }
+
+    /// Returns the unknown descriptors that follow the endpoint descriptor, such as
+    /// class-specific descriptors.
+    pub fn extra(&self) -> &[u8] {
+        &self.extra
+    }
+
+    fn audio_field(&self, offset: usize) -> Option<u8> {
+        match self.length() as usize {
+            AUDIO_ENDPOINT_DESCRIPTOR_LEN.. => Some(self.raw[offset]),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Debug for EndpointDescriptor {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug = fmt.debug_struct("EndpointDescriptor");
+
+        debug.field("bLength", &self.length());
+        debug.field("bDescriptorType", &self.descriptor_type());
+        debug.field("bEndpointAddress", &self.address());
+        debug.field("bmAttributes", &self.raw[3]);
+        debug.field("wMaxPacketSize", &self.max_packet_size());
+        debug.field("bInterval", &self.interval());
+        debug.field("companion", &self.companion);
+        debug.field("extra", &self.extra);
+
+        debug.finish()
+    }
+}
+
+impl SuperSpeedEndpointCompanion {
+    /// Parses a SuperSpeed endpoint companion descriptor.
+    pub fn from_bytes(data: &[u8]) -> crate::Result<Self> {
+        Ok(Self {
+            raw: header(
+                data,
+                DESCRIPTOR_TYPE_SS_ENDPOINT_COMPANION,
+                SS_ENDPOINT_COMPANION_LEN,
+            )?,
+        })
+    }
+
+    /// Wraps a descriptor returned by `GetSuperSpeedPipeCompanionDescriptor`.
+    pub(crate) fn from_native(desc: &_USB_SUPERSPEED_ENDPOINT_COMPANION_DESCRIPTOR) -> Self {
+        let [bytes_lo, bytes_hi] = desc.wBytesPerInterval.to_le_bytes();
+        Self {
+            raw: [
+                desc.bLength,
+                desc.bDescriptorType,
+                desc.bMaxBurst,
+                // SAFETY: every variant of the union is a view of the same byte.
+                unsafe { desc.bmAttributes.AsUchar },
+                bytes_lo,
+                bytes_hi,
+            ],
+        }
+    }
+
+    #[cfg(any(test, feature = "sim"))]
+    pub(crate) fn to_native(self) -> _USB_SUPERSPEED_ENDPOINT_COMPANION_DESCRIPTOR {
+        _USB_SUPERSPEED_ENDPOINT_COMPANION_DESCRIPTOR {
+            bLength: self.raw[0],
+            bDescriptorType: self.raw[1],
+            bMaxBurst: self.raw[2],
+            bmAttributes: _USB_SUPERSPEED_ENDPOINT_COMPANION_DESCRIPTOR__bindgen_ty_1 {
+                AsUchar: self.raw[3],
+            },
+            wBytesPerInterval: self.bytes_per_interval(),
+        }
+    }
+
+    /// Returns the maximum number of packets the endpoint can send or receive as part of a
+    /// burst, minus one.
+    pub fn max_burst(&self) -> u8 {
+        self.raw[2]
+    }
+
+    /// Returns the raw `bmAttributes` field.
+    pub fn attributes(&self) -> u8 {
+        self.raw[3]
+    }
+
+    /// For bulk endpoints: returns the base-2 logarithm of the number of streams supported.
+    pub fn max_streams(&self) -> u8 {
+        self.raw[3] & 0x1F
+    }
+
+    /// For isochronous endpoints: returns the maximum number of packets within a service
+    /// interval, in multiples of `max_burst() + 1`, minus one.
+    pub fn mult(&self) -> u8 {
+        self.raw[3] & 0x03
+    }
+
+    /// Returns the total number of bytes the endpoint transfers every service interval.
+    ///
+    /// Only valid for periodic endpoints.
+    pub fn bytes_per_interval(&self) -> u16 {
+        u16::from_le_bytes([self.raw[4], self.raw[5]])
+    }
+}
+
+impl fmt::Debug for SuperSpeedEndpointCompanion {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug = fmt.debug_struct("SuperSpeedEndpointCompanion");
+
+        debug.field("bMaxBurst", &self.max_burst());
+        debug.field("bmAttributes", &self.attributes());
+        debug.field("wBytesPerInterval", &self.bytes_per_interval());
+
+        debug.finish()
+    }
+}
+
+/// Checks the `bLength` and `bDescriptorType` of a descriptor and copies its first `N` bytes.
+pub(crate) fn header<const N: usize>(
+    data: &[u8],
+    descriptor_type: u8,
+    min_len: usize,
+) -> crate::Result<[u8; N]> {
+    if data.len() < min_len
+        || (data[0] as usize) < min_len
+        || (data[0] as usize) > data.len()
+        || data[1] != descriptor_type
+    {
+        return Err(Error::from_code(ERROR_INVALID_DATA));
+    }
+
+    let mut raw = [0; N];
+    raw.copy_from_slice(&data[..N]);
+    Ok(raw)
+}
+
+/// Splits a buffer of concatenated descriptors on their `bLength` fields.
+pub(crate) struct Descriptors<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Descriptors<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+}
+
+impl<'a> Iterator for Descriptors<'a> {
+    type Item = crate::Result<&'a [u8]>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.data.is_empty() {
+            return None;
+        }
+
+        let len = self.data[0] as usize;
+        if len < 2 || len > self.data.len() {
+            self.data = &[];
+            return Some(Err(Error::from_code(ERROR_INVALID_DATA)));
+        }
+
+        let (desc, rest) = self.data.split_at(len);
+        self.data = rest;
+        Some(Ok(desc))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Captured from a CDC-ACM serial adapter: a communications interface with a class-specific
+    // header and an interrupt endpoint, and a data interface with two bulk endpoints.
+    const CDC_ACM: [u8; 67] = [
+        0x09, 0x02, 0x43, 0x00, 0x02, 0x01, 0x00, 0xC0, 0x32, // config
+        0x09, 0x04, 0x00, 0x00, 0x01, 0x02, 0x02, 0x01, 0x00, // interface 0
+        0x05, 0x24, 0x00, 0x10, 0x01, // CDC header
+        0x05, 0x24, 0x01, 0x00, 0x01, // CDC call management
+        0x04, 0x24, 0x02, 0x02, // CDC ACM
+        0x05, 0x24, 0x06, 0x00, 0x01, // CDC union
+        0x07, 0x05, 0x82, 0x03, 0x08, 0x00, 0xFF, // interrupt in
+        0x09, 0x04, 0x01, 0x00, 0x02, 0x0A, 0x00, 0x00, 0x00, // interface 1
+        0x07, 0x05, 0x01, 0x02, 0x40, 0x00, 0x00, // bulk out
+        0x07, 0x05, 0x81, 0x02, 0x40, 0x00, 0x00, // bulk in
+    ];
+
+    // A SuperSpeed bulk endpoint pair followed by an alternate setting with an isochronous
+    // endpoint, each with a companion descriptor.
+    const SUPERSPEED: [u8; 66] = [
+        0x09, 0x02, 0x42, 0x00, 0x01, 0x01, 0x04, 0xA0, 0x48, // config
+        0x09, 0x04, 0x00, 0x00, 0x02, 0xFF, 0x00, 0x00, 0x00, // interface 0, alt 0
+        0x07, 0x05, 0x81, 0x02, 0x00, 0x04, 0x00, // bulk in
+        0x06, 0x30, 0x0F, 0x04, 0x00, 0x00, // companion
+        0x07, 0x05, 0x02, 0x02, 0x00, 0x04, 0x00, // bulk out
+        0x06, 0x30, 0x0F, 0x00, 0x00, 0x00, // companion
+        0x09, 0x04, 0x00, 0x01, 0x01, 0xFF, 0x00, 0x00, 0x00, // interface 0, alt 1
+        0x07, 0x05, 0x83, 0x05, 0x00, 0x04, 0x01, // iso in
+        0x06, 0x30, 0x03, 0x02, 0x00, 0x30, // companion
+    ];
+
+    #[test]
+    fn parses_interfaces_and_endpoints() {
+        let config = ConfigDescriptor::from_bytes(&CDC_ACM).unwrap();
+        assert_eq!(config.number(), 1);
+        assert_eq!(config.total_length(), 67);
+        assert_eq!(config.max_power(), 100);
+        assert!(config.self_powered());
+        assert!(!config.remote_wakeup());
+        assert_eq!(config.description_string_index(), None);
+        assert!(config.extra().is_empty());
+
+        let interfaces: Vec<_> = config.interfaces().collect();
+        assert_eq!(interfaces.len(), 2);
+
+        let comm = interfaces[0].descriptors().next().unwrap();
+        assert_eq!(comm.class_code(), 0x02);
+        assert_eq!(comm.extra().len(), 19);
+        let endpoints: Vec<_> = comm.endpoint_descriptors().collect();
+        assert_eq!(endpoints.len(), 1);
+        assert_eq!(endpoints[0].address(), 0x82);
+        assert_eq!(endpoints[0].transfer_type(), TransferType::Interrupt);
+        assert_eq!(endpoints[0].interval(), 0xFF);
+        assert_eq!(endpoints[0].refresh(), None);
+
+        let data = interfaces[1].descriptors().next().unwrap();
+        assert_eq!(data.interface_number(), 1);
+        let endpoints: Vec<_> = data.endpoint_descriptors().collect();
+        assert_eq!(endpoints[0].direction(), Direction::Out);
+        assert_eq!(endpoints[1].direction(), Direction::In);
+        assert_eq!(endpoints[1].number(), 1);
+        assert_eq!(endpoints[1].max_packet_size(), 64);
+        assert!(endpoints[1].superspeed_companion().is_none());
+    }
+
+    #[test]
+    fn parses_alternate_settings_and_companions() {
+        let config = ConfigDescriptor::from_bytes(&SUPERSPEED).unwrap();
+        assert_eq!(config.description_string_index(), Some(4));
+        assert!(config.remote_wakeup());
+
+        let interface = config.interfaces().next().unwrap();
+        let settings: Vec<_> = interface.descriptors().collect();
+        assert_eq!(settings.len(), 2);
+
+        let bulk = settings[0].endpoint_descriptors().next().unwrap();
+        let companion = bulk.superspeed_companion().unwrap();
+        assert_eq!(bulk.max_packet_size(), 1024);
+        assert_eq!(companion.max_burst(), 15);
+        assert_eq!(companion.max_streams(), 4);
+
+        let iso = settings[1].endpoint_descriptors().next().unwrap();
+        let companion = iso.superspeed_companion().unwrap();
+        assert_eq!(settings[1].setting_number(), 1);
+        assert_eq!(iso.transfer_type(), TransferType::Isochronous);
+        assert_eq!(iso.sync_type(), SyncType::Asynchronous);
+        assert_eq!(iso.usage_type(), UsageType::Data);
+        assert_eq!(companion.mult(), 2);
+        assert_eq!(companion.bytes_per_interval(), 0x3000);
+    }
+
+    #[test]
+    fn numbers_endpoints_above_seven() {
+        let config = ConfigDescriptor::from_bytes(&[
+            0x09, 0x02, 0x20, 0x00, 0x01, 0x01, 0x00, 0x80, 0x32, // config
+            0x09, 0x04, 0x00, 0x00, 0x02, 0xFF, 0x00, 0x00, 0x00, // interface
+            0x07, 0x05, 0x89, 0x02, 0x00, 0x02, 0x00, // bulk in 9
+            0x07, 0x05, 0x0F, 0x02, 0x00, 0x02, 0x00, // bulk out 15
+        ])
+        .unwrap();
+        let setting = config
+            .interfaces()
+            .next()
+            .unwrap()
+            .descriptors()
+            .next()
+            .unwrap();
+        let endpoints: Vec<_> = setting.endpoint_descriptors().collect();
+        assert_eq!(endpoints[0].number(), 9);
+        assert_eq!(endpoints[0].direction(), Direction::In);
+        assert_eq!(endpoints[1].number(), 15);
+        assert_eq!(endpoints[1].direction(), Direction::Out);
+    }
+
+    #[test]
+    fn rejects_malformed_config_descriptor() {
+        assert!(ConfigDescriptor::from_bytes(&CDC_ACM[..30]).is_err());
+
+        let mut bad_length = CDC_ACM;
+        bad_length[18] = 0xFF;
+        assert!(ConfigDescriptor::from_bytes(&bad_length).is_err());
+
+        let mut orphan_endpoint = CDC_ACM;
+        orphan_endpoint[10] = 0x0B;
+        assert!(ConfigDescriptor::from_bytes(&orphan_endpoint).is_err());
+    }
+}
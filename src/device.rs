@@ -1,138 +1,201 @@
 use core::fmt;
-use std::collections::HashSet;
-use std::ffi::c_void;
-use std::mem;
-use std::ptr::NonNull;
+use std::borrow::Cow;
+use std::sync::Arc;
 
-use libusbk_sys::{LibK_LoadDriverAPI, KLST_DEVINFO, KUSB_DRIVER_API};
+use libusbk_sys::{CHAR, KLST_DEVINFO};
 
-use crate::error::try_unsafe;
-use crate::DeviceHandle;
+use crate::backend::{Backend, DeviceListBackend};
+use crate::error::{Error, ERROR_DEVICE_NOT_CONNECTED, ERROR_NO_UNICODE_TRANSLATION};
+use crate::{DeviceHandle, DeviceList, InstanceId};
 
-const STRING_LEN: usize = 256;
+pub(crate) const STRING_LEN: usize = 256;
 
-// wrapper type
-// TODO not pub
-#[repr(transparent)]
+/// A device found in a [`DeviceList`].
+///
+/// The string properties come from Windows device setup and are stored by `libusbK` as
+/// NUL-terminated strings in the system ANSI code page. The plain getters return text up to the
+/// first NUL; when it is not valid UTF-8 it is converted from the ANSI code page on Windows and
+/// decoded lossily elsewhere. The `try_*` getters return an error for such text instead.
+///
+/// A device keeps the list it was found in alive, so it stays valid after the [`DeviceList`]
+/// is dropped.
+///
+/// [`DeviceList`]: crate::DeviceList
 #[derive(Clone)]
-pub struct Device(pub(crate) *mut KLST_DEVINFO);
+pub struct Device {
+    info: DeviceInfo,
+    backend: Arc<dyn Backend>,
+}
+
+#[derive(Clone)]
+enum DeviceInfo {
+    /// Info owned by a device list, which is kept alive for as long as the device.
+    List {
+        info: *const KLST_DEVINFO,
+        _list: Arc<dyn DeviceListBackend>,
+    },
+    /// A copy of info that was only valid temporarily, e.g. during a hot-plug notification.
+    /// The copy is owned and stays valid, but it is not an element of a device list.
+    Snapshot(Arc<KLST_DEVINFO>),
+}
 
+// SAFETY: the info is only read, and the list it points into is `Send + Sync` and kept alive by
+// the device.
 unsafe impl Send for Device {}
 unsafe impl Sync for Device {}
 
 impl Device {
-    pub fn open(&self) -> crate::Result<DeviceHandle> {
-        let mut handle = mem::MaybeUninit::<*mut c_void>::uninit();
+    /// Creates a device for `info`, which must point into `list`.
+    pub(crate) fn from_list(
+        list: Arc<dyn DeviceListBackend>,
+        info: *const KLST_DEVINFO,
+        backend: Arc<dyn Backend>,
+    ) -> Self {
+        Self {
+            info: DeviceInfo::List { info, _list: list },
+            backend,
+        }
+    }
 
-        let mut dev: KUSB_DRIVER_API = unsafe { std::mem::zeroed() };
-        // TODO: this may not be desirable always
-        try_unsafe!(LibK_LoadDriverAPI(&mut dev, self.driver_id()));
+    /// Creates a device from a copy of `info`.
+    pub(crate) fn snapshot(info: &KLST_DEVINFO, backend: Arc<dyn Backend>) -> Self {
+        Self {
+            info: DeviceInfo::Snapshot(Arc::new(*info)),
+            backend,
+        }
+    }
 
-        try_unsafe!(dev.Init.unwrap()(handle.as_mut_ptr(), self.0));
+    /// Opens the device.
+    ///
+    /// Devices from hot-plug notifications are looked up in a new [`DeviceList`] by their
+    /// device path first, since `libusbK` only opens devices through the info of a list.
+    ///
+    /// [`DeviceList`]: crate::DeviceList
+    pub fn open(&self) -> crate::Result<DeviceHandle> {
+        match &self.info {
+            DeviceInfo::List { .. } => {
+                let inner = self.backend.open(self.inner())?;
+                Ok(DeviceHandle::new(inner, self.clone()))
+            }
+            DeviceInfo::Snapshot(_) => self.listed()?.open(),
+        }
+    }
+
+    /// Finds the connected device with the same device path in a new device list.
+    fn listed(&self) -> crate::Result<Device> {
+        DeviceList::with_backend(self.backend.clone())?
+            .iter()
+            .find(|device| device.inner().DevicePath == self.inner().DevicePath)
+            .ok_or(Error::from_code(ERROR_DEVICE_NOT_CONNECTED))
+    }
 
-        let ptr = unsafe { NonNull::new(handle.assume_init()).unwrap() };
-        Ok(DeviceHandle {
-            dev,
-            driver_id: self.driver_id(),
-            handle: Some(ptr),
-            claimed_interface: HashSet::new(),
-        })
+    /// The backend the device was found through.
+    pub(crate) fn backend(&self) -> &Arc<dyn Backend> {
+        &self.backend
     }
 
     pub fn driver_id(&self) -> i32 {
         self.inner().DriverID
     }
 
-    pub fn device_interface_guid(&self) -> &str {
-        let data = &self.inner().DeviceInterfaceGUID;
-        unsafe {
-            std::str::from_utf8(std::mem::transmute::<&[i8; STRING_LEN], &[u8; STRING_LEN]>(
-                data,
-            ))
-            .unwrap()
-        }
+    /// Returns the device interface GUID.
+    pub fn device_interface_guid(&self) -> Cow<'_, str> {
+        decode(c_str(&self.inner().DeviceInterfaceGUID))
     }
 
-    pub fn device_id(&self) -> &str {
-        let data = &self.inner().DeviceID;
-        unsafe {
-            std::str::from_utf8(std::mem::transmute::<&[i8; STRING_LEN], &[u8; STRING_LEN]>(
-                data,
-            ))
-            .unwrap()
-        }
+    /// Like [`Device::device_interface_guid`], but fails instead of substituting text that is not UTF-8.
+    pub fn try_device_interface_guid(&self) -> crate::Result<&str> {
+        try_decode(c_str(&self.inner().DeviceInterfaceGUID))
     }
 
-    pub fn class_guid(&self) -> &str {
-        let data = &self.inner().ClassGUID;
-        unsafe {
-            std::str::from_utf8(std::mem::transmute::<&[i8; STRING_LEN], &[u8; STRING_LEN]>(
-                data,
-            ))
-            .unwrap()
-        }
+    /// Returns the device instance ID, e.g. `USB\VID_04D8&PID_FA2E\LUSBW1`.
+    pub fn device_id(&self) -> Cow<'_, str> {
+        decode(c_str(&self.inner().DeviceID))
     }
 
-    pub fn manufacturer(&self) -> &str {
-        let data = &self.inner().Mfg;
-        unsafe {
-            std::str::from_utf8(std::mem::transmute::<&[i8; STRING_LEN], &[u8; STRING_LEN]>(
-                data,
-            ))
-            .unwrap()
-        }
+    /// Like [`Device::device_id`], but fails instead of substituting text that is not UTF-8.
+    pub fn try_device_id(&self) -> crate::Result<&str> {
+        try_decode(c_str(&self.inner().DeviceID))
     }
 
-    pub fn device_descriptor(&self) -> &str {
-        let data = &self.inner().DeviceDesc;
-        unsafe {
-            std::str::from_utf8(std::mem::transmute::<&[i8; STRING_LEN], &[u8; STRING_LEN]>(
-                data,
-            ))
-            .unwrap()
-        }
+    /// Returns the device setup class GUID.
+    pub fn class_guid(&self) -> Cow<'_, str> {
+        decode(c_str(&self.inner().ClassGUID))
     }
 
-    pub fn service(&self) -> &str {
-        let data = &self.inner().Service;
-        unsafe {
-            std::str::from_utf8(std::mem::transmute::<&[i8; STRING_LEN], &[u8; STRING_LEN]>(
-                data,
-            ))
-            .unwrap()
-        }
+    /// Like [`Device::class_guid`], but fails instead of substituting text that is not UTF-8.
+    pub fn try_class_guid(&self) -> crate::Result<&str> {
+        try_decode(c_str(&self.inner().ClassGUID))
     }
 
-    pub fn symbolic_link(&self) -> &str {
-        let data = &self.inner().SymbolicLink;
-        unsafe {
-            std::str::from_utf8(std::mem::transmute::<&[i8; STRING_LEN], &[u8; STRING_LEN]>(
-                data,
-            ))
-            .unwrap()
-        }
+    /// Returns the manufacturer name from the device's setup information.
+    pub fn manufacturer(&self) -> Cow<'_, str> {
+        decode(c_str(&self.inner().Mfg))
     }
 
-    pub fn device_path(&self) -> &str {
-        let data = &self.inner().DevicePath;
-        unsafe {
-            std::str::from_utf8(std::mem::transmute::<&[i8; STRING_LEN], &[u8; STRING_LEN]>(
-                data,
-            ))
-            .unwrap()
-        }
+    /// Like [`Device::manufacturer`], but fails instead of substituting text that is not UTF-8.
+    pub fn try_manufacturer(&self) -> crate::Result<&str> {
+        try_decode(c_str(&self.inner().Mfg))
+    }
+
+    /// Returns the device description from the device's setup information.
+    pub fn device_descriptor(&self) -> Cow<'_, str> {
+        decode(c_str(&self.inner().DeviceDesc))
+    }
+
+    /// Like [`Device::device_descriptor`], but fails instead of substituting text that is not UTF-8.
+    pub fn try_device_descriptor(&self) -> crate::Result<&str> {
+        try_decode(c_str(&self.inner().DeviceDesc))
+    }
+
+    /// Returns the name of the service (driver) the device is bound to.
+    pub fn service(&self) -> Cow<'_, str> {
+        decode(c_str(&self.inner().Service))
+    }
+
+    /// Like [`Device::service`], but fails instead of substituting text that is not UTF-8.
+    pub fn try_service(&self) -> crate::Result<&str> {
+        try_decode(c_str(&self.inner().Service))
+    }
+
+    /// Returns the symbolic link of the device interface.
+    pub fn symbolic_link(&self) -> Cow<'_, str> {
+        decode(c_str(&self.inner().SymbolicLink))
+    }
+
+    /// Like [`Device::symbolic_link`], but fails instead of substituting text that is not UTF-8.
+    pub fn try_symbolic_link(&self) -> crate::Result<&str> {
+        try_decode(c_str(&self.inner().SymbolicLink))
     }
 
+    /// Returns the device interface path used to open the device.
+    pub fn device_path(&self) -> Cow<'_, str> {
+        decode(c_str(&self.inner().DevicePath))
+    }
+
+    /// Like [`Device::device_path`], but fails instead of substituting text that is not UTF-8.
+    pub fn try_device_path(&self) -> crate::Result<&str> {
+        try_decode(c_str(&self.inner().DevicePath))
+    }
+
+    /// Returns the vendor ID, or 0 if the device ID does not contain one.
     pub fn vendor_id(&self) -> u16 {
-        let index = self.device_id().find("VID_").unwrap() + 4;
-        let sub_str = &self.device_id()[index..index + 4];
-        u16::from_str_radix(sub_str, 16).unwrap()
+        u16::try_from(self.inner().Common.Vid).unwrap_or_default()
     }
 
+    /// Returns the product ID, or 0 if the device ID does not contain one.
     pub fn product_id(&self) -> u16 {
-        let index = self.device_id().find("PID_").unwrap() + 4;
-        let sub_str = &self.device_id()[index..index + 4];
-        u16::from_str_radix(sub_str, 16).unwrap()
+        u16::try_from(self.inner().Common.Pid).unwrap_or_default()
+    }
+
+    /// Returns the interface number if the device is a function of a composite device.
+    pub fn interface_number(&self) -> Option<u8> {
+        u8::try_from(self.inner().Common.MI).ok()
+    }
+
+    /// Returns the parsed device instance ID.
+    pub fn instance_id(&self) -> crate::Result<InstanceId> {
+        self.try_device_id()?.parse()
     }
 
     pub fn lusb0_filter_index(&self) -> i32 {
@@ -140,24 +203,24 @@ impl Device {
     }
 
     pub fn connected(&self) -> bool {
-        match self.inner().Connected {
-            0 => false,
-            _ => true,
-        }
+        self.inner().Connected != 0
     }
 
-    pub fn serial_number(&self) -> &str {
-        let data = &self.inner().SerialNumber;
-        unsafe {
-            std::str::from_utf8(std::mem::transmute::<&[i8; STRING_LEN], &[u8; STRING_LEN]>(
-                data,
-            ))
-            .unwrap()
-        }
+    /// Returns the serial number, as parsed from the device instance ID by `libusbK`.
+    pub fn serial_number(&self) -> Cow<'_, str> {
+        decode(c_str(&self.inner().SerialNumber))
+    }
+
+    /// Like [`Device::serial_number`], but fails instead of substituting text that is not UTF-8.
+    pub fn try_serial_number(&self) -> crate::Result<&str> {
+        try_decode(c_str(&self.inner().SerialNumber))
     }
 
     fn inner(&self) -> &KLST_DEVINFO {
-        unsafe { &*self.0 }
+        match &self.info {
+            DeviceInfo::List { info, .. } => unsafe { &**info },
+            DeviceInfo::Snapshot(info) => info,
+        }
     }
 }
 
@@ -166,6 +229,7 @@ impl fmt::Debug for Device {
         f.debug_struct("Device")
             .field("product_id", &self.product_id())
             .field("vendor_id", &self.vendor_id())
+            .field("interface_number", &self.interface_number())
             .field("driver_id", &self.driver_id())
             .field("device_interface_guid", &self.device_interface_guid())
             .field("device_id", &self.device_id())
@@ -181,3 +245,104 @@ impl fmt::Debug for Device {
             .finish()
     }
 }
+
+/// Returns the bytes of a fixed-width C string up to, but not including, the first NUL.
+pub(crate) fn c_str(data: &[CHAR; STRING_LEN]) -> &[u8] {
+    // SAFETY: `CHAR` is `i8` or `u8` depending on the target, both of which have the layout of `u8`.
+    let data = unsafe { &*(data as *const [CHAR; STRING_LEN] as *const [u8; STRING_LEN]) };
+    let len = data.iter().position(|&b| b == 0).unwrap_or(STRING_LEN);
+    &data[..len]
+}
+
+fn try_decode(bytes: &[u8]) -> crate::Result<&str> {
+    std::str::from_utf8(bytes).map_err(|_| Error::from_code(ERROR_NO_UNICODE_TRANSLATION))
+}
+
+fn decode(bytes: &[u8]) -> Cow<'_, str> {
+    match std::str::from_utf8(bytes) {
+        Ok(s) => Cow::Borrowed(s),
+        Err(_) => Cow::Owned(decode_ansi(bytes)),
+    }
+}
+
+#[cfg(windows)]
+fn decode_ansi(bytes: &[u8]) -> String {
+    use winapi::um::stringapiset::MultiByteToWideChar;
+    use winapi::um::winnls::CP_ACP;
+
+    let mut wide = vec![0u16; bytes.len()];
+    let len = unsafe {
+        MultiByteToWideChar(
+            CP_ACP,
+            0,
+            bytes.as_ptr() as *const _,
+            bytes.len() as i32,
+            wide.as_mut_ptr(),
+            wide.len() as i32,
+        )
+    };
+    if len <= 0 {
+        return String::from_utf8_lossy(bytes).into_owned();
+    }
+    String::from_utf16_lossy(&wide[..len as usize])
+}
+
+#[cfg(not(windows))]
+fn decode_ansi(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{SimBackend, SimDevice};
+
+    fn fixed(s: &[u8]) -> [CHAR; STRING_LEN] {
+        let mut data = [0; STRING_LEN];
+        for (dst, &src) in data.iter_mut().zip(s) {
+            *dst = src as CHAR;
+        }
+        data
+    }
+
+    #[test]
+    fn strings_stop_at_first_nul() {
+        let data = fixed(b"ACME\0junk");
+        assert_eq!(c_str(&data), b"ACME");
+        assert_eq!(try_decode(c_str(&data)).unwrap(), "ACME");
+
+        let full = fixed(&[b'A'; STRING_LEN]);
+        assert_eq!(c_str(&full).len(), STRING_LEN);
+    }
+
+    #[test]
+    fn non_utf8_strings_fall_back() {
+        // "Gerät" in Windows-1252.
+        let data = fixed(b"Ger\xE4t");
+        assert_eq!(
+            try_decode(c_str(&data)),
+            Err(Error::Other(ERROR_NO_UNICODE_TRANSLATION))
+        );
+        let decoded = decode(c_str(&data));
+        assert!(decoded.starts_with("Ger"));
+        assert!(decoded.ends_with('t'));
+    }
+
+    #[test]
+    fn snapshots_open_through_a_device_list() {
+        let backend = SimBackend::new();
+        let device = SimDevice::new(0x1234, 0x5678);
+        backend.add_device(&device);
+        let backend = Arc::new(backend);
+        let listed = DeviceList::with_backend(backend.clone())
+            .unwrap()
+            .find_with_vid_and_pid(0x1234, 0x5678)
+            .unwrap();
+        let snapshot = Device::snapshot(listed.inner(), backend.clone());
+        drop(listed);
+
+        drop(snapshot.open().unwrap());
+        backend.remove_device(&device);
+        assert!(matches!(snapshot.open(), Err(Error::NoDevice(_))));
+    }
+}
@@ -0,0 +1,160 @@
+use std::fmt;
+
+use crate::config_descriptor::header;
+use crate::fields::{Version, DESCRIPTOR_TYPE_DEVICE};
+
+pub(crate) const DEVICE_DESCRIPTOR_LEN: usize = 18;
+
+/// Describes a device.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct DeviceDescriptor {
+    raw: [u8; DEVICE_DESCRIPTOR_LEN],
+}
+
+impl DeviceDescriptor {
+    /// Parses a device descriptor as returned by GET_DESCRIPTOR(DEVICE).
+    pub fn from_bytes(data: &[u8]) -> crate::Result<Self> {
+        Ok(Self {
+            raw: header(data, DESCRIPTOR_TYPE_DEVICE, DEVICE_DESCRIPTOR_LEN)?,
+        })
+    }
+
+    /// Returns the size of the descriptor in bytes
+    pub fn length(&self) -> u8 {
+        self.raw[0]
+    }
+
+    /// Returns the descriptor type
+    pub fn descriptor_type(&self) -> u8 {
+        self.raw[1]
+    }
+
+    /// Returns the device's maximum supported USB version.
+    pub fn usb_version(&self) -> Version {
+        Version::from_bcd(self.word(2))
+    }
+
+    /// Returns the device's class code.
+    pub fn class_code(&self) -> u8 {
+        self.raw[4]
+    }
+
+    /// Returns the device's sub class code.
+    pub fn sub_class_code(&self) -> u8 {
+        self.raw[5]
+    }
+
+    /// Returns the device's protocol code.
+    pub fn protocol_code(&self) -> u8 {
+        self.raw[6]
+    }
+
+    /// Returns the maximum packet size for endpoint zero.
+    pub fn max_packet_size(&self) -> u8 {
+        self.raw[7]
+    }
+
+    /// Returns the device's vendor ID.
+    pub fn vendor_id(&self) -> u16 {
+        self.word(8)
+    }
+
+    /// Returns the device's product ID.
+    pub fn product_id(&self) -> u16 {
+        self.word(10)
+    }
+
+    /// Returns the device's version.
+    pub fn device_version(&self) -> Version {
+        Version::from_bcd(self.word(12))
+    }
+
+    /// Returns the index of the string descriptor that contains the manufacturer name.
+    pub fn manufacturer_string_index(&self) -> Option<u8> {
+        string_index(self.raw[14])
+    }
+
+    /// Returns the index of the string descriptor that contains the product name.
+    pub fn product_string_index(&self) -> Option<u8> {
+        string_index(self.raw[15])
+    }
+
+    /// Returns the index of the string descriptor that contains the serial number.
+    pub fn serial_number_string_index(&self) -> Option<u8> {
+        string_index(self.raw[16])
+    }
+
+    /// Returns the number of config descriptors available for the device.
+    pub fn num_configurations(&self) -> u8 {
+        self.raw[17]
+    }
+
+    fn word(&self, offset: usize) -> u16 {
+        u16::from_le_bytes([self.raw[offset], self.raw[offset + 1]])
+    }
+}
+
+impl fmt::Debug for DeviceDescriptor {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug = fmt.debug_struct("DeviceDescriptor");
+
+        debug.field("bLength", &self.length());
+        debug.field("bDescriptorType", &self.descriptor_type());
+        debug.field("bcdUSB", &self.word(2));
+        debug.field("bDeviceClass", &self.class_code());
+        debug.field("bDeviceSubClass", &self.sub_class_code());
+        debug.field("bDeviceProtocol", &self.protocol_code());
+        debug.field("bMaxPacketSize", &self.max_packet_size());
+        debug.field("idVendor", &self.vendor_id());
+        debug.field("idProduct", &self.product_id());
+        debug.field("bcdDevice", &self.word(12));
+        debug.field("iManufacturer", &self.raw[14]);
+        debug.field("iProduct", &self.raw[15]);
+        debug.field("iSerialNumber", &self.raw[16]);
+        debug.field("bNumConfigurations", &self.num_configurations());
+
+        debug.finish()
+    }
+}
+
+pub(crate) fn string_index(index: u8) -> Option<u8> {
+    match index {
+        0 => None,
+        n => Some(n),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Captured from an FTDI FT232R.
+    const FT232R: [u8; 18] = [
+        0x12, 0x01, 0x00, 0x02, 0x00, 0x00, 0x00, 0x08, 0x03, 0x04, 0x01, 0x60, 0x00, 0x06, 0x01,
+        0x02, 0x03, 0x01,
+    ];
+
+    #[test]
+    fn parses_device_descriptor() {
+        let desc = DeviceDescriptor::from_bytes(&FT232R).unwrap();
+        assert_eq!(desc.usb_version(), Version(2, 0, 0));
+        assert_eq!(desc.class_code(), 0);
+        assert_eq!(desc.max_packet_size(), 8);
+        assert_eq!(desc.vendor_id(), 0x0403);
+        assert_eq!(desc.product_id(), 0x6001);
+        assert_eq!(desc.device_version(), Version(6, 0, 0));
+        assert_eq!(desc.manufacturer_string_index(), Some(1));
+        assert_eq!(desc.product_string_index(), Some(2));
+        assert_eq!(desc.serial_number_string_index(), Some(3));
+        assert_eq!(desc.num_configurations(), 1);
+    }
+
+    #[test]
+    fn rejects_malformed_device_descriptor() {
+        assert!(DeviceDescriptor::from_bytes(&FT232R[..17]).is_err());
+
+        let mut wrong_type = FT232R;
+        wrong_type[1] = 0x02;
+        assert!(DeviceDescriptor::from_bytes(&wrong_type).is_err());
+    }
+}
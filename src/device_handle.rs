@@ -1,85 +1,1001 @@
-use libusbk_sys::{UsbK_ReleaseInterface, KUSB_DRIVER_API};
-use std::collections::HashSet;
+use libusbk_sys::{_KUSB_PROPERTY_KUSB_PROPERTY_DEVICE_FILE_HANDLE, WINUSB_SETUP_PACKET};
+use std::cell::{RefCell, RefMut};
+use std::collections::{HashMap, HashSet};
 use std::ffi::c_void;
 use std::fmt::Display;
+use std::mem;
 use std::ptr::NonNull;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
-use crate::error::try_unsafe;
+use once_cell::sync::OnceCell;
+
+use crate::async_io::{self, AsyncIo, TransferFuture};
+use crate::backend::{Backend, DeviceHandleBackend};
+use crate::bos_descriptor::{BosDescriptor, BOS_DESCRIPTOR_LEN};
+use crate::config_descriptor::{
+    ConfigDescriptor, Interface, InterfaceDescriptor, SuperSpeedEndpointCompanion,
+    CONFIG_DESCRIPTOR_LEN,
+};
+use crate::device::Device;
+use crate::device_descriptor::{DeviceDescriptor, DEVICE_DESCRIPTOR_LEN};
+use crate::device_list::DeviceListBuilder;
+use crate::error::{
+    Error, ERROR_ACCESS_DENIED, ERROR_INVALID_DATA, ERROR_INVALID_HANDLE, ERROR_INVALID_PARAMETER,
+    ERROR_NOT_FOUND, ERROR_NOT_SUPPORTED, WAIT_TIMEOUT,
+};
+use crate::fields::{
+    request_type, Direction, Recipient, RequestType, Speed, DESCRIPTOR_TYPE_BOS,
+    DESCRIPTOR_TYPE_CONFIG, DESCRIPTOR_TYPE_DEVICE, DESCRIPTOR_TYPE_STRING,
+    DEVICE_INFORMATION_SPEED, ENDPOINT_DIR_MASK, ENDPOINT_IN, ENDPOINT_OUT,
+    FEATURE_DEVICE_REMOTE_WAKEUP, FEATURE_ENDPOINT_HALT, REQUEST_CLEAR_FEATURE,
+    REQUEST_GET_DESCRIPTOR, REQUEST_SET_FEATURE, SPEED_FULL, SPEED_HIGH, SPEED_LOW,
+};
+use crate::interface::{ClaimedInterface, InterfaceSelector};
+use crate::iso::{IsoPacketInformation, IsoTransfer};
+use crate::language::{self, Language};
+use crate::overlapped::{OverlappedPool, Transfer};
+use crate::pipe_info::PipeInfo;
+use crate::pipe_policy::{PipePolicy, PipePolicyKind};
+use crate::power_policy::{PowerPolicy, PowerPolicyKind};
 use crate::Result;
 
-type UsbkHandle = NonNull<c_void>;
-type Interface = (u8, bool);
+/// The default control pipe.
+const CONTROL_PIPE: u8 = 0x00;
+
+static NEXT_HANDLE_ID: AtomicUsize = AtomicUsize::new(1);
+
+/// How often [`DeviceHandle::reset_and_reopen`] checks whether the device has dropped off the
+/// bus after the reset.
+const REMOVAL_POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// How long [`DeviceHandle::reset_and_reopen`] waits for the device to drop off the bus before
+/// assuming that the reset does not re-enumerate it.
+const REMOVAL_TIMEOUT: Duration = Duration::from_secs(2);
 
-unsafe impl Send for DeviceHandle {}
+/// How often [`DeviceHandle::reset_and_reopen`] looks for the device while it re-enumerates.
+const REOPEN_POLL_INTERVAL: Duration = Duration::from_millis(50);
 
 #[derive(Debug)]
 pub struct DeviceHandle {
-    pub(crate) dev: KUSB_DRIVER_API,
+    /// Borrowed for each call, so that I/O only needs a shared reference to the handle and
+    /// can run while interfaces are claimed.
+    inner: RefCell<Box<dyn DeviceHandleBackend>>,
+    id: usize,
     pub(crate) driver_id: i32,
+    /// The device the handle was opened from, for finding it again after a reset.
+    device: Device,
     // TODO use bitmap
-    pub(crate) claimed_interface: HashSet<Interface>,
-    // TODO not pub
-    pub(crate) handle: Option<UsbkHandle>,
+    claimed_interface: RefCell<HashSet<u8>>,
+    /// The active configuration, read when the first interface is claimed to find the endpoints
+    /// of each interface.
+    config: OnceCell<ConfigDescriptor>,
+    /// The transfer timeouts last set through this handle, by pipe, so that they are only sent
+    /// to the driver when they change.
+    transfer_timeouts: RefCell<HashMap<u8, Duration>>,
+    /// Created with the first future.
+    pub(crate) async_io: OnceCell<AsyncIo>,
 }
 
 impl DeviceHandle {
-    pub fn claim_interface(&mut self, num_or_index: u8, is_index: bool) -> Result<()> {
-        try_unsafe!(self.dev.ClaimInterface.unwrap()(
-            self.handle.unwrap().as_ptr(),
-            num_or_index,
-            is_index.into()
-        ));
-        self.claimed_interface.insert((num_or_index, is_index));
+    pub(crate) fn new(inner: Box<dyn DeviceHandleBackend>, device: Device) -> Self {
+        Self {
+            inner: RefCell::new(inner),
+            id: NEXT_HANDLE_ID.fetch_add(1, Ordering::Relaxed),
+            driver_id: device.driver_id(),
+            device,
+            claimed_interface: RefCell::new(HashSet::new()),
+            config: OnceCell::new(),
+            transfer_timeouts: RefCell::new(HashMap::new()),
+            async_io: OnceCell::new(),
+        }
+    }
+
+    pub(crate) fn inner(&self) -> RefMut<'_, Box<dyn DeviceHandleBackend>> {
+        self.inner.borrow_mut()
+    }
+
+    /// Opens a handle to an interface associated with this handle's interface, e.g. the other
+    /// functions of a composite device. `index` counts from zero for the interface following
+    /// this handle's.
+    ///
+    /// The returned handle is independent of this one and can be moved to another thread. It
+    /// keeps this handle's underlying device open until it is dropped, and starts without any
+    /// interface claimed.
+    pub fn associated_interface(&self, index: u8) -> Result<DeviceHandle> {
+        let inner = self.inner().associated_interface(index)?;
+        Ok(DeviceHandle::new(inner, self.device.clone()))
+    }
+
+    /// Opens another handle to the same device and interface, e.g. to use it from another
+    /// thread. The new handle starts without any interface claimed.
+    pub fn try_clone(&self) -> Result<DeviceHandle> {
+        let inner = self.inner().try_clone()?;
+        Ok(DeviceHandle::new(inner, self.device.clone()))
+    }
+
+    /// Resets the port the device is attached to.
+    ///
+    /// The device re-enumerates afterwards, so this handle can no longer be used for transfers.
+    /// Use [`reset_and_reopen`](Self::reset_and_reopen) to get a handle to the device once it
+    /// is back.
+    pub fn reset(&mut self) -> Result<()> {
+        self.inner().reset_device()?;
+        // The reset released every interface.
+        self.claimed_interface.get_mut().clear();
+        self.transfer_timeouts.get_mut().clear();
+        Ok(())
+    }
+
+    /// Resets the device like [`reset`](Self::reset), then waits for it to re-enumerate and
+    /// opens it again.
+    ///
+    /// The device is recognized by its instance ID, which includes its serial number if it has
+    /// one, and its re-enumeration is tracked through hotplug events. The reset returns before
+    /// the device drops off the bus, so this first waits for it to be removed, for at most two
+    /// seconds in case the reset does not re-enumerate it. The device is then considered back
+    /// once it has arrived again, can be opened and its device descriptor read. Handles from
+    /// [`associated_interface`](Self::associated_interface) reopen the device their parent was
+    /// opened from.
+    ///
+    /// Fails with [`Error::Timeout`] if the device is not back within `timeout`. A `timeout`
+    /// of zero waits indefinitely.
+    pub fn reset_and_reopen(mut self, timeout: Duration) -> Result<DeviceHandle> {
+        let device = self.device.clone();
+        let mut same_device = DeviceListBuilder::new();
+        same_device.device_id(&device.device_id());
+        let pattern = same_device.pattern_match()?.unwrap_or_default();
+        // Watching from before the reset, so that no event can be missed.
+        let watch = device.backend().watch(&pattern)?;
+        self.reset()?;
+        drop(self);
+
+        let start = Instant::now();
+        let expired = |limit: Duration| start.elapsed() >= limit;
+        let removal_limit = match timeout.is_zero() {
+            true => REMOVAL_TIMEOUT,
+            false => timeout.min(REMOVAL_TIMEOUT),
+        };
+        // Opening the old instance would give a handle that stops working once it is removed.
+        // An arrival without a removal means the device was already gone before the reset.
+        while watch.removals() == 0 && watch.arrivals() == 0 && !expired(removal_limit) {
+            thread::sleep(REMOVAL_POLL_INTERVAL);
+        }
+        loop {
+            if watch.arrivals() >= watch.removals() {
+                if let Some(handle) = reopen(device.backend(), &same_device)? {
+                    return Ok(handle);
+                }
+            }
+            if !timeout.is_zero() && expired(timeout) {
+                return Err(Error::from_code(WAIT_TIMEOUT));
+            }
+            thread::sleep(REOPEN_POLL_INTERVAL);
+        }
+    }
+
+    /// Returns the `bConfigurationValue` of the active configuration, or zero if the device is
+    /// not configured.
+    pub fn active_configuration(&self) -> Result<u8> {
+        self.inner().get_configuration()
+    }
+
+    /// Makes the configuration with the `bConfigurationValue` `value` the active one.
+    ///
+    /// Changing the configuration releases every claimed interface. Fails with
+    /// [`Error::NotSupported`] for devices using the WinUSB driver, which always uses the first
+    /// configuration.
+    pub fn set_active_configuration(&mut self, value: u8) -> Result<()> {
+        if self.driver_id() == DriverId::WinUsb {
+            return Err(Error::from_code(ERROR_NOT_SUPPORTED));
+        }
+        self.inner().set_configuration(value)?;
+        self.claimed_interface.get_mut().clear();
+        self.transfer_timeouts.get_mut().clear();
+        self.config.take();
+        Ok(())
+    }
+
+    /// Reads the descriptor of the active configuration, or of the first configuration if the
+    /// driver cannot tell which one is active.
+    fn active_config_descriptor(&self) -> Result<ConfigDescriptor> {
+        if let Ok(value) = self.active_configuration() {
+            for index in 0..self.device_descriptor()?.num_configurations() {
+                let config = self.config_descriptor(index)?;
+                if config.number() == value {
+                    return Ok(config);
+                }
+            }
+        }
+        self.config_descriptor(0)
+    }
+
+    /// Claims an interface, which stays claimed until the returned guard is dropped. Guards for
+    /// several interfaces can be held at once.
+    ///
+    /// Pipe transfers on endpoints of interfaces that are not claimed fail with
+    /// [`Error::Access`].
+    pub fn claim_interface(&self, interface: InterfaceSelector) -> Result<ClaimedInterface<'_>> {
+        self.config
+            .get_or_try_init(|| self.active_config_descriptor())?;
+        let descriptor = self
+            .find_interface(interface)
+            .cloned()
+            .ok_or(Error::from_code(ERROR_NOT_FOUND))?;
+        let (num_or_index, is_index) = interface.to_raw();
+        self.inner().claim_interface(num_or_index, is_index)?;
+        self.claimed_interface
+            .borrow_mut()
+            .insert(descriptor.number());
+
+        // The guard releases the interface again if this fails.
+        let mut claimed = ClaimedInterface {
+            handle: self,
+            interface: descriptor,
+            alt_setting: 0,
+        };
+        claimed.alt_setting = self.alternate_setting(interface)?;
+        Ok(claimed)
+    }
+
+    /// Releases an interface claimed with [`claim_interface`](Self::claim_interface), for
+    /// guards that were leaked.
+    ///
+    /// Interfaces still claimed when the handle is dropped are released then.
+    pub fn release_interface(&self, interface: InterfaceSelector) -> Result<()> {
+        let (num_or_index, is_index) = interface.to_raw();
+        self.inner().release_interface(num_or_index, is_index)?;
+        if let Some(number) = self.find_interface(interface).map(|i| i.number()) {
+            self.claimed_interface.borrow_mut().remove(&number);
+        }
         Ok(())
     }
 
+    /// Makes an interface the current one without claiming it again. Claiming an interface
+    /// also selects it.
+    pub fn select_interface(&self, interface: InterfaceSelector) -> Result<()> {
+        let (num_or_index, is_index) = interface.to_raw();
+        self.inner().select_interface(num_or_index, is_index)
+    }
+
+    /// Switches an interface to one of its alternate settings, e.g. to change the bandwidth
+    /// reserved for its isochronous endpoints.
+    pub fn set_alternate_setting(&self, interface: InterfaceSelector, setting: u8) -> Result<()> {
+        let (num_or_index, is_index) = interface.to_raw();
+        self.inner()
+            .set_alt_interface(num_or_index, is_index, setting)?;
+        // The driver recreates the pipes with their default policies.
+        self.transfer_timeouts.borrow_mut().clear();
+        Ok(())
+    }
+
+    /// Returns the alternate setting an interface is currently using.
+    pub fn alternate_setting(&self, interface: InterfaceSelector) -> Result<u8> {
+        let (num_or_index, is_index) = interface.to_raw();
+        self.inner().get_alt_interface(num_or_index, is_index)
+    }
+
+    /// Returns the descriptor of the alternate setting at `alt_setting_index` of the current
+    /// interface, i.e. the one last claimed or selected.
+    ///
+    /// The descriptor comes from the driver and does not include endpoints; use
+    /// [`pipes`](Self::pipes) to list them.
+    pub fn interface_settings(&self, alt_setting_index: u8) -> Result<InterfaceDescriptor> {
+        let desc = self.inner().query_interface_settings(alt_setting_index)?;
+        Ok(InterfaceDescriptor::from_native(&desc))
+    }
+
+    /// Lists the pipes of the alternate setting at `alt_setting_index` of the current interface,
+    /// so that pipe IDs do not need to be hardcoded.
+    pub fn pipes(&self, alt_setting_index: u8) -> Result<Vec<PipeInfo>> {
+        let settings = self.interface_settings(alt_setting_index)?;
+        (0..settings.num_endpoints())
+            .map(|pipe_index| {
+                let info = self.inner().query_pipe(alt_setting_index, pipe_index)?;
+                PipeInfo::from_native(&info)
+            })
+            .collect()
+    }
+
+    /// Reads the SuperSpeed endpoint companion descriptor of the pipe at `pipe_index` of an
+    /// alternate setting of the current interface, describing the bursts and bytes per interval
+    /// the endpoint supports.
+    ///
+    /// Endpoints only have a companion descriptor when the device is connected at SuperSpeed.
+    pub fn superspeed_companion(
+        &self,
+        alt_setting_index: u8,
+        pipe_index: u8,
+    ) -> Result<SuperSpeedEndpointCompanion> {
+        let desc = self
+            .inner()
+            .superspeed_pipe_companion(alt_setting_index, pipe_index)?;
+        Ok(SuperSpeedEndpointCompanion::from_native(&desc))
+    }
+
+    /// Returns the speed the device is connected at.
+    ///
+    /// Drivers report SuperSpeed links as high speed. They are told apart by the driver having
+    /// SuperSpeed endpoint companion descriptors for the current interface, which devices only
+    /// return when connected at SuperSpeed, so a link is reported as high speed if no alternate
+    /// setting of the current interface has an endpoint.
+    pub fn speed(&self) -> Result<Speed> {
+        let mut speed = 0;
+        self.inner()
+            .query_device_information(DEVICE_INFORMATION_SPEED, std::slice::from_mut(&mut speed))?;
+        match speed {
+            SPEED_LOW => Ok(Speed::Low),
+            SPEED_FULL => Ok(Speed::Full),
+            SPEED_HIGH.. if self.has_superspeed_companions() => Ok(Speed::Super),
+            SPEED_HIGH.. => Ok(Speed::High),
+            _ => Err(Error::from_code(ERROR_INVALID_DATA)),
+        }
+    }
+
+    /// Returns whether the driver has a SuperSpeed endpoint companion descriptor for any pipe of
+    /// the current interface.
+    fn has_superspeed_companions(&self) -> bool {
+        let mut inner = self.inner();
+        for alt_setting_index in 0..=u8::MAX {
+            let Ok(setting) = inner.query_interface_settings(alt_setting_index) else {
+                return false;
+            };
+            for pipe_index in 0..setting.bNumEndpoints {
+                if inner
+                    .superspeed_pipe_companion(alt_setting_index, pipe_index)
+                    .is_ok()
+                {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    fn find_interface(&self, interface: InterfaceSelector) -> Option<&Interface> {
+        let mut interfaces = self.config.get()?.interfaces();
+        match interface {
+            InterfaceSelector::Number(number) => interfaces.find(|i| i.number() == number),
+            InterfaceSelector::Index(index) => interfaces.nth(index.into()),
+        }
+    }
+
+    /// Returns whether the interface numbered `number` is claimed through this handle.
+    pub(crate) fn is_claimed(&self, number: u8) -> bool {
+        self.claimed_interface.borrow().contains(&number)
+    }
+
+    /// Fails if `endpoint` belongs to an interface of the active configuration that is not
+    /// claimed.
+    pub(crate) fn check_claimed(&self, endpoint: u8) -> Result<()> {
+        let config = self
+            .config
+            .get_or_try_init(|| self.active_config_descriptor())?;
+        let claimed = self.claimed_interface.borrow();
+        let owner = config.interfaces().find(|interface| {
+            interface
+                .descriptors()
+                .flat_map(|setting| setting.endpoint_descriptors())
+                .any(|e| e.address() == endpoint)
+        });
+        match owner {
+            Some(interface) if !claimed.contains(&interface.number()) => {
+                Err(Error::from_code(ERROR_ACCESS_DENIED))
+            }
+            _ => Ok(()),
+        }
+    }
+
     pub fn driver_id(&self) -> DriverId {
         DriverId::from(self.driver_id)
     }
 
-    pub fn read_pipe(&mut self, pipe_id: u8, buffer: &mut [u8]) -> crate::Result<u32> {
-        let mut transferred: u32 = 0;
-        try_unsafe!(self.dev.ReadPipe.unwrap()(
-            self.handle.unwrap().as_ptr(),
-            pipe_id,
-            buffer.as_mut_ptr(),
-            buffer.len() as u32,
-            &mut transferred,
-            std::ptr::null_mut(),
-        ));
-        return Ok(transferred);
+    pub fn read_pipe(&self, pipe_id: u8, buffer: &mut [u8]) -> crate::Result<u32> {
+        self.check_claimed(pipe_id)?;
+        self.inner().read_pipe(pipe_id, buffer)
+    }
+
+    pub fn write_pipe(&self, pipe_id: u8, buffer: &[u8]) -> Result<u32> {
+        self.check_claimed(pipe_id)?;
+        self.inner().write_pipe(pipe_id, buffer)
+    }
+
+    /// Reads from a pipe, failing with [`Error::Timeout`] if the transfer does not complete in
+    /// time.
+    ///
+    /// `timeout` is a persistent setting of the pipe: this sets its
+    /// [`PipePolicy::TransferTimeout`], which stays in effect for later transfers, including
+    /// ones through [`read_pipe`](Self::read_pipe). The policy is only sent to the driver when
+    /// it differs from the one last set through this handle. A `timeout` of zero waits
+    /// indefinitely.
+    pub fn read_pipe_timeout(
+        &self,
+        pipe_id: u8,
+        buffer: &mut [u8],
+        timeout: Duration,
+    ) -> Result<u32> {
+        self.check_claimed(pipe_id)?;
+        self.set_transfer_timeout(pipe_id, timeout)?;
+        self.inner().read_pipe(pipe_id, buffer)
+    }
+
+    /// Writes to a pipe, failing with [`Error::Timeout`] if the transfer does not complete in
+    /// time.
+    ///
+    /// Like [`read_pipe_timeout`](Self::read_pipe_timeout), this persistently sets the pipe's
+    /// [`PipePolicy::TransferTimeout`]. A `timeout` of zero waits indefinitely.
+    pub fn write_pipe_timeout(&self, pipe_id: u8, buffer: &[u8], timeout: Duration) -> Result<u32> {
+        self.check_claimed(pipe_id)?;
+        self.set_transfer_timeout(pipe_id, timeout)?;
+        self.inner().write_pipe(pipe_id, buffer)
+    }
+
+    /// Starts reading from a pipe into `buffer` without waiting for the transfer to complete.
+    ///
+    /// `pool` must have been created for this handle. Up to `buffer.len()` bytes are read; the
+    /// data is available from the returned transfer once it completes.
+    pub fn submit_read(
+        &self,
+        pool: &OverlappedPool,
+        pipe_id: u8,
+        buffer: Vec<u8>,
+    ) -> Result<Transfer> {
+        pool.check_owner(self)?;
+        self.check_claimed(pipe_id)?;
+        pool.submit_read(pipe_id, buffer)
+    }
+
+    /// Starts writing `buffer` to a pipe without waiting for the transfer to complete.
+    ///
+    /// `pool` must have been created for this handle.
+    pub fn submit_write(
+        &self,
+        pool: &OverlappedPool,
+        pipe_id: u8,
+        buffer: Vec<u8>,
+    ) -> Result<Transfer> {
+        pool.check_owner(self)?;
+        self.check_claimed(pipe_id)?;
+        pool.submit_write(pipe_id, buffer)
+    }
+
+    /// Reads up to `len` bytes from a bulk or interrupt IN endpoint.
+    ///
+    /// The returned future completes from a background thread and works with any executor. It
+    /// does not borrow the handle. Dropping it before it resolves cancels the transfer, as does
+    /// closing the handle, which makes it resolve to [`Error::Cancelled`].
+    pub fn read_bulk(&self, endpoint: u8, len: usize) -> TransferFuture<Vec<u8>> {
+        if endpoint & ENDPOINT_DIR_MASK != ENDPOINT_IN {
+            return TransferFuture::failed(Error::from_code(ERROR_INVALID_PARAMETER), |data| data);
+        }
+        match self.check_claimed(endpoint).and_then(|_| self.async_io()) {
+            Ok(io) => io.read(endpoint, len),
+            Err(err) => TransferFuture::failed(err, |data| data),
+        }
+    }
+
+    /// Writes `data` to a bulk or interrupt OUT endpoint, resolving to the number of bytes
+    /// written.
+    ///
+    /// See [`read_bulk`](Self::read_bulk) for how the future completes.
+    pub fn write_bulk(&self, endpoint: u8, data: &[u8]) -> TransferFuture<usize> {
+        if endpoint & ENDPOINT_DIR_MASK != ENDPOINT_OUT {
+            return TransferFuture::failed(Error::from_code(ERROR_INVALID_PARAMETER), |_| 0);
+        }
+        match self.check_claimed(endpoint).and_then(|_| self.async_io()) {
+            Ok(io) => io.write(endpoint, data.to_vec()),
+            Err(err) => TransferFuture::failed(err, |_| 0),
+        }
+    }
+
+    /// Reads up to `len` bytes using a control transfer.
+    ///
+    /// The `request_type` parameter must have an inbound direction, as for
+    /// [`read_control`](Self::read_control). See [`read_bulk`](Self::read_bulk) for how the
+    /// future completes.
+    pub fn control_in(
+        &self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        len: usize,
+    ) -> TransferFuture<Vec<u8>> {
+        let setup = match request_type & ENDPOINT_DIR_MASK {
+            ENDPOINT_IN => setup_packet(request_type, request, value, index, len),
+            _ => Err(Error::from_code(ERROR_INVALID_PARAMETER)),
+        };
+        match setup.and_then(|setup| Ok((setup, self.async_io()?))) {
+            Ok((setup, io)) => io.control_in(setup, len),
+            Err(err) => TransferFuture::failed(err, |data| data),
+        }
     }
 
-    pub fn write_pipe(&mut self, pipe_id: u8, buffer: &[u8]) -> Result<u32> {
-        let mut transferred: u32 = 0;
-        let ptr = buffer.as_ptr();
-        try_unsafe!(self.dev.WritePipe.unwrap()(
-            self.handle.unwrap().as_ptr(),
+    /// Writes `data` using a control transfer, resolving to the number of bytes written.
+    ///
+    /// The `request_type` parameter must have an outbound direction, as for
+    /// [`write_control`](Self::write_control). See [`read_bulk`](Self::read_bulk) for how the
+    /// future completes.
+    pub fn control_out(
+        &self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        data: &[u8],
+    ) -> TransferFuture<usize> {
+        let setup = match request_type & ENDPOINT_DIR_MASK {
+            ENDPOINT_OUT => setup_packet(request_type, request, value, index, data.len()),
+            _ => Err(Error::from_code(ERROR_INVALID_PARAMETER)),
+        };
+        match setup.and_then(|setup| Ok((setup, self.async_io()?))) {
+            Ok((setup, io)) => io.control_out(setup, data.to_vec()),
+            Err(err) => TransferFuture::failed(err, |_| 0),
+        }
+    }
+
+    /// Reads from an isochronous IN endpoint into the packets of `transfer`, filling in the
+    /// length and status of each packet and the frame the transfer started in.
+    ///
+    /// The transfer is cancelled if it does not complete within `timeout`. A `timeout` of zero
+    /// waits indefinitely.
+    pub fn read_iso(
+        &self,
+        endpoint: u8,
+        transfer: &mut IsoTransfer,
+        timeout: Duration,
+    ) -> Result<()> {
+        if endpoint & ENDPOINT_DIR_MASK != ENDPOINT_IN {
+            return Err(Error::from_code(ERROR_INVALID_PARAMETER));
+        }
+        self.iso_transfer(endpoint, transfer, timeout)
+    }
+
+    /// Writes the packets of `transfer` to an isochronous OUT endpoint.
+    ///
+    /// See [`read_iso`](Self::read_iso) for how the transfer is run.
+    pub fn write_iso(
+        &self,
+        endpoint: u8,
+        transfer: &mut IsoTransfer,
+        timeout: Duration,
+    ) -> Result<()> {
+        if endpoint & ENDPOINT_DIR_MASK != ENDPOINT_OUT {
+            return Err(Error::from_code(ERROR_INVALID_PARAMETER));
+        }
+        self.iso_transfer(endpoint, transfer, timeout)
+    }
+
+    fn iso_transfer(
+        &self,
+        endpoint: u8,
+        transfer: &mut IsoTransfer,
+        timeout: Duration,
+    ) -> Result<()> {
+        self.check_claimed(endpoint)?;
+        let (buffer, packets, start_frame) = transfer.parts_mut();
+        let started = self
+            .inner()
+            .iso_transfer(endpoint, buffer, packets, start_frame, timeout)?;
+        transfer.set_start_frame(started);
+        Ok(())
+    }
+
+    /// Returns the current USB frame number, for choosing the start frame of an
+    /// [`IsoTransfer`].
+    pub fn current_frame_number(&self) -> Result<u32> {
+        self.inner().current_frame_number()
+    }
+
+    /// Computes how an isochronous pipe is scheduled at the speed the device is connected at,
+    /// using `IsochK_CalcPacketInformation`. The result sizes transfers with
+    /// [`IsoTransfer::for_duration`].
+    pub fn iso_packet_information(&self, pipe: &PipeInfo) -> Result<IsoPacketInformation> {
+        let high_speed = self.speed()? >= Speed::High;
+        let info = self
+            .inner()
+            .iso_packet_information(high_speed, &pipe.to_native())?;
+        Ok(IsoPacketInformation::from_native(
+            &info,
+            pipe.max_bytes_per_interval(),
+        ))
+    }
+
+    pub(crate) fn async_io(&self) -> Result<&AsyncIo> {
+        self.async_io
+            .get_or_try_init(|| AsyncIo::new(OverlappedPool::new(self, async_io::MAX_TRANSFERS)?))
+    }
+
+    /// Sets a policy on the pipe with the given endpoint address, or on the control pipe for
+    /// address 0.
+    pub fn set_pipe_policy(&self, pipe_id: u8, policy: PipePolicy) -> Result<()> {
+        let result =
+            self.inner()
+                .set_pipe_policy(pipe_id, policy.kind().policy_type(), &policy.to_bytes());
+        if let PipePolicy::TransferTimeout(timeout) = policy {
+            let mut timeouts = self.transfer_timeouts.borrow_mut();
+            match result {
+                Ok(()) => timeouts.insert(pipe_id, timeout),
+                Err(_) => timeouts.remove(&pipe_id),
+            };
+        }
+        result
+    }
+
+    /// Sets a pipe's [`PipePolicy::TransferTimeout`] unless it was last set to `timeout`
+    /// through this handle.
+    fn set_transfer_timeout(&self, pipe_id: u8, timeout: Duration) -> Result<()> {
+        if self.transfer_timeouts.borrow().get(&pipe_id) == Some(&timeout) {
+            return Ok(());
+        }
+        self.set_pipe_policy(pipe_id, PipePolicy::TransferTimeout(timeout))
+    }
+
+    /// Reads the current value of a pipe policy.
+    pub fn get_pipe_policy(&self, pipe_id: u8, kind: PipePolicyKind) -> Result<PipePolicy> {
+        let mut buf = [0; 4];
+        let len = self.inner().get_pipe_policy(
             pipe_id,
-            ptr as *mut u8,
-            buffer.len() as u32,
-            &mut transferred,
-            std::ptr::null_mut()
-        ));
-        return Ok(transferred);
+            kind.policy_type(),
+            &mut buf[..kind.value_len()],
+        )?;
+        PipePolicy::from_bytes(kind, &buf[..len as usize])
+    }
+
+    /// Sets a policy on the power management of the device, e.g. to keep it from being
+    /// suspended during a long transfer.
+    pub fn set_power_policy(&self, policy: PowerPolicy) -> Result<()> {
+        self.inner()
+            .set_power_policy(policy.kind().policy_type(), &policy.to_bytes())
+    }
+
+    /// Reads the current value of a power policy.
+    pub fn get_power_policy(&self, kind: PowerPolicyKind) -> Result<PowerPolicy> {
+        let mut buf = [0; 4];
+        let len = self
+            .inner()
+            .get_power_policy(kind.policy_type(), &mut buf[..kind.value_len()])?;
+        PowerPolicy::from_bytes(kind, &buf[..len as usize])
     }
 
-    pub fn raw_handle(&self) -> NonNull<c_void> {
-        self.handle.unwrap()
+    /// Enables or disables the device's ability to wake the host from suspend, with a standard
+    /// SET_FEATURE or CLEAR_FEATURE(DEVICE_REMOTE_WAKEUP) request.
+    ///
+    /// Only devices whose configuration descriptor reports remote wakeup support accept the
+    /// request. A `timeout` of zero waits indefinitely.
+    pub fn set_remote_wakeup(&self, enable: bool, timeout: Duration) -> Result<()> {
+        let request = match enable {
+            true => REQUEST_SET_FEATURE,
+            false => REQUEST_CLEAR_FEATURE,
+        };
+        self.write_control(
+            request_type(Direction::Out, RequestType::Standard, Recipient::Device),
+            request,
+            FEATURE_DEVICE_REMOTE_WAKEUP,
+            0,
+            &[],
+            timeout,
+        )?;
+        Ok(())
+    }
+
+    /// Resets a pipe, cancelling its pending transfers and clearing its stall condition and
+    /// data toggle on both the host and the device.
+    ///
+    /// This is the usual way to recover an endpoint after a transfer failed with
+    /// [`Error::Pipe`].
+    pub fn reset_pipe(&self, pipe_id: u8) -> Result<()> {
+        self.check_claimed(pipe_id)?;
+        self.inner().reset_pipe(pipe_id)
+    }
+
+    /// Cancels the transfers pending on a pipe, which fail with [`Error::Cancelled`].
+    ///
+    /// To abort a blocking transfer running on another thread, use a [`PipeAborter`].
+    pub fn abort_pipe(&self, pipe_id: u8) -> Result<()> {
+        self.check_claimed(pipe_id)?;
+        self.inner().abort_pipe(pipe_id)
+    }
+
+    /// Discards the data the driver buffered for a pipe, e.g. after a read ended early with
+    /// [`PipePolicy::AllowPartialReads`] enabled.
+    pub fn flush_pipe(&self, pipe_id: u8) -> Result<()> {
+        self.check_claimed(pipe_id)?;
+        self.inner().flush_pipe(pipe_id)
+    }
+
+    /// Clears the halt condition of an endpoint with a standard CLEAR_FEATURE(ENDPOINT_HALT)
+    /// request.
+    ///
+    /// Unlike [`reset_pipe`](Self::reset_pipe), this leaves the host's state of the pipe
+    /// untouched.
+    ///
+    /// A `timeout` of zero waits indefinitely.
+    pub fn clear_halt(&self, endpoint: u8, timeout: Duration) -> Result<()> {
+        self.check_claimed(endpoint)?;
+        self.write_control(
+            request_type(Direction::Out, RequestType::Standard, Recipient::Endpoint),
+            REQUEST_CLEAR_FEATURE,
+            FEATURE_ENDPOINT_HALT,
+            endpoint.into(),
+            &[],
+            timeout,
+        )?;
+        Ok(())
+    }
+
+    /// Returns a [`PipeAborter`] for aborting transfers on this device from other threads.
+    pub fn pipe_aborter(&self) -> Result<PipeAborter> {
+        Ok(PipeAborter {
+            inner: Arc::new(Mutex::new(self.inner().try_clone()?)),
+        })
+    }
+
+    /// Reads data using a control transfer.
+    ///
+    /// The `request_type` parameter must be built with [`request_type`](crate::request_type) and
+    /// have an inbound direction. Returns the number of bytes read into `buf`.
+    ///
+    /// `timeout` becomes the control pipe's [`PipePolicy::TransferTimeout`], which is only sent
+    /// to the driver when it changes. A `timeout` of zero waits indefinitely.
+    pub fn read_control(
+        &self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> Result<u32> {
+        if request_type & ENDPOINT_DIR_MASK != ENDPOINT_IN {
+            return Err(Error::from_code(ERROR_INVALID_PARAMETER));
+        }
+        let setup = setup_packet(request_type, request, value, index, buf.len())?;
+        self.set_transfer_timeout(CONTROL_PIPE, timeout)?;
+        self.inner().read_control(setup, buf)
+    }
+
+    /// Writes data using a control transfer.
+    ///
+    /// The `request_type` parameter must be built with [`request_type`](crate::request_type) and
+    /// have an outbound direction. Returns the number of bytes written from `buf`.
+    ///
+    /// `timeout` becomes the control pipe's [`PipePolicy::TransferTimeout`], which is only sent
+    /// to the driver when it changes. A `timeout` of zero waits indefinitely.
+    pub fn write_control(
+        &self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buf: &[u8],
+        timeout: Duration,
+    ) -> Result<u32> {
+        if request_type & ENDPOINT_DIR_MASK != ENDPOINT_OUT {
+            return Err(Error::from_code(ERROR_INVALID_PARAMETER));
+        }
+        let setup = setup_packet(request_type, request, value, index, buf.len())?;
+        self.set_transfer_timeout(CONTROL_PIPE, timeout)?;
+        self.inner().write_control(setup, buf)
+    }
+
+    /// Identifies the handle, for checking that an [`OverlappedPool`] belongs to it. IDs are
+    /// never reused, even after the handle is dropped.
+    pub(crate) fn id(&self) -> usize {
+        self.id
+    }
+
+    /// Reads the device descriptor.
+    pub fn device_descriptor(&self) -> Result<DeviceDescriptor> {
+        let mut buf = [0; DEVICE_DESCRIPTOR_LEN];
+        let len = self
+            .inner()
+            .get_descriptor(DESCRIPTOR_TYPE_DEVICE, 0, 0, &mut buf)?;
+        DeviceDescriptor::from_bytes(&buf[..len as usize])
+    }
+
+    /// Reads the configuration descriptor at `index`, together with its interface, endpoint and
+    /// class-specific descriptors.
+    pub fn config_descriptor(&self, index: u8) -> Result<ConfigDescriptor> {
+        let data =
+            self.read_descriptor_set(DESCRIPTOR_TYPE_CONFIG, index, CONFIG_DESCRIPTOR_LEN)?;
+        ConfigDescriptor::from_bytes(&data)
+    }
+
+    /// Reads the BOS descriptor and its device capabilities.
+    ///
+    /// Devices older than USB 2.1 usually stall this request.
+    pub fn bos_descriptor(&self) -> Result<BosDescriptor> {
+        let data = self.read_descriptor_set(DESCRIPTOR_TYPE_BOS, 0, BOS_DESCRIPTOR_LEN)?;
+        BosDescriptor::from_bytes(&data)
+    }
+
+    /// Reads the languages supported by the device's string descriptors.
+    ///
+    /// Devices without string descriptors may stall this request or return an empty list.
+    pub fn read_languages(&self, timeout: Duration) -> Result<Vec<Language>> {
+        let data = self.read_string_data(0, 0, timeout)?;
+        language::parse_languages(&data)
+    }
+
+    /// Reads the string descriptor at `index` in the given language and decodes it from
+    /// UTF-16LE.
+    pub fn read_string_descriptor(
+        &self,
+        language: Language,
+        index: u8,
+        timeout: Duration,
+    ) -> Result<String> {
+        let data = self.read_string_data(index, language.lang_id(), timeout)?;
+        language::parse_string(&data)
+    }
+
+    /// Reads the device's manufacturer string, or `None` if the device does not provide one.
+    pub fn read_manufacturer_string(
+        &self,
+        language: Language,
+        device: &DeviceDescriptor,
+        timeout: Duration,
+    ) -> Result<Option<String>> {
+        self.read_optional_string(language, device.manufacturer_string_index(), timeout)
+    }
+
+    /// Reads the device's product string, or `None` if the device does not provide one.
+    pub fn read_product_string(
+        &self,
+        language: Language,
+        device: &DeviceDescriptor,
+        timeout: Duration,
+    ) -> Result<Option<String>> {
+        self.read_optional_string(language, device.product_string_index(), timeout)
+    }
+
+    /// Reads the serial number reported by the device itself, or `None` if the device does not
+    /// provide one.
+    ///
+    /// Unlike [`Device::serial_number`](crate::Device::serial_number), this is not taken from
+    /// the Windows device setup information.
+    pub fn read_serial_number_string(
+        &self,
+        language: Language,
+        device: &DeviceDescriptor,
+        timeout: Duration,
+    ) -> Result<Option<String>> {
+        self.read_optional_string(language, device.serial_number_string_index(), timeout)
+    }
+
+    fn read_optional_string(
+        &self,
+        language: Language,
+        index: Option<u8>,
+        timeout: Duration,
+    ) -> Result<Option<String>> {
+        index
+            .map(|index| self.read_string_descriptor(language, index, timeout))
+            .transpose()
+    }
+
+    fn read_string_data(&self, index: u8, lang_id: u16, timeout: Duration) -> Result<Vec<u8>> {
+        let mut buf = vec![0; 255];
+        let len = self.read_control(
+            request_type(Direction::In, RequestType::Standard, Recipient::Device),
+            REQUEST_GET_DESCRIPTOR,
+            u16::from(DESCRIPTOR_TYPE_STRING) << 8 | u16::from(index),
+            lang_id,
+            &mut buf,
+            timeout,
+        )?;
+        buf.truncate(len as usize);
+        Ok(buf)
+    }
+
+    /// Reads a descriptor whose header carries the total length of the descriptors following it.
+    fn read_descriptor_set(
+        &self,
+        descriptor_type: u8,
+        index: u8,
+        header_len: usize,
+    ) -> Result<Vec<u8>> {
+        let mut data = vec![0; header_len];
+        let len = self
+            .inner()
+            .get_descriptor(descriptor_type, index, 0, &mut data)?;
+        if len < 4 {
+            return Err(Error::from_code(ERROR_INVALID_DATA));
+        }
+
+        data.resize(u16::from_le_bytes([data[2], data[3]]) as usize, 0);
+        let len = self
+            .inner()
+            .get_descriptor(descriptor_type, index, 0, &mut data)?;
+        data.truncate(len as usize);
+        Ok(data)
+    }
+
+    /// The underlying `libusbK` handle, or `None` if the device was opened through a backend
+    /// without one.
+    pub fn raw_handle(&self) -> Option<NonNull<c_void>> {
+        self.inner().raw_handle()
+    }
+
+    /// The Windows file handle of the device, e.g. for associating it with an I/O completion
+    /// port or issuing `DeviceIoControl` requests directly.
+    ///
+    /// The handle belongs to the driver and stays valid until this handle is dropped; it must
+    /// not be closed. Backends without a device file fail with [`Error::NotSupported`].
+    pub fn device_file_handle(&self) -> Result<NonNull<c_void>> {
+        let mut value = [0; mem::size_of::<*mut c_void>()];
+        self.inner()
+            .get_property(_KUSB_PROPERTY_KUSB_PROPERTY_DEVICE_FILE_HANDLE, &mut value)?;
+        NonNull::new(usize::from_ne_bytes(value) as *mut c_void)
+            .ok_or(Error::from_code(ERROR_INVALID_HANDLE))
+    }
+}
+
+/// Returns the [device file handle](DeviceHandle::device_file_handle), or a null handle if the
+/// backend has no device file.
+#[cfg(windows)]
+impl std::os::windows::io::AsRawHandle for DeviceHandle {
+    fn as_raw_handle(&self) -> std::os::windows::io::RawHandle {
+        self.device_file_handle()
+            .map_or(std::ptr::null_mut(), |handle| handle.as_ptr())
     }
 }
 
 impl Drop for DeviceHandle {
     fn drop(&mut self) {
-        if let Some(handle) = self.handle {
-            for i in &self.claimed_interface {
-                unsafe { UsbK_ReleaseInterface(handle.as_ptr(), i.0, i.1 as i32) };
-            }
-            unsafe { self.dev.Free.unwrap()(handle.as_ptr()) };
+        let inner = self.inner.get_mut();
+        for &number in self.claimed_interface.get_mut().iter() {
+            let _ = inner.release_interface(number, false);
         }
     }
 }
 
+/// Aborts the transfers pending on the pipes of a device, as returned by
+/// [`DeviceHandle::pipe_aborter`].
+///
+/// Unlike the [`DeviceHandle`] it was created from, the aborter can be shared between threads,
+/// so it can abort a blocking [`DeviceHandle::read_pipe`] from another thread. It keeps the
+/// device open until it and all its clones are dropped.
+#[derive(Debug, Clone)]
+pub struct PipeAborter {
+    inner: Arc<Mutex<Box<dyn DeviceHandleBackend>>>,
+}
+
+impl PipeAborter {
+    /// Cancels the transfers pending on a pipe, which fail with [`Error::Cancelled`].
+    pub fn abort_pipe(&self, pipe_id: u8) -> Result<()> {
+        self.inner.lock().unwrap().abort_pipe(pipe_id)
+    }
+}
+
+/// Opens the first connected device listed by `builder`, if it is ready for transfers.
+fn reopen(backend: &Arc<dyn Backend>, builder: &DeviceListBuilder) -> Result<Option<DeviceHandle>> {
+    let list = builder.build_with_backend(backend.clone())?;
+    let Some(device) = list.iter().next() else {
+        return Ok(None);
+    };
+    let handle = device.open().ok();
+    Ok(handle.filter(|handle| handle.device_descriptor().is_ok()))
+}
+
+fn setup_packet(
+    request_type: u8,
+    request: u8,
+    value: u16,
+    index: u16,
+    len: usize,
+) -> Result<WINUSB_SETUP_PACKET> {
+    Ok(WINUSB_SETUP_PACKET {
+        RequestType: request_type,
+        Request: request,
+        Value: value,
+        Index: index,
+        Length: u16::try_from(len).map_err(|_| Error::from_code(ERROR_INVALID_PARAMETER))?,
+    })
+}
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(i32)]
 pub enum DriverId {
@@ -115,3 +1031,393 @@ impl Display for DriverId {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::sim::tests::open;
+    use crate::backend::{SimConfigBuilder, SimControl, SimDevice, SimOp};
+    use crate::{request_type, Direction, Recipient, RequestType};
+
+    #[test]
+    fn read_and_write_pipes() {
+        let device = SimDevice::new(0x1234, 0x5678);
+        let handle = open(&device);
+        assert!(handle.raw_handle().is_none());
+        assert!(matches!(
+            handle.device_file_handle(),
+            Err(Error::NotSupported(_))
+        ));
+
+        device.push_read(0x81, &[1, 2, 3]);
+        let mut buf = [0; 64];
+        assert_eq!(handle.read_pipe(0x81, &mut buf).unwrap(), 3);
+        assert_eq!(&buf[..3], &[1, 2, 3]);
+        assert!(handle.read_pipe(0x81, &mut buf).is_err());
+
+        assert_eq!(handle.write_pipe(0x01, &[4, 5]).unwrap(), 2);
+        assert_eq!(device.take_writes(0x01), vec![vec![4, 5]]);
+    }
+
+    #[test]
+    fn reports_speed_and_companions() {
+        let device = SimDevice::new(0x1234, 0x5678);
+        device.set_usb_version(0x0300);
+        // The endpoints as a USB 3 device returns them on a USB 2 link.
+        device.set_config(
+            0,
+            SimConfigBuilder::new(1)
+                .interface(0, 0)
+                .endpoint(0x81, 0x02, 512, 0) // bulk in
+                .endpoint(0x82, 0x01, 1024, 1), // iso in
+        );
+        let handle = open(&device);
+        // A USB 3 device behind a USB 2 hub.
+        assert_eq!(handle.speed(), Ok(Speed::High));
+        device.set_speed(2);
+        assert_eq!(handle.speed(), Ok(Speed::Full));
+
+        device.set_config(
+            0,
+            SimConfigBuilder::new(1)
+                .interface(0, 0)
+                .endpoint(0x81, 0x02, 1024, 0) // bulk in
+                .superspeed_companion(15, 0, 0) // 16 packet bursts
+                .endpoint(0x82, 0x01, 1024, 1) // iso in
+                .superspeed_companion(1, 1, 0x1800), // 2 x 2 packets per interval
+        );
+        device.set_speed(3);
+        assert_eq!(handle.speed(), Ok(Speed::Super));
+
+        let bulk = handle.superspeed_companion(0, 0).unwrap();
+        assert_eq!(bulk.max_burst(), 15);
+        let iso = handle.superspeed_companion(0, 1).unwrap();
+        assert_eq!((iso.max_burst(), iso.mult()), (1, 1));
+        assert_eq!(iso.bytes_per_interval(), 6144);
+        assert!(handle.superspeed_companion(0, 2).is_err());
+
+        device.inject_error(SimOp::QueryDeviceInformation, 50);
+        assert!(handle.speed().is_err());
+    }
+
+    #[test]
+    fn recovers_and_aborts_pipes() {
+        let device = SimDevice::new(0x1234, 0x5678);
+        let handle = open(&device);
+        let mut buf = [0; 8];
+
+        device.set_halted(0x81, true);
+        device.push_read(0x81, &[1]);
+        assert_eq!(handle.read_pipe(0x81, &mut buf), Err(Error::Pipe(31)));
+        handle.reset_pipe(0x81).unwrap();
+        assert_eq!(handle.read_pipe(0x81, &mut buf), Ok(1));
+
+        device.set_halted(0x02, true);
+        handle.clear_halt(0x02, Duration::from_secs(1)).unwrap();
+        assert!(!device.is_halted(0x02));
+        assert_eq!(
+            handle.get_pipe_policy(0x00, PipePolicyKind::TransferTimeout),
+            Ok(PipePolicy::TransferTimeout(Duration::from_secs(1)))
+        );
+        assert_eq!(
+            device.control_log(),
+            [SimControl {
+                request_type: 0x02,
+                request: 0x01,
+                value: 0,
+                index: 0x02,
+                data: vec![],
+            }]
+        );
+
+        device.push_read(0x81, &[2]);
+        handle.flush_pipe(0x81).unwrap();
+        assert!(matches!(
+            handle.read_pipe(0x81, &mut buf),
+            Err(Error::Timeout(_))
+        ));
+
+        device.set_blocking_reads(0x81, true);
+        let aborter = handle.pipe_aborter().unwrap();
+        let reader = thread::spawn(move || {
+            let mut buf = [0; 8];
+            let result = handle.read_pipe(0x81, &mut buf);
+            (handle, result)
+        });
+        // Keep aborting in case the read had not started waiting yet.
+        while !reader.is_finished() {
+            aborter.abort_pipe(0x81).unwrap();
+            thread::sleep(Duration::from_millis(5));
+        }
+        let (_handle, result) = reader.join().unwrap();
+        assert_eq!(result, Err(Error::Cancelled(995)));
+
+        device.inject_error(SimOp::AbortPipe, 87);
+        assert!(aborter.abort_pipe(0x81).is_err());
+    }
+
+    #[test]
+    fn selects_configurations() {
+        let device = SimDevice::new(0x1234, 0x5678);
+        device.set_num_configurations(2);
+        device.set_config(
+            1,
+            SimConfigBuilder::new(2)
+                .interface(0, 0)
+                .endpoint(0x83, 0x02, 512, 0), // bulk in
+        );
+        let mut handle = open(&device);
+        assert_eq!(handle.active_configuration(), Ok(1));
+
+        handle.set_active_configuration(2).unwrap();
+        assert_eq!(device.configuration(), 2);
+        let claimed = handle
+            .claim_interface(InterfaceSelector::Number(0))
+            .unwrap();
+        assert_eq!(claimed.endpoints().count(), 1);
+        assert!(claimed.check_claimed(0x83).is_ok());
+        drop(claimed);
+        assert!(matches!(handle.check_claimed(0x83), Err(Error::Access(_))));
+
+        handle.set_active_configuration(1).unwrap();
+        assert!(device.claimed_interfaces().is_empty());
+        // The endpoint is not part of the first configuration.
+        assert!(handle.check_claimed(0x83).is_ok());
+        assert!(matches!(
+            handle.set_active_configuration(3),
+            Err(Error::InvalidParam(_))
+        ));
+
+        device.set_driver_id(DriverId::WinUsb);
+        let mut handle = open(&device);
+        assert!(matches!(
+            handle.set_active_configuration(2),
+            Err(Error::NotSupported(_))
+        ));
+        assert_eq!(device.configuration(), 1);
+    }
+
+    #[test]
+    fn resets_and_reopens_devices() {
+        let device = SimDevice::new(0x1234, 0x5678);
+        let mut handle = open(&device);
+        handle
+            .claim_interface(InterfaceSelector::Number(0))
+            .unwrap()
+            .release()
+            .unwrap();
+        handle.reset().unwrap();
+        assert_eq!(device.reset_count(), 1);
+
+        device.set_reset_delay(Some(Duration::from_millis(100)));
+        let handle = handle.reset_and_reopen(Duration::from_secs(5)).unwrap();
+        assert_eq!(device.reset_count(), 2);
+        assert_eq!(device.open_handles(), 1);
+        device.push_read(0x81, &[1]);
+        assert_eq!(handle.read_pipe(0x81, &mut [0; 8]), Ok(1));
+
+        // The old instance can still be opened right after the reset.
+        device.set_removal_delay(Duration::from_millis(100));
+        device.set_reset_delay(Some(Duration::from_millis(10)));
+        let reconnects = device.reconnect_count();
+        let handle = handle.reset_and_reopen(Duration::from_secs(5)).unwrap();
+        assert_eq!(device.reconnect_count(), reconnects + 1);
+
+        // Re-enumerating faster than any poll is still noticed.
+        device.set_removal_delay(Duration::ZERO);
+        device.set_reset_delay(Some(Duration::ZERO));
+        let handle = handle.reset_and_reopen(Duration::from_secs(5)).unwrap();
+        assert_eq!(device.reconnect_count(), reconnects + 2);
+
+        // A device that stays on the bus is reopened once the removal wait gives up.
+        device.set_removal_delay(Duration::from_secs(60));
+        let handle = handle.reset_and_reopen(Duration::from_millis(100)).unwrap();
+        assert_eq!(device.reconnect_count(), reconnects + 2);
+        assert_eq!(device.open_handles(), 1);
+
+        device.set_removal_delay(Duration::ZERO);
+        device.set_reset_delay(None);
+        assert!(matches!(
+            handle.reset_and_reopen(Duration::from_millis(100)),
+            Err(Error::Timeout(_))
+        ));
+        assert_eq!(device.open_handles(), 0);
+    }
+
+    #[test]
+    fn opens_associated_interfaces_and_clones() {
+        let device = SimDevice::new(0x1234, 0x5678);
+        device.set_interfaces(&[0, 1, 2]);
+        let handle = open(&device);
+
+        let second = handle.associated_interface(0).unwrap();
+        let third = handle.associated_interface(1).unwrap();
+        assert!(handle.associated_interface(2).is_err());
+        assert!(second.associated_interface(0).is_ok());
+        assert!(second.associated_interface(1).is_err());
+
+        let clone = handle.try_clone().unwrap();
+        assert_eq!(clone.driver_id(), handle.driver_id());
+        drop(handle);
+        assert_eq!(device.open_handles(), 3);
+
+        device.push_read(0x83, &[1]);
+        let reader = thread::spawn(move || {
+            let mut buf = [0; 8];
+            third.read_pipe(0x83, &mut buf)
+        });
+        assert_eq!(reader.join().unwrap(), Ok(1));
+
+        drop(second);
+        drop(clone);
+        assert_eq!(device.open_handles(), 0);
+    }
+
+    #[test]
+    fn injected_errors_are_returned() {
+        let device = SimDevice::new(0x1234, 0x5678);
+        let handle = open(&device);
+
+        device.inject_error(SimOp::WritePipe, 31);
+        assert_eq!(handle.write_pipe(0x01, &[0]), Err(Error::Pipe(31)));
+        assert_eq!(handle.write_pipe(0x01, &[0]), Ok(1));
+    }
+
+    #[test]
+    fn reads_descriptors() {
+        let device = SimDevice::new(0x1234, 0x5678);
+        device.set_config(
+            0,
+            SimConfigBuilder::new(1)
+                .interface(0, 0)
+                .endpoint(0x81, 0x02, 512, 0) // bulk in
+                .endpoint(0x01, 0x02, 512, 0), // bulk out
+        );
+        let handle = open(&device);
+
+        let desc = handle.device_descriptor().unwrap();
+        assert_eq!(desc.vendor_id(), 0x1234);
+        assert_eq!(desc.product_id(), 0x5678);
+
+        let config = handle.config_descriptor(0).unwrap();
+        assert_eq!(config.total_length(), 32);
+        let interface = config.interfaces().next().unwrap();
+        let setting = interface.descriptors().next().unwrap();
+        assert_eq!(setting.endpoint_descriptors().count(), 2);
+
+        assert!(handle.config_descriptor(1).is_err());
+        assert!(handle.bos_descriptor().is_err());
+    }
+
+    #[test]
+    fn reads_string_descriptors() {
+        let device = SimDevice::new(0x1234, 0x5678);
+        device.set_languages(&[0x0409, 0x0407]);
+        device.set_string(0x0409, 1, "ACME");
+        device.set_string(0x0407, 1, "ACME GmbH");
+        device.set_string(0x0409, 3, "SN-0042");
+        let handle = open(&device);
+        let timeout = Duration::from_secs(1);
+
+        let languages = handle.read_languages(timeout).unwrap();
+        assert_eq!(
+            languages,
+            vec![Language::ENGLISH_US, Language::from_lang_id(0x0407)]
+        );
+        assert_eq!(
+            handle
+                .read_string_descriptor(languages[1], 1, timeout)
+                .unwrap(),
+            "ACME GmbH"
+        );
+
+        let desc = DeviceDescriptor::from_bytes(&[
+            0x12, 0x01, 0x00, 0x02, 0x00, 0x00, 0x00, 0x40, 0x34, 0x12, 0x78, 0x56, 0x00, 0x01,
+            0x01, 0x00, 0x03, 0x01,
+        ])
+        .unwrap();
+        assert_eq!(
+            handle
+                .read_manufacturer_string(Language::ENGLISH_US, &desc, timeout)
+                .unwrap()
+                .as_deref(),
+            Some("ACME")
+        );
+        assert_eq!(
+            handle
+                .read_product_string(Language::ENGLISH_US, &desc, timeout)
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            handle
+                .read_serial_number_string(Language::ENGLISH_US, &desc, timeout)
+                .unwrap()
+                .as_deref(),
+            Some("SN-0042")
+        );
+        assert!(handle
+            .read_string_descriptor(Language::ENGLISH_US, 2, timeout)
+            .is_err());
+    }
+
+    #[test]
+    fn control_transfers() {
+        let device = SimDevice::new(0x1234, 0x5678);
+        let handle = open(&device);
+        let timeout = Duration::from_millis(500);
+
+        let rt_in = request_type(Direction::In, RequestType::Vendor, Recipient::Device);
+        device.push_control_in(&[0xAA, 0xBB]);
+        let mut buf = [0; 8];
+        assert_eq!(
+            handle
+                .read_control(rt_in, 0x01, 0x0203, 0x0405, &mut buf, timeout)
+                .unwrap(),
+            2
+        );
+        assert_eq!(&buf[..2], &[0xAA, 0xBB]);
+        assert_eq!(
+            device.pipe_policy(CONTROL_PIPE, 0x03),
+            Some(500u32.to_ne_bytes().to_vec())
+        );
+
+        // The unchanged timeout is not set again.
+        device.inject_error(SimOp::SetPipePolicy, 31);
+        let rt_out = request_type(Direction::Out, RequestType::Vendor, Recipient::Device);
+        assert_eq!(
+            handle
+                .write_control(rt_out, 0x02, 0, 0, &[9, 8, 7], timeout)
+                .unwrap(),
+            3
+        );
+        assert_eq!(
+            device.control_log(),
+            vec![
+                SimControl {
+                    request_type: rt_in,
+                    request: 0x01,
+                    value: 0x0203,
+                    index: 0x0405,
+                    data: vec![],
+                },
+                SimControl {
+                    request_type: rt_out,
+                    request: 0x02,
+                    value: 0,
+                    index: 0,
+                    data: vec![9, 8, 7],
+                },
+            ]
+        );
+
+        assert_eq!(
+            handle.read_control(rt_out, 0x01, 0, 0, &mut buf, timeout),
+            Err(Error::InvalidParam(ERROR_INVALID_PARAMETER))
+        );
+        assert_eq!(
+            handle.write_control(rt_in, 0x01, 0, 0, &[], timeout),
+            Err(Error::InvalidParam(ERROR_INVALID_PARAMETER))
+        );
+    }
+}
@@ -1,58 +1,324 @@
-use std::ffi::c_void;
-use std::ptr::NonNull;
 use std::sync::Arc;
+use std::vec;
 
-use libusbk_sys::{LstK_Count, LstK_FindByVidPid, LstK_Free, LstK_Init};
+use libusbk_sys::{
+    _KLST_FLAG_KLST_FLAG_INCLUDE_DISCONNECT, _KLST_FLAG_KLST_FLAG_INCLUDE_RAWGUID, CHAR,
+    KLST_DEVINFO_HANDLE, KLST_FLAG, KLST_PATTERN_MATCH,
+};
 
-use crate::device::Device;
-use crate::error::try_unsafe;
-
-type KListHandle = NonNull<c_void>;
+use crate::backend::{self, Backend, DeviceListBackend};
+use crate::device::{Device, STRING_LEN};
+use crate::error::{Error, ERROR_INVALID_PARAMETER};
 
 /// A list of detected USB devices.
 pub struct DeviceList {
-    list: Arc<DeviceListInner>,
+    list: Arc<dyn DeviceListBackend>,
+    backend: Arc<dyn Backend>,
 }
 
-struct DeviceListInner {
-    inner: KListHandle,
-}
+impl DeviceList {
+    pub fn new() -> crate::Result<Self> {
+        Self::with_backend(backend::default())
+    }
 
-impl Drop for DeviceListInner {
-    fn drop(&mut self) {
-        unsafe { LstK_Free(self.inner.as_ptr()) };
+    /// Lists the devices known to `backend` instead of the native `libusbK` one.
+    pub fn with_backend(backend: Arc<dyn Backend>) -> crate::Result<Self> {
+        DeviceListBuilder::new().build_with_backend(backend)
+    }
+
+    pub fn length(&self) -> crate::Result<u32> {
+        self.list.count()
+    }
+
+    /// Returns an iterator over the devices in the list.
+    pub fn iter(&self) -> Devices<'_> {
+        Devices {
+            list: self,
+            infos: self.list.devices().into_iter(),
+        }
+    }
+
+    pub fn find_with_vid_and_pid(&self, vid: i32, pid: i32) -> crate::Result<Device> {
+        Ok(self.device(self.list.find_by_vid_pid(vid, pid)?))
+    }
+
+    fn device(&self, info: KLST_DEVINFO_HANDLE) -> Device {
+        Device::from_list(self.list.clone(), info, self.backend.clone())
     }
 }
 
-impl DeviceList {
-    pub fn new() -> crate::Result<Self> {
-        let mut context = std::mem::MaybeUninit::<*mut c_void>::uninit();
+/// Creates a [`DeviceList`] with non-default enumeration options.
+///
+/// Patterns are matched case-insensitively by `libusbK` and may contain the wildcards `*` and
+/// `?`.
+#[derive(Debug, Default, Clone)]
+pub struct DeviceListBuilder {
+    flags: KLST_FLAG,
+    device_id: Option<String>,
+    interface_guid: Option<String>,
+    class_guid: Option<String>,
+}
+
+impl DeviceListBuilder {
+    pub fn new() -> Self {
+        DeviceListBuilder::default()
+    }
+
+    /// Also lists devices through their raw device interface GUID.
+    pub fn include_raw_guid(&mut self, include: bool) -> &mut Self {
+        self.set_flag(_KLST_FLAG_KLST_FLAG_INCLUDE_RAWGUID, include)
+    }
+
+    /// Also lists devices that were installed but are not currently connected.
+    pub fn include_disconnected(&mut self, include: bool) -> &mut Self {
+        self.set_flag(_KLST_FLAG_KLST_FLAG_INCLUDE_DISCONNECT, include)
+    }
 
-        try_unsafe!(LstK_Init(context.as_mut_ptr(), 0));
+    /// Only lists devices whose device instance ID matches `pattern`, e.g. `USB\VID_04D8*`.
+    pub fn device_id(&mut self, pattern: &str) -> &mut Self {
+        self.device_id = Some(pattern.to_owned());
+        self
+    }
+
+    /// Only lists devices whose device interface GUID matches `pattern`.
+    pub fn interface_guid(&mut self, pattern: &str) -> &mut Self {
+        self.interface_guid = Some(pattern.to_owned());
+        self
+    }
 
-        Ok(Self {
-            list: unsafe {
-                Arc::new(DeviceListInner {
-                    inner: NonNull::new_unchecked(context.assume_init()),
-                })
-            },
+    /// Only lists devices whose device setup class GUID matches `pattern`.
+    pub fn class_guid(&mut self, pattern: &str) -> &mut Self {
+        self.class_guid = Some(pattern.to_owned());
+        self
+    }
+
+    pub fn build(&self) -> crate::Result<DeviceList> {
+        self.build_with_backend(backend::default())
+    }
+
+    /// Lists the devices known to `backend` instead of the native `libusbK` one.
+    pub fn build_with_backend(&self, backend: Arc<dyn Backend>) -> crate::Result<DeviceList> {
+        let pattern = self.pattern_match()?;
+        Ok(DeviceList {
+            list: Arc::from(backend.device_list(self.flags, pattern.as_ref())?),
+            backend,
         })
     }
 
-    pub fn length(&self) -> crate::Result<u32> {
-        let mut count = 0;
-        try_unsafe!(LstK_Count(self.list.inner.as_ptr(), &mut count));
-        Ok(count)
+    fn set_flag(&mut self, flag: KLST_FLAG, set: bool) -> &mut Self {
+        if set {
+            self.flags |= flag;
+        } else {
+            self.flags &= !flag;
+        }
+        self
     }
 
-    pub fn find_with_vid_and_pid(&self, vid: i32, pid: i32) -> crate::Result<Device> {
-        let mut device = Device(std::ptr::null_mut());
-        try_unsafe!(LstK_FindByVidPid(
-            self.list.inner.as_ptr(),
-            vid,
-            pid,
-            &mut device.0
-        ));
-        Ok(device)
+    pub(crate) fn pattern_match(&self) -> crate::Result<Option<KLST_PATTERN_MATCH>> {
+        if self.device_id.is_none() && self.interface_guid.is_none() && self.class_guid.is_none() {
+            return Ok(None);
+        }
+
+        let mut pattern = KLST_PATTERN_MATCH::default();
+        copy_pattern(&mut pattern.DeviceID, &self.device_id)?;
+        copy_pattern(&mut pattern.DeviceInterfaceGUID, &self.interface_guid)?;
+        copy_pattern(&mut pattern.ClassGUID, &self.class_guid)?;
+        Ok(Some(pattern))
+    }
+}
+
+/// Copies a pattern into a NUL-terminated `KLST_PATTERN_MATCH` field.
+fn copy_pattern(dst: &mut [CHAR; STRING_LEN], src: &Option<String>) -> crate::Result<()> {
+    let src = src.as_deref().unwrap_or_default().as_bytes();
+    if src.len() >= STRING_LEN || src.contains(&0) {
+        return Err(Error::from_code(ERROR_INVALID_PARAMETER));
+    }
+    for (d, &s) in dst.iter_mut().zip(src) {
+        *d = s as CHAR;
+    }
+    Ok(())
+}
+
+impl<'a> IntoIterator for &'a DeviceList {
+    type Item = Device;
+    type IntoIter = Devices<'a>;
+
+    fn into_iter(self) -> Devices<'a> {
+        self.iter()
+    }
+}
+
+/// Iterator over the devices in a [`DeviceList`].
+pub struct Devices<'a> {
+    list: &'a DeviceList,
+    infos: vec::IntoIter<KLST_DEVINFO_HANDLE>,
+}
+
+impl<'a> Iterator for Devices<'a> {
+    type Item = Device;
+
+    fn next(&mut self) -> Option<Device> {
+        self.infos.next().map(|info| self.list.device(info))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.infos.size_hint()
+    }
+}
+
+impl<'a> ExactSizeIterator for Devices<'a> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{SimBackend, SimDevice};
+
+    #[test]
+    fn lists_simulated_devices() {
+        let backend = SimBackend::new();
+        backend.add_device(&SimDevice::new(0x1234, 0x5678));
+        backend.add_device(&SimDevice::new(0x1234, 0x9abc));
+
+        let list = DeviceList::with_backend(Arc::new(backend)).unwrap();
+        assert_eq!(list.length().unwrap(), 2);
+
+        let device = list.find_with_vid_and_pid(0x1234, 0x9abc).unwrap();
+        assert_eq!(device.vendor_id(), 0x1234);
+        assert_eq!(device.product_id(), 0x9abc);
+        assert!(list.find_with_vid_and_pid(0x1234, 0x0001).is_err());
+    }
+
+    #[test]
+    fn reports_composite_functions() {
+        let backend = SimBackend::new();
+        let function = SimDevice::new(0x1234, 0x5678);
+        function.set_serial_number("SN42");
+        function.set_interface_number(2);
+        backend.add_device(&function);
+
+        let list = DeviceList::with_backend(Arc::new(backend)).unwrap();
+        let device = list.find_with_vid_and_pid(0x1234, 0x5678).unwrap();
+        assert_eq!(device.interface_number(), Some(2));
+        assert_eq!(device.device_id(), r"USB\VID_1234&PID_5678&MI_02\SN42");
+
+        let id = device.instance_id().unwrap();
+        assert_eq!(id.vendor_id(), Some(0x1234));
+        assert_eq!(id.interface_number(), Some(2));
+        assert_eq!(id.instance(), "SN42");
+    }
+
+    #[test]
+    fn iterates_over_all_devices() {
+        let backend = SimBackend::new();
+        backend.add_device(&SimDevice::new(0x1234, 0x0001));
+        backend.add_device(&SimDevice::new(0x1234, 0x0002));
+        backend.add_device(&SimDevice::new(0xabcd, 0x0003));
+
+        let list = DeviceList::with_backend(Arc::new(backend)).unwrap();
+        assert_eq!(list.iter().len(), 3);
+
+        let ids: Vec<_> = list
+            .iter()
+            .map(|d| (d.vendor_id(), d.product_id()))
+            .collect();
+        assert_eq!(ids, [(0x1234, 0x0001), (0x1234, 0x0002), (0xabcd, 0x0003)]);
+
+        let mut opened = 0;
+        for device in &list {
+            device.open().unwrap();
+            opened += 1;
+        }
+        assert_eq!(opened, 3);
+    }
+
+    #[test]
+    fn builder_includes_disconnected_devices() {
+        let backend = SimBackend::new();
+        let unplugged = SimDevice::new(0x1234, 0x0001);
+        unplugged.set_connected(false);
+        backend.add_device(&unplugged);
+        backend.add_device(&SimDevice::new(0x1234, 0x0002));
+        let backend = Arc::new(backend);
+
+        let list = DeviceList::with_backend(backend.clone()).unwrap();
+        assert_eq!(list.length().unwrap(), 1);
+
+        let list = DeviceListBuilder::new()
+            .include_disconnected(true)
+            .build_with_backend(backend)
+            .unwrap();
+        assert_eq!(list.length().unwrap(), 2);
+        let device = list.find_with_vid_and_pid(0x1234, 0x0001).unwrap();
+        assert!(!device.connected());
+        assert!(device.open().is_err());
+    }
+
+    #[test]
+    fn builder_filters_by_pattern() {
+        const GUID: &str = "{6C696275-7362-4B2D-9A2E-0123456789AB}";
+
+        let backend = SimBackend::new();
+        let first = SimDevice::new(0x1234, 0x0001);
+        first.set_interface_guid(GUID);
+        backend.add_device(&first);
+        backend.add_device(&SimDevice::new(0x1234, 0x0002));
+        backend.add_device(&SimDevice::new(0xabcd, 0x0003));
+        let backend = Arc::new(backend);
+
+        let list = DeviceListBuilder::new()
+            .device_id(r"usb\vid_1234&pid_000?*")
+            .build_with_backend(backend.clone())
+            .unwrap();
+        assert_eq!(list.length().unwrap(), 2);
+
+        let list = DeviceListBuilder::new()
+            .interface_guid(&GUID.to_lowercase())
+            .build_with_backend(backend.clone())
+            .unwrap();
+        let products: Vec<_> = list.iter().map(|d| d.product_id()).collect();
+        assert_eq!(products, [0x0001]);
+
+        assert!(DeviceListBuilder::new()
+            .class_guid(&"x".repeat(STRING_LEN))
+            .build_with_backend(backend)
+            .is_err());
+    }
+
+    #[test]
+    fn devices_outlive_their_list() {
+        let backend = SimBackend::new();
+        let sim = SimDevice::new(0x1234, 0x5678);
+        sim.set_serial_number("SN42");
+        backend.add_device(&sim);
+
+        let devices: Vec<Device> = DeviceList::with_backend(Arc::new(backend))
+            .unwrap()
+            .iter()
+            .collect();
+        let device = devices[0].clone();
+        drop(devices);
+
+        assert_eq!(device.vendor_id(), 0x1234);
+        assert_eq!(device.serial_number(), "SN42");
+        device.open().unwrap();
+    }
+
+    #[test]
+    fn list_is_a_snapshot() {
+        let backend = SimBackend::new();
+        let device = SimDevice::new(0x1234, 0x5678);
+        backend.add_device(&device);
+
+        let list = DeviceList::with_backend(Arc::new(backend.clone())).unwrap();
+        backend.remove_device(&device);
+
+        assert_eq!(list.length().unwrap(), 1);
+        assert!(list
+            .find_with_vid_and_pid(0x1234, 0x5678)
+            .unwrap()
+            .open()
+            .is_err());
+        let list = DeviceList::with_backend(Arc::new(backend)).unwrap();
+        assert_eq!(list.length().unwrap(), 0);
     }
 }
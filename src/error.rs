@@ -1,3 +1,5 @@
+use std::fmt;
+use std::io;
 use std::result;
 
 use thiserror::Error;
@@ -6,28 +8,207 @@ use thiserror::Error;
 pub type Result<T> = result::Result<T, Error>;
 
 /// Errors returned by the `libusb` library.
+///
+/// Every variant keeps the Win32 error code it was created from, see [`Error::code`]. Codes are
+/// classified by [`Error::from_code`]; codes without a more specific meaning become
+/// [`Error::Other`].
 #[derive(Error, Debug, Copy, Clone, Eq, PartialEq)]
+#[non_exhaustive]
 pub enum Error {
-    #[error("libusbk error code: `{0}`")]
-    Code(u32),
+    /// The operation timed out.
+    #[error("operation timed out ({})", Win32(*.0))]
+    Timeout(u32),
+    /// The device has been disconnected.
+    #[error("no such device ({})", Win32(*.0))]
+    NoDevice(u32),
+    /// The device or interface is in use.
+    #[error("resource busy ({})", Win32(*.0))]
+    Busy(u32),
+    /// The endpoint stalled, or the device rejected a control request.
+    #[error("pipe error ({})", Win32(*.0))]
+    Pipe(u32),
+    /// The caller lacks the permissions for the operation.
+    #[error("access denied ({})", Win32(*.0))]
+    Access(u32),
+    /// The requested entity was not found.
+    #[error("entity not found ({})", Win32(*.0))]
+    NotFound(u32),
+    /// An argument was rejected by this crate or the driver.
+    #[error("invalid parameter ({})", Win32(*.0))]
+    InvalidParam(u32),
+    /// The device sent more data than the buffer could hold.
+    #[error("overflow ({})", Win32(*.0))]
+    Overflow(u32),
+    /// The transfer failed, or the device returned malformed data.
+    #[error("input/output error ({})", Win32(*.0))]
+    Io(u32),
+    /// The operation was cancelled or aborted.
+    #[error("operation cancelled ({})", Win32(*.0))]
+    Cancelled(u32),
+    /// The driver or operating system does not support the operation.
+    #[error("operation not supported ({})", Win32(*.0))]
+    NotSupported(u32),
+    /// Any other error.
+    #[error("libusbk error ({})", Win32(*.0))]
+    Other(u32),
 }
 
-#[doc(hidden)]
-pub(crate) fn from_libusbk(err: u32) -> Error {
-    match err {
-        i => Error::Code(i),
+impl Error {
+    /// Classifies a Win32 error code, as returned by `GetLastError`.
+    pub fn from_code(code: u32) -> Self {
+        match code {
+            ERROR_SEM_TIMEOUT | WAIT_TIMEOUT | ERROR_TIMEOUT => Error::Timeout(code),
+            ERROR_DEV_NOT_EXIST | ERROR_NO_SUCH_DEVICE | ERROR_DEVICE_NOT_CONNECTED => {
+                Error::NoDevice(code)
+            }
+            ERROR_SHARING_VIOLATION | ERROR_BUSY | ERROR_DEVICE_IN_USE => Error::Busy(code),
+            ERROR_GEN_FAILURE => Error::Pipe(code),
+            ERROR_ACCESS_DENIED => Error::Access(code),
+            ERROR_FILE_NOT_FOUND | ERROR_NO_MORE_ITEMS | ERROR_NOT_FOUND => Error::NotFound(code),
+            ERROR_INVALID_HANDLE | ERROR_INVALID_PARAMETER => Error::InvalidParam(code),
+            ERROR_BUFFER_OVERFLOW | ERROR_INSUFFICIENT_BUFFER | ERROR_MORE_DATA => {
+                Error::Overflow(code)
+            }
+            ERROR_INVALID_DATA | ERROR_CRC | ERROR_IO_DEVICE => Error::Io(code),
+            ERROR_OPERATION_ABORTED | ERROR_CANCELLED => Error::Cancelled(code),
+            ERROR_INVALID_FUNCTION | ERROR_NOT_SUPPORTED | ERROR_CALL_NOT_IMPLEMENTED => {
+                Error::NotSupported(code)
+            }
+            _ => Error::Other(code),
+        }
+    }
+
+    /// Returns the Win32 error code.
+    pub fn code(&self) -> u32 {
+        match *self {
+            Error::Timeout(code)
+            | Error::NoDevice(code)
+            | Error::Busy(code)
+            | Error::Pipe(code)
+            | Error::Access(code)
+            | Error::NotFound(code)
+            | Error::InvalidParam(code)
+            | Error::Overflow(code)
+            | Error::Io(code)
+            | Error::Cancelled(code)
+            | Error::NotSupported(code)
+            | Error::Other(code) => code,
+        }
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(err: Error) -> Self {
+        let kind = match err {
+            Error::Timeout(_) => io::ErrorKind::TimedOut,
+            Error::NoDevice(_) => io::ErrorKind::NotConnected,
+            Error::Busy(_) => io::ErrorKind::ResourceBusy,
+            Error::Pipe(_) => io::ErrorKind::BrokenPipe,
+            Error::Access(_) => io::ErrorKind::PermissionDenied,
+            Error::NotFound(_) => io::ErrorKind::NotFound,
+            Error::InvalidParam(_) => io::ErrorKind::InvalidInput,
+            Error::Io(_) => io::ErrorKind::InvalidData,
+            Error::NotSupported(_) => io::ErrorKind::Unsupported,
+            Error::Overflow(_) | Error::Cancelled(_) | Error::Other(_) => io::ErrorKind::Other,
+        };
+        io::Error::new(kind, err)
+    }
+}
+
+pub(crate) const ERROR_INVALID_FUNCTION: u32 = 1;
+pub(crate) const ERROR_FILE_NOT_FOUND: u32 = 2;
+pub(crate) const ERROR_ACCESS_DENIED: u32 = 5;
+pub(crate) const ERROR_INVALID_HANDLE: u32 = 6;
+/// Returned for malformed descriptors.
+pub(crate) const ERROR_INVALID_DATA: u32 = 13;
+pub(crate) const ERROR_CRC: u32 = 23;
+/// Returned by WinUSB when an endpoint stalls.
+pub(crate) const ERROR_GEN_FAILURE: u32 = 31;
+pub(crate) const ERROR_SHARING_VIOLATION: u32 = 32;
+pub(crate) const ERROR_NOT_SUPPORTED: u32 = 50;
+pub(crate) const ERROR_DEV_NOT_EXIST: u32 = 55;
+/// Returned for arguments rejected before reaching the driver.
+pub(crate) const ERROR_INVALID_PARAMETER: u32 = 87;
+pub(crate) const ERROR_BUFFER_OVERFLOW: u32 = 111;
+pub(crate) const ERROR_CALL_NOT_IMPLEMENTED: u32 = 120;
+/// Returned by WinUSB when a pipe transfer timeout expires.
+pub(crate) const ERROR_SEM_TIMEOUT: u32 = 121;
+pub(crate) const ERROR_INSUFFICIENT_BUFFER: u32 = 122;
+pub(crate) const ERROR_BUSY: u32 = 170;
+pub(crate) const ERROR_MORE_DATA: u32 = 234;
+pub(crate) const WAIT_TIMEOUT: u32 = 258;
+pub(crate) const ERROR_NO_MORE_ITEMS: u32 = 259;
+pub(crate) const ERROR_NO_SUCH_DEVICE: u32 = 433;
+pub(crate) const ERROR_OPERATION_ABORTED: u32 = 995;
+/// Returned when an overlapped transfer was started but has not completed yet.
+pub(crate) const ERROR_IO_PENDING: u32 = 997;
+/// Returned for text that cannot be decoded.
+pub(crate) const ERROR_NO_UNICODE_TRANSLATION: u32 = 1113;
+pub(crate) const ERROR_IO_DEVICE: u32 = 1117;
+pub(crate) const ERROR_DEVICE_NOT_CONNECTED: u32 = 1167;
+pub(crate) const ERROR_NOT_FOUND: u32 = 1168;
+pub(crate) const ERROR_CANCELLED: u32 = 1223;
+pub(crate) const ERROR_TIMEOUT: u32 = 1460;
+pub(crate) const ERROR_DEVICE_IN_USE: u32 = 2404;
+
+/// Displays a Win32 error code together with its system message, where available.
+struct Win32(u32);
+
+impl fmt::Display for Win32 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Win32 error {}", self.0)?;
+        if let Some(message) = system_message(self.0) {
+            write!(f, ": {message}")?;
+        }
+        Ok(())
     }
 }
 
+#[cfg(windows)]
+fn system_message(code: u32) -> Option<String> {
+    use winapi::um::winbase::{
+        FormatMessageW, FORMAT_MESSAGE_FROM_SYSTEM, FORMAT_MESSAGE_IGNORE_INSERTS,
+    };
+
+    let mut buf = [0u16; 512];
+    let len = unsafe {
+        FormatMessageW(
+            FORMAT_MESSAGE_FROM_SYSTEM | FORMAT_MESSAGE_IGNORE_INSERTS,
+            std::ptr::null(),
+            code,
+            0,
+            buf.as_mut_ptr(),
+            buf.len() as u32,
+            std::ptr::null_mut(),
+        )
+    };
+    let message = String::from_utf16_lossy(&buf[..len as usize]);
+    let message = message.trim_end();
+    (!message.is_empty()).then(|| message.to_owned())
+}
+
+#[cfg(not(windows))]
+fn system_message(_code: u32) -> Option<String> {
+    None
+}
+
+#[doc(hidden)]
+pub(crate) fn last_error() -> Error {
+    #[cfg(windows)]
+    let err: u32 = unsafe { winapi::um::errhandlingapi::GetLastError() };
+    #[cfg(not(windows))]
+    let err: u32 = std::io::Error::last_os_error()
+        .raw_os_error()
+        .unwrap_or_default() as u32;
+    Error::from_code(err)
+}
+
 #[doc(hidden)]
 macro_rules! try_unsafe {
     ($x:expr) => {
         match unsafe { $x } {
             0 => {
-                use winapi::um::errhandlingapi::GetLastError;
-
-                let err: u32 = unsafe { GetLastError() };
-                return Err($crate::error::from_libusbk(err));
+                return Err($crate::error::last_error());
             }
             _ => (),
         }
@@ -35,3 +216,49 @@ macro_rules! try_unsafe {
 }
 
 pub(crate) use try_unsafe;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_win32_codes() {
+        assert_eq!(Error::from_code(121), Error::Timeout(121));
+        assert_eq!(Error::from_code(1460), Error::Timeout(1460));
+        assert_eq!(Error::from_code(31), Error::Pipe(31));
+        assert_eq!(Error::from_code(1167), Error::NoDevice(1167));
+        assert_eq!(Error::from_code(170), Error::Busy(170));
+        assert_eq!(Error::from_code(5), Error::Access(5));
+        assert_eq!(Error::from_code(1168), Error::NotFound(1168));
+        assert_eq!(Error::from_code(87), Error::InvalidParam(87));
+        assert_eq!(Error::from_code(234), Error::Overflow(234));
+        assert_eq!(Error::from_code(13), Error::Io(13));
+        assert_eq!(Error::from_code(995), Error::Cancelled(995));
+        assert_eq!(Error::from_code(50), Error::NotSupported(50));
+        assert_eq!(Error::from_code(1113), Error::Other(1113));
+    }
+
+    #[test]
+    fn keeps_the_win32_code() {
+        for code in [0, 1, 31, 121, 995, 1167, 0xE000_0001] {
+            assert_eq!(Error::from_code(code).code(), code);
+        }
+    }
+
+    #[test]
+    fn converts_to_io_errors() {
+        let err = io::Error::from(Error::from_code(121));
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        let inner = err.get_ref().unwrap().downcast_ref::<Error>();
+        assert_eq!(inner, Some(&Error::Timeout(121)));
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn displays_kind_and_code() {
+        assert_eq!(
+            Error::from_code(121).to_string(),
+            "operation timed out (Win32 error 121)"
+        );
+    }
+}
@@ -0,0 +1,259 @@
+/// Transfer and endpoint directions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    /// Direction for read (device to host) transfers.
+    In,
+    /// Direction for write (host to device) transfers.
+    Out,
+}
+
+/// The speed a device is connected at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Speed {
+    /// 1.5 Mbit/s.
+    Low,
+    /// 12 Mbit/s.
+    Full,
+    /// 480 Mbit/s.
+    High,
+    /// 5 Gbit/s or faster.
+    Super,
+}
+
+/// An endpoint's transfer type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TransferType {
+    /// Control endpoint.
+    Control,
+    /// Isochronous endpoint.
+    Isochronous,
+    /// Bulk endpoint.
+    Bulk,
+    /// Interrupt endpoint.
+    Interrupt,
+}
+
+/// Isochronous synchronization mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SyncType {
+    /// No synchronisation.
+    NoSync,
+    /// Asynchronous.
+    Asynchronous,
+    /// Adaptive.
+    Adaptive,
+    /// Synchronous.
+    Synchronous,
+}
+
+/// Isochronous usage type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum UsageType {
+    /// Data endpoint.
+    Data,
+    /// Feedback endpoint.
+    Feedback,
+    /// Explicit feedback data endpoint.
+    FeedbackData,
+    /// Reserved.
+    Reserved,
+}
+
+/// Types of control transfers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RequestType {
+    /// Requests that are defined by the USB standard.
+    Standard,
+    /// Requests that are defined by a device class, e.g., HID.
+    Class,
+    /// Vendor-specific requests.
+    Vendor,
+    /// Reserved for future use.
+    Reserved,
+}
+
+/// Recipients of control transfers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Recipient {
+    /// The recipient is a device.
+    Device,
+    /// The recipient is an interface.
+    Interface,
+    /// The recipient is an endpoint.
+    Endpoint,
+    /// Other.
+    Other,
+}
+
+/// A three-part version consisting of major, minor, and sub minor components.
+///
+/// This can be used to represent versions of the format `J.M.N`, where `J` is the major version,
+/// `M` is the minor version, and `N` is the sub minor version. A version is constructed by
+/// providing the fields in the same order to the tuple. For example:
+///
+/// ```
+/// libusbk::Version(0, 2, 1);
+/// ```
+///
+/// represents the version 0.2.1.
+///
+/// The intended use case of `Version` is to extract meaning from the version fields in USB
+/// descriptors, such as `bcdUSB` and `bcdDevice` in device descriptors.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Version(pub u8, pub u8, pub u8);
+
+impl Version {
+    /// Extracts a version from a binary coded decimal (BCD) field. BCD fields exist in USB
+    /// descriptors as 16-bit integers encoding a version as `0xJJMN`, where `JJ` is the major
+    /// version, `M` is the minor version, and `N` is the sub minor version. For example, 2.0 is
+    /// encoded as `0x0200` and 1.1 is encoded as `0x0110`.
+    pub fn from_bcd(mut raw: u16) -> Self {
+        let sub_minor: u8 = (raw & 0x000F) as u8;
+        raw >>= 4;
+
+        let minor: u8 = (raw & 0x000F) as u8;
+        raw >>= 4;
+
+        let mut major: u8 = (raw & 0x000F) as u8;
+        raw >>= 4;
+
+        major += (10 * raw) as u8;
+
+        Version(major, minor, sub_minor)
+    }
+
+    /// Returns the major version.
+    pub fn major(self) -> u8 {
+        let Version(major, _, _) = self;
+        major
+    }
+
+    /// Returns the minor version.
+    pub fn minor(self) -> u8 {
+        let Version(_, minor, _) = self;
+        minor
+    }
+
+    /// Returns the sub minor version.
+    pub fn sub_minor(self) -> u8 {
+        let Version(_, _, sub_minor) = self;
+        sub_minor
+    }
+}
+
+impl std::fmt::Display for Version {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}.{}", self.major(), self.minor(), self.sub_minor())
+    }
+}
+
+/// Builds a value for the `bmRequestType` field of a control transfer setup packet.
+///
+/// The `bmRequestType` field of a USB control transfer setup packet is a bit field specifying
+/// three parameters, which are given to this function by corresponding enum values.
+///
+/// ## Examples
+///
+/// The following example returns a `bmRequestType` value for a standard inbound transfer to the
+/// device, which could be used for reading a device's descriptors:
+///
+/// ```no_run
+/// use libusbk::{Direction, RequestType, Recipient};
+///
+/// libusbk::request_type(Direction::In, RequestType::Standard, Recipient::Device);
+/// ```
+pub const fn request_type(
+    direction: Direction,
+    request_type: RequestType,
+    recipient: Recipient,
+) -> u8 {
+    let mut value: u8 = match direction {
+        Direction::Out => ENDPOINT_OUT,
+        Direction::In => ENDPOINT_IN,
+    };
+
+    value |= match request_type {
+        RequestType::Standard => REQUEST_TYPE_STANDARD,
+        RequestType::Class => REQUEST_TYPE_CLASS,
+        RequestType::Vendor => REQUEST_TYPE_VENDOR,
+        RequestType::Reserved => REQUEST_TYPE_RESERVED,
+    };
+
+    value |= match recipient {
+        Recipient::Device => RECIPIENT_DEVICE,
+        Recipient::Interface => RECIPIENT_INTERFACE,
+        Recipient::Endpoint => RECIPIENT_ENDPOINT,
+        Recipient::Other => RECIPIENT_OTHER,
+    };
+
+    value
+}
+
+pub(crate) const ENDPOINT_DIR_MASK: u8 = 0x80;
+pub(crate) const ENDPOINT_IN: u8 = 0x80;
+pub(crate) const ENDPOINT_OUT: u8 = 0x00;
+
+pub(crate) const DESCRIPTOR_TYPE_DEVICE: u8 = 0x01;
+pub(crate) const DESCRIPTOR_TYPE_CONFIG: u8 = 0x02;
+pub(crate) const DESCRIPTOR_TYPE_STRING: u8 = 0x03;
+pub(crate) const DESCRIPTOR_TYPE_INTERFACE: u8 = 0x04;
+pub(crate) const DESCRIPTOR_TYPE_ENDPOINT: u8 = 0x05;
+pub(crate) const DESCRIPTOR_TYPE_BOS: u8 = 0x0F;
+pub(crate) const DESCRIPTOR_TYPE_DEVICE_CAPABILITY: u8 = 0x10;
+pub(crate) const DESCRIPTOR_TYPE_SS_ENDPOINT_COMPANION: u8 = 0x30;
+
+/// The `QueryDeviceInformation` type for the speed of the device.
+pub(crate) const DEVICE_INFORMATION_SPEED: u32 = 0x01;
+pub(crate) const SPEED_LOW: u8 = 0x01;
+pub(crate) const SPEED_FULL: u8 = 0x02;
+pub(crate) const SPEED_HIGH: u8 = 0x03;
+
+pub(crate) const REQUEST_CLEAR_FEATURE: u8 = 0x01;
+pub(crate) const REQUEST_SET_FEATURE: u8 = 0x03;
+pub(crate) const REQUEST_GET_DESCRIPTOR: u8 = 0x06;
+
+pub(crate) const FEATURE_ENDPOINT_HALT: u16 = 0x00;
+pub(crate) const FEATURE_DEVICE_REMOTE_WAKEUP: u16 = 0x01;
+
+const REQUEST_TYPE_STANDARD: u8 = 0x00 << 5;
+const REQUEST_TYPE_CLASS: u8 = 0x01 << 5;
+const REQUEST_TYPE_VENDOR: u8 = 0x02 << 5;
+const REQUEST_TYPE_RESERVED: u8 = 0x03 << 5;
+
+const RECIPIENT_DEVICE: u8 = 0x00;
+const RECIPIENT_INTERFACE: u8 = 0x01;
+const RECIPIENT_ENDPOINT: u8 = 0x02;
+const RECIPIENT_OTHER: u8 = 0x03;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn version_from_bcd() {
+        assert_eq!(Version::from_bcd(0x0200), Version(2, 0, 0));
+        assert_eq!(Version::from_bcd(0x0110), Version(1, 1, 0));
+        assert_eq!(Version::from_bcd(0x1234), Version(12, 3, 4));
+        assert_eq!(Version(3, 2, 0).to_string(), "3.2.0");
+    }
+
+    #[test]
+    fn request_type_builds_bitfield() {
+        assert_eq!(
+            request_type(Direction::In, RequestType::Standard, Recipient::Device),
+            0x80
+        );
+        assert_eq!(
+            request_type(Direction::Out, RequestType::Vendor, Recipient::Interface),
+            0x41
+        );
+        assert_eq!(
+            request_type(Direction::In, RequestType::Class, Recipient::Endpoint),
+            0xA2
+        );
+        assert_eq!(
+            request_type(Direction::Out, RequestType::Reserved, Recipient::Other),
+            0x63
+        );
+    }
+}
@@ -1,4 +1,3 @@
-use std::ffi::c_void;
 use std::sync::Mutex;
 
 use libusbk_sys::{
@@ -6,7 +5,8 @@ use libusbk_sys::{
 };
 use once_cell::sync::OnceCell;
 
-use crate::device::{self, Device};
+use crate::backend;
+use crate::device::Device;
 use crate::error::{try_unsafe, Result};
 
 static DATA: OnceCell<Mutex<Option<Data>>> = OnceCell::new();
@@ -24,7 +24,7 @@ pub trait Hotplug: Send {
     fn device_left(&mut self, device: Device);
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Default, Copy, Clone)]
 pub struct HotplugBuilder {
     vendor_id: Option<i32>,
     product_id: Option<i32>,
@@ -56,21 +56,11 @@ impl HotplugBuilder {
         });
         Ok(Registration {
             params: self.params,
-            handle: 0 as *mut c_void,
+            handle: std::ptr::null_mut(),
         })
     }
 }
 
-impl Default for HotplugBuilder {
-    fn default() -> Self {
-        Self {
-            vendor_id: None,
-            product_id: None,
-            params: libusbk_sys::_KHOT_PARAMS::default(),
-        }
-    }
-}
-
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum NotificationType {
     Arrival,
@@ -106,7 +96,7 @@ impl Registration {
         let vid = data_s.vid;
         let pid = data_s.pid;
 
-        let device = device::Device(device_info);
+        let device = Device::snapshot(&*device_info, backend::default());
 
         if let Some(vid) = vid {
             if vid != device.vendor_id() as i32 {
@@ -0,0 +1,166 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::error::{Error, ERROR_INVALID_DATA};
+
+/// A parsed Windows device instance ID, such as `USB\VID_04D8&PID_FA2E&MI_00\6&2C4E1E0&0&0000`.
+///
+/// An instance ID has the form `<enumerator>\<device ID>\<instance>`. For USB devices the device
+/// ID carries the vendor and product IDs and, for functions of a composite device, the interface
+/// number. Root hubs, composite parents of non-USB buses and other IDs may lack some or all of
+/// these, which is reported as `None` rather than an error.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct InstanceId {
+    enumerator: String,
+    device_id: String,
+    instance: String,
+    vendor_id: Option<u16>,
+    product_id: Option<u16>,
+    interface_number: Option<u8>,
+}
+
+impl InstanceId {
+    /// Returns the enumerator that created the device, e.g. `USB`.
+    pub fn enumerator(&self) -> &str {
+        &self.enumerator
+    }
+
+    /// Returns the device ID part, e.g. `VID_04D8&PID_FA2E&MI_00`.
+    pub fn device_id(&self) -> &str {
+        &self.device_id
+    }
+
+    /// Returns the instance-specific part, which is the serial number for many USB devices. This
+    /// is empty if the ID has no such part.
+    pub fn instance(&self) -> &str {
+        &self.instance
+    }
+
+    /// Returns the vendor ID from the `VID_xxxx` part, if present.
+    pub fn vendor_id(&self) -> Option<u16> {
+        self.vendor_id
+    }
+
+    /// Returns the product ID from the `PID_xxxx` part, if present.
+    pub fn product_id(&self) -> Option<u16> {
+        self.product_id
+    }
+
+    /// Returns the interface number from the `MI_xx` part, if present.
+    pub fn interface_number(&self) -> Option<u8> {
+        self.interface_number
+    }
+}
+
+impl FromStr for InstanceId {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(3, '\\');
+        let enumerator = parts.next().unwrap_or_default();
+        let device_id = parts.next().unwrap_or_default();
+        let instance = parts.next().unwrap_or_default();
+        if enumerator.is_empty() || device_id.is_empty() {
+            return Err(Error::from_code(ERROR_INVALID_DATA));
+        }
+
+        let mut id = InstanceId {
+            enumerator: enumerator.to_owned(),
+            device_id: device_id.to_owned(),
+            instance: instance.to_owned(),
+            vendor_id: None,
+            product_id: None,
+            interface_number: None,
+        };
+        for field in device_id.split('&') {
+            if let Some(vid) = hex_field(field, "VID_", 4) {
+                id.vendor_id = Some(vid);
+            } else if let Some(pid) = hex_field(field, "PID_", 4) {
+                id.product_id = Some(pid);
+            } else if let Some(mi) = hex_field(field, "MI_", 2) {
+                id.interface_number = Some(mi as u8);
+            }
+        }
+        Ok(id)
+    }
+}
+
+impl fmt::Display for InstanceId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}\\{}", self.enumerator, self.device_id)?;
+        if !self.instance.is_empty() {
+            write!(f, "\\{}", self.instance)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses `<prefix><digits>` where `digits` is exactly `len` hex digits, ignoring ASCII case.
+fn hex_field(field: &str, prefix: &str, len: usize) -> Option<u16> {
+    if field.len() != prefix.len() + len
+        || !field.is_char_boundary(prefix.len())
+        || !field[..prefix.len()].eq_ignore_ascii_case(prefix)
+    {
+        return None;
+    }
+    let digits = &field[prefix.len()..];
+    if !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    u16::from_str_radix(digits, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_usb_device() {
+        let id: InstanceId = r"USB\VID_04D8&PID_FA2E\LUSBW1".parse().unwrap();
+        assert_eq!(id.enumerator(), "USB");
+        assert_eq!(id.device_id(), "VID_04D8&PID_FA2E");
+        assert_eq!(id.instance(), "LUSBW1");
+        assert_eq!(id.vendor_id(), Some(0x04D8));
+        assert_eq!(id.product_id(), Some(0xFA2E));
+        assert_eq!(id.interface_number(), None);
+        assert_eq!(id.to_string(), r"USB\VID_04D8&PID_FA2E\LUSBW1");
+    }
+
+    #[test]
+    fn parses_composite_function() {
+        let id: InstanceId = r"usb\vid_046d&pid_c52b&mi_02\7&1A2B3C4D&0&0002"
+            .parse()
+            .unwrap();
+        assert_eq!(id.vendor_id(), Some(0x046D));
+        assert_eq!(id.product_id(), Some(0xC52B));
+        assert_eq!(id.interface_number(), Some(2));
+        assert_eq!(id.instance(), "7&1A2B3C4D&0&0002");
+    }
+
+    #[test]
+    fn tolerates_ids_without_usb_fields() {
+        let hub: InstanceId = r"USB\ROOT_HUB30\4&2F1C0B5E&0&0".parse().unwrap();
+        assert_eq!(hub.vendor_id(), None);
+        assert_eq!(hub.product_id(), None);
+        assert_eq!(hub.interface_number(), None);
+
+        let pci: InstanceId = r"PCI\VEN_8086&DEV_A36D&SUBSYS_00000000&REV_10\3&11583659&0&A0"
+            .parse()
+            .unwrap();
+        assert_eq!(pci.enumerator(), "PCI");
+        assert_eq!(pci.vendor_id(), None);
+
+        let truncated: InstanceId = r"USB\VID_12&PID_XYZW&MI_1".parse().unwrap();
+        assert_eq!(truncated.vendor_id(), None);
+        assert_eq!(truncated.product_id(), None);
+        assert_eq!(truncated.interface_number(), None);
+        assert_eq!(truncated.instance(), "");
+    }
+
+    #[test]
+    fn rejects_malformed_ids() {
+        assert!("".parse::<InstanceId>().is_err());
+        assert!("USB".parse::<InstanceId>().is_err());
+        assert!(r"\VID_1234".parse::<InstanceId>().is_err());
+    }
+}
@@ -0,0 +1,215 @@
+use std::ops::Deref;
+
+use crate::config_descriptor::{EndpointDescriptor, Interface};
+use crate::device_handle::DeviceHandle;
+use crate::Result;
+
+/// Identifies an interface of an open device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum InterfaceSelector {
+    /// The interface's `bInterfaceNumber`.
+    Number(u8),
+    /// The position of the interface in the active configuration, starting at zero.
+    Index(u8),
+}
+
+impl InterfaceSelector {
+    /// Splits the selector into the `NumberOrIndex` and `IsIndex` arguments taken by `libusbK`.
+    pub(crate) fn to_raw(self) -> (u8, bool) {
+        match self {
+            InterfaceSelector::Number(number) => (number, false),
+            InterfaceSelector::Index(index) => (index, true),
+        }
+    }
+}
+
+/// An interface claimed through [`DeviceHandle::claim_interface`], released when dropped.
+///
+/// The guard borrows the handle it was claimed from and dereferences to it, so the handle stays
+/// usable for transfers while the interface is claimed, including for claiming further
+/// interfaces.
+#[derive(Debug)]
+pub struct ClaimedInterface<'a> {
+    pub(crate) handle: &'a DeviceHandle,
+    pub(crate) interface: Interface,
+    pub(crate) alt_setting: u8,
+}
+
+impl ClaimedInterface<'_> {
+    /// Returns the interface's number.
+    pub fn number(&self) -> u8 {
+        self.interface.number()
+    }
+
+    /// Returns the interface with all its alternate settings.
+    pub fn descriptor(&self) -> &Interface {
+        &self.interface
+    }
+
+    /// Returns the alternate setting the interface is using.
+    pub fn alt_setting(&self) -> u8 {
+        self.alt_setting
+    }
+
+    /// Switches the interface to one of its alternate settings.
+    pub fn set_alternate_setting(&mut self, setting: u8) -> Result<()> {
+        let number = self.number();
+        self.handle
+            .set_alternate_setting(InterfaceSelector::Number(number), setting)?;
+        self.alt_setting = setting;
+        Ok(())
+    }
+
+    /// Returns the endpoints of the alternate setting the interface is using.
+    pub fn endpoints(&self) -> impl Iterator<Item = &EndpointDescriptor> {
+        self.interface
+            .descriptors()
+            .filter(|setting| setting.setting_number() == self.alt_setting)
+            .flat_map(|setting| setting.endpoint_descriptors())
+    }
+
+    /// Releases the interface, returning the error that dropping the guard would ignore.
+    pub fn release(self) -> Result<()> {
+        let number = self.number();
+        self.handle
+            .release_interface(InterfaceSelector::Number(number))
+    }
+}
+
+impl Deref for ClaimedInterface<'_> {
+    type Target = DeviceHandle;
+
+    fn deref(&self) -> &DeviceHandle {
+        self.handle
+    }
+}
+
+impl Drop for ClaimedInterface<'_> {
+    fn drop(&mut self) {
+        let number = self.number();
+        if self.handle.is_claimed(number) {
+            let _ = self
+                .handle
+                .release_interface(InterfaceSelector::Number(number));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::sim::tests::open;
+    use crate::backend::{SimConfigBuilder, SimDevice, SimOp};
+    use crate::error::Error;
+
+    #[test]
+    fn guards_pipe_io_to_claimed_interfaces() {
+        let device = SimDevice::new(0x1234, 0x5678);
+        device.set_interfaces(&[0, 1]);
+        device.set_alt_setting_count(1, 2);
+        device.set_config(
+            0,
+            SimConfigBuilder::new(1)
+                .interface(0, 0)
+                .endpoint(0x81, 0x02, 512, 0) // bulk in
+                .interface(1, 0)
+                .interface(1, 1)
+                .endpoint(0x82, 0x01, 1024, 1), // iso in
+        );
+        let handle = open(&device);
+        let mut buf = [0; 8];
+
+        assert!(matches!(
+            handle.read_pipe(0x82, &mut buf),
+            Err(Error::Access(_))
+        ));
+
+        let mut streaming = handle
+            .claim_interface(InterfaceSelector::Number(1))
+            .unwrap();
+        assert_eq!(streaming.endpoints().count(), 0);
+        streaming.set_alternate_setting(1).unwrap();
+        assert_eq!(streaming.alt_setting(), 1);
+        let addresses: Vec<_> = streaming.endpoints().map(|e| e.address()).collect();
+        assert_eq!(addresses, [0x82]);
+
+        device.push_read(0x81, &[2]);
+        device.push_read(0x82, &[3]);
+        assert!(matches!(
+            streaming.read_pipe(0x81, &mut buf),
+            Err(Error::Access(_))
+        ));
+        assert_eq!(streaming.read_pipe(0x82, &mut buf).unwrap(), 1);
+        assert_eq!(buf[0], 3);
+        // The default control pipe belongs to no interface.
+        assert!(streaming.check_claimed(0x00).is_ok());
+
+        streaming.release().unwrap();
+        assert!(device.claimed_interfaces().is_empty());
+        assert!(matches!(
+            handle.read_pipe(0x82, &mut buf),
+            Err(Error::Access(_))
+        ));
+    }
+
+    #[test]
+    fn drop_releases_claimed_interfaces() {
+        let device = SimDevice::new(0x1234, 0x5678);
+        device.set_interfaces(&[0, 2]);
+        let handle = open(&device);
+        assert_eq!(device.open_handles(), 1);
+
+        let first = handle
+            .claim_interface(InterfaceSelector::Number(0))
+            .unwrap();
+        let second = handle.claim_interface(InterfaceSelector::Index(1)).unwrap();
+        assert_eq!(second.number(), 2);
+        assert_eq!(device.claimed_interfaces(), vec![0, 2]);
+        drop(second);
+        assert_eq!(device.claimed_interfaces(), vec![0]);
+        assert!(first.claim_interface(InterfaceSelector::Number(1)).is_err());
+
+        // Interfaces a guard failed to release are released with the handle.
+        device.inject_error(SimOp::ReleaseInterface, 31);
+        drop(first);
+        assert_eq!(device.claimed_interfaces(), vec![0]);
+        drop(handle);
+        assert!(device.claimed_interfaces().is_empty());
+        assert_eq!(device.open_handles(), 0);
+    }
+
+    #[test]
+    fn manages_interfaces_and_alternate_settings() {
+        use InterfaceSelector::{Index, Number};
+
+        let device = SimDevice::new(0x1234, 0x5678);
+        device.set_interfaces(&[0, 1]);
+        device.set_alt_setting_count(1, 3);
+        let handle = open(&device);
+
+        let first = handle.claim_interface(Number(0)).unwrap();
+        let second = handle.claim_interface(Number(1)).unwrap();
+        assert_eq!(device.selected_interface(), Some(1));
+        handle.select_interface(Index(0)).unwrap();
+        assert_eq!(device.selected_interface(), Some(0));
+
+        assert_eq!(handle.alternate_setting(Number(1)).unwrap(), 0);
+        handle.set_alternate_setting(Number(1), 2).unwrap();
+        assert_eq!(handle.alternate_setting(Index(1)).unwrap(), 2);
+        assert!(handle.set_alternate_setting(Number(1), 3).is_err());
+        assert!(handle.set_alternate_setting(Number(0), 1).is_err());
+
+        handle.release_interface(Index(1)).unwrap();
+        assert_eq!(device.claimed_interfaces(), vec![0]);
+        assert!(handle.release_interface(Number(1)).is_err());
+        // The guard does not release the interface a second time.
+        drop(second);
+        device.inject_error(SimOp::ReleaseInterface, 31);
+        assert!(handle.release_interface(Number(0)).is_err());
+        assert_eq!(device.claimed_interfaces(), vec![0]);
+
+        // Only the interface that is still claimed is released on drop.
+        drop(first);
+        assert!(device.claimed_interfaces().is_empty());
+    }
+}
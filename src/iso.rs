@@ -0,0 +1,301 @@
+use libusbk_sys::KISOCH_PACKET_INFORMATION;
+
+use crate::error::{Error, ERROR_INVALID_PARAMETER};
+use crate::Result;
+
+/// One packet of an [`IsoTransfer`], covering a fixed region of the transfer buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IsoPacket {
+    pub(crate) offset: u32,
+    pub(crate) size: u32,
+    pub(crate) length: u32,
+    pub(crate) status: u32,
+}
+
+impl IsoPacket {
+    /// Returns the offset of the packet in the transfer buffer.
+    pub fn offset(&self) -> u32 {
+        self.offset
+    }
+
+    /// Returns the number of bytes reserved for the packet.
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    /// Returns the number of bytes the device sent in the packet, once the transfer completed.
+    pub fn length(&self) -> u32 {
+        self.length
+    }
+
+    /// Returns the `USBD_STATUS` code reported for the packet, zero on success.
+    pub fn status(&self) -> u32 {
+        self.status
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.status == 0
+    }
+}
+
+/// How an isochronous pipe is scheduled, as computed by `IsochK_CalcPacketInformation` for
+/// [`DeviceHandle::iso_packet_information`](crate::DeviceHandle::iso_packet_information).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IsoPacketInformation {
+    packets_per_frame: u32,
+    polling_period_us: u32,
+    bytes_per_ms: u32,
+    packet_size: u32,
+}
+
+impl IsoPacketInformation {
+    /// Wraps the schedule computed for a pipe transferring up to `packet_size` bytes every
+    /// service interval.
+    pub(crate) fn from_native(info: &KISOCH_PACKET_INFORMATION, packet_size: u32) -> Self {
+        Self {
+            packets_per_frame: info.PacketsPerFrame,
+            polling_period_us: info.PollingPeriodMicroseconds,
+            bytes_per_ms: info.BytesPerMillisecond,
+            packet_size,
+        }
+    }
+
+    /// Returns the number of packets the endpoint transfers every 1 ms frame.
+    pub fn packets_per_frame(&self) -> u32 {
+        self.packets_per_frame
+    }
+
+    /// Returns the time between packets, in microseconds.
+    pub fn polling_period_us(&self) -> u32 {
+        self.polling_period_us
+    }
+
+    /// Returns the maximum number of bytes the endpoint transfers per millisecond.
+    pub fn bytes_per_ms(&self) -> u32 {
+        self.bytes_per_ms
+    }
+
+    /// Returns the maximum number of bytes in one packet, which is the pipe's
+    /// [`max_bytes_per_interval`](crate::PipeInfo::max_bytes_per_interval).
+    pub fn packet_size(&self) -> u32 {
+        self.packet_size
+    }
+}
+
+/// A buffer divided into packets for an isochronous transfer.
+///
+/// Transfers are run with [`DeviceHandle::read_iso`] and [`DeviceHandle::write_iso`], which
+/// fill in the length and status of every packet. A transfer can be run again after
+/// [`reuse`](Self::reuse).
+///
+/// [`DeviceHandle::read_iso`]: crate::DeviceHandle::read_iso
+/// [`DeviceHandle::write_iso`]: crate::DeviceHandle::write_iso
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IsoTransfer {
+    buffer: Vec<u8>,
+    packets: Vec<IsoPacket>,
+    start_frame: Option<u32>,
+}
+
+impl IsoTransfer {
+    /// Creates a transfer of `num_packets` packets of `packet_size` bytes each.
+    ///
+    /// Fails with [`Error::InvalidParam`] if the buffer would not fit the 32-bit offsets and
+    /// lengths the drivers take.
+    pub fn new(num_packets: usize, packet_size: u32) -> Result<Self> {
+        let num_packets = u32::try_from(num_packets).map_err(|_| invalid_param())?;
+        let len = num_packets
+            .checked_mul(packet_size)
+            .ok_or_else(invalid_param)?;
+        let packets = (0..num_packets)
+            .map(|i| {
+                Ok(IsoPacket {
+                    offset: i.checked_mul(packet_size).ok_or_else(invalid_param)?,
+                    size: packet_size,
+                    length: 0,
+                    status: 0,
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            buffer: vec![0; len as usize],
+            packets,
+            start_frame: None,
+        })
+    }
+
+    /// Creates a transfer covering `ms` milliseconds of the pipe described by `info`.
+    ///
+    /// Fails with [`Error::InvalidParam`] if the transfer would be too large for
+    /// [`new`](Self::new).
+    pub fn for_duration(info: &IsoPacketInformation, ms: u32) -> Result<Self> {
+        let num_packets = ms
+            .checked_mul(1000)
+            .and_then(|us| us.checked_div(info.polling_period_us()))
+            .ok_or_else(invalid_param)?;
+        Self::new(num_packets.max(1) as usize, info.packet_size())
+    }
+
+    /// Returns the whole transfer buffer.
+    pub fn buffer(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    /// Returns the whole transfer buffer, e.g. to fill in the packets of a write.
+    pub fn buffer_mut(&mut self) -> &mut [u8] {
+        &mut self.buffer
+    }
+
+    pub fn packets(&self) -> &[IsoPacket] {
+        &self.packets
+    }
+
+    /// Returns the bytes transferred in the packet at `index`.
+    pub fn packet_data(&self, index: usize) -> Option<&[u8]> {
+        let packet = self.packets.get(index)?;
+        let start = packet.offset as usize;
+        let len = packet.length.min(packet.size) as usize;
+        self.buffer.get(start..start + len)
+    }
+
+    /// Returns an iterator over the packets together with the bytes transferred in each.
+    pub fn iter(&self) -> impl Iterator<Item = (&IsoPacket, &[u8])> {
+        self.packets
+            .iter()
+            .enumerate()
+            .map(|(i, packet)| (packet, self.packet_data(i).unwrap_or_default()))
+    }
+
+    /// Returns the number of packets that failed.
+    pub fn error_count(&self) -> usize {
+        self.packets.iter().filter(|p| !p.is_ok()).count()
+    }
+
+    /// Returns the frame the transfer starts in. After the transfer ran, this is the frame it
+    /// actually started in, if the driver reports it.
+    pub fn start_frame(&self) -> Option<u32> {
+        self.start_frame
+    }
+
+    /// Sets the frame to start the transfer in, e.g. a few frames after
+    /// [`DeviceHandle::current_frame_number`](crate::DeviceHandle::current_frame_number). With
+    /// `None` the transfer starts as soon as possible.
+    pub fn set_start_frame(&mut self, frame: Option<u32>) {
+        self.start_frame = frame;
+    }
+
+    /// Clears the results of the last transfer so the buffer can be used again. The transfer
+    /// will start as soon as possible unless a start frame is set again.
+    pub fn reuse(&mut self) {
+        for packet in &mut self.packets {
+            packet.length = 0;
+            packet.status = 0;
+        }
+        self.start_frame = None;
+    }
+
+    pub(crate) fn parts_mut(&mut self) -> (&mut [u8], &mut [IsoPacket], Option<u32>) {
+        (&mut self.buffer, &mut self.packets, self.start_frame)
+    }
+}
+
+fn invalid_param() -> Error {
+    Error::from_code(ERROR_INVALID_PARAMETER)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::backend::sim::tests::open;
+    use crate::backend::SimDevice;
+
+    #[test]
+    fn lays_out_packets() {
+        let mut transfer = IsoTransfer::new(3, 192).unwrap();
+        assert_eq!(transfer.buffer().len(), 576);
+        let offsets: Vec<_> = transfer.packets().iter().map(|p| p.offset()).collect();
+        assert_eq!(offsets, [0, 192, 384]);
+
+        let (buffer, packets, _) = transfer.parts_mut();
+        buffer[192..195].copy_from_slice(&[1, 2, 3]);
+        packets[1].length = 3;
+        packets[2].status = 0xC000_0011;
+        transfer.set_start_frame(Some(100));
+
+        assert_eq!(transfer.packet_data(0), Some(&[][..]));
+        assert_eq!(transfer.packet_data(1), Some(&[1, 2, 3][..]));
+        assert_eq!(transfer.packet_data(3), None);
+        let lengths: Vec<_> = transfer.iter().map(|(_, data)| data.len()).collect();
+        assert_eq!(lengths, [0, 3, 0]);
+        assert_eq!(transfer.error_count(), 1);
+
+        transfer.reuse();
+        assert_eq!(transfer.error_count(), 0);
+        assert_eq!(transfer.packet_data(1), Some(&[][..]));
+        assert_eq!(transfer.start_frame(), None);
+    }
+
+    #[test]
+    fn sizes_transfers_from_packet_information() {
+        let native = KISOCH_PACKET_INFORMATION {
+            PacketsPerFrame: 8,
+            PollingPeriodMicroseconds: 125,
+            BytesPerMillisecond: 24576,
+        };
+        let info = IsoPacketInformation::from_native(&native, 3072);
+        assert_eq!(info.packets_per_frame(), 8);
+        assert_eq!(info.polling_period_us(), 125);
+        assert_eq!(info.bytes_per_ms(), 24576);
+
+        let transfer = IsoTransfer::for_duration(&info, 4).unwrap();
+        assert_eq!(transfer.packets().len(), 32);
+        assert_eq!(transfer.packets()[1].offset(), 3072);
+        assert!(IsoTransfer::for_duration(&info, u32::MAX).is_err());
+
+        let unscheduled = IsoPacketInformation::from_native(&Default::default(), 3072);
+        assert!(IsoTransfer::for_duration(&unscheduled, 4).is_err());
+    }
+
+    #[test]
+    fn rejects_transfers_beyond_32_bits() {
+        assert!(matches!(
+            IsoTransfer::new(2, u32::MAX),
+            Err(Error::InvalidParam(_))
+        ));
+        assert!(IsoTransfer::new(usize::MAX, 2).is_err());
+        assert_eq!(IsoTransfer::new(4, 0).unwrap().buffer().len(), 0);
+    }
+
+    #[test]
+    fn isochronous_transfers() {
+        let device = SimDevice::new(0x1234, 0x5678);
+        device.set_frame_number(100);
+        let handle = open(&device);
+        let timeout = Duration::from_secs(1);
+
+        device.push_read(0x81, &[1, 2, 3]);
+        device.push_read(0x81, &[4; 12]);
+        let mut transfer = IsoTransfer::new(3, 8).unwrap();
+        handle.read_iso(0x81, &mut transfer, timeout).unwrap();
+        assert_eq!(transfer.start_frame(), Some(100));
+        assert_eq!(transfer.packet_data(0), Some(&[1, 2, 3][..]));
+        assert_eq!(transfer.packet_data(1), Some(&[4; 8][..]));
+        assert_eq!(transfer.packet_data(2), Some(&[][..]));
+        assert_eq!(handle.current_frame_number().unwrap(), 103);
+
+        transfer.reuse();
+        transfer.set_start_frame(Some(110));
+        transfer.buffer_mut().fill(5);
+        handle.write_iso(0x01, &mut transfer, timeout).unwrap();
+        assert_eq!(transfer.start_frame(), Some(110));
+        assert_eq!(device.take_writes(0x01), vec![vec![5; 8]; 3]);
+        assert!(transfer.packets().iter().all(|p| p.length() == 8));
+
+        assert!(matches!(
+            handle.write_iso(0x81, &mut transfer, timeout),
+            Err(Error::InvalidParam(_))
+        ));
+    }
+}
@@ -0,0 +1,106 @@
+use std::fmt;
+
+use crate::config_descriptor::header;
+use crate::fields::DESCRIPTOR_TYPE_STRING;
+
+/// A language supported by a device's string descriptors.
+///
+/// Languages are identified by a USB LANGID: a 10-bit primary language ID and a 6-bit
+/// sublanguage ID, as defined in the USB "Language Identifiers (LANGIDs)" document.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Language {
+    raw: u16,
+}
+
+impl Language {
+    /// English (United States), the language most devices provide strings in.
+    pub const ENGLISH_US: Language = Language::from_lang_id(0x0409);
+
+    /// Creates a language from its raw LANGID.
+    pub const fn from_lang_id(lang_id: u16) -> Self {
+        Self { raw: lang_id }
+    }
+
+    /// Returns the raw LANGID.
+    pub fn lang_id(self) -> u16 {
+        self.raw
+    }
+
+    /// Returns the primary language ID, e.g. `0x09` for English.
+    pub fn primary_language_id(self) -> u16 {
+        self.raw & 0x03FF
+    }
+
+    /// Returns the sublanguage ID, e.g. `0x01` for United States when the primary language is
+    /// English.
+    pub fn sub_language_id(self) -> u16 {
+        self.raw >> 10
+    }
+}
+
+impl fmt::Debug for Language {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Language({:#06x})", self.raw)
+    }
+}
+
+/// Parses string descriptor zero into the languages it lists.
+pub(crate) fn parse_languages(data: &[u8]) -> crate::Result<Vec<Language>> {
+    let body = string_body(data)?;
+    Ok(body
+        .chunks_exact(2)
+        .map(|c| Language::from_lang_id(u16::from_le_bytes([c[0], c[1]])))
+        .collect())
+}
+
+/// Decodes the UTF-16LE text of a string descriptor. Unpaired surrogates are replaced with
+/// U+FFFD.
+pub(crate) fn parse_string(data: &[u8]) -> crate::Result<String> {
+    let body = string_body(data)?;
+    let units: Vec<u16> = body
+        .chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .collect();
+    Ok(String::from_utf16_lossy(&units))
+}
+
+fn string_body(data: &[u8]) -> crate::Result<&[u8]> {
+    let [len, _] = header(data, DESCRIPTOR_TYPE_STRING, 2)?;
+    Ok(&data[2..len as usize])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_language_ids() {
+        let languages = parse_languages(&[0x06, 0x03, 0x09, 0x04, 0x07, 0x04]).unwrap();
+        assert_eq!(
+            languages,
+            vec![Language::ENGLISH_US, Language::from_lang_id(0x0407)]
+        );
+        assert_eq!(languages[0].primary_language_id(), 0x09);
+        assert_eq!(languages[0].sub_language_id(), 0x01);
+    }
+
+    #[test]
+    fn decodes_utf16_strings() {
+        // "Gerät" followed by a byte past bLength that must be ignored.
+        let data = [
+            0x0C, 0x03, 0x47, 0x00, 0x65, 0x00, 0x72, 0x00, 0xE4, 0x00, 0x74, 0x00, 0xFF,
+        ];
+        assert_eq!(parse_string(&data).unwrap(), "Gerät");
+        assert_eq!(parse_string(&[0x02, 0x03]).unwrap(), "");
+
+        let unpaired = [0x04, 0x03, 0x00, 0xD8];
+        assert_eq!(parse_string(&unpaired).unwrap(), "\u{FFFD}");
+    }
+
+    #[test]
+    fn rejects_malformed_string_descriptor() {
+        assert!(parse_string(&[0x04, 0x02, 0x41, 0x00]).is_err());
+        assert!(parse_string(&[0x08, 0x03, 0x41, 0x00]).is_err());
+        assert!(parse_languages(&[0x01]).is_err());
+    }
+}
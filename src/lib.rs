@@ -1,21 +1,68 @@
 pub use libusbk_sys as ffi;
 
+pub use crate::async_io::TransferFuture;
+pub use crate::backend::{
+    Backend, DeviceHandleBackend, DeviceListBackend, DeviceWatch, LibUsbK, OverlappedPoolBackend,
+    PendingTransfer, StreamBackend,
+};
+#[cfg(feature = "sim")]
+pub use crate::backend::{SimBackend, SimConfigBuilder, SimControl, SimDevice, SimOp};
+pub use crate::bos_descriptor::{
+    BosCapability, BosDescriptor, SuperSpeedCapability, Usb2Extension,
+};
+pub use crate::config_descriptor::{
+    ConfigDescriptor, EndpointDescriptor, Interface, InterfaceDescriptor,
+    SuperSpeedEndpointCompanion,
+};
 pub use crate::device::Device;
-pub use crate::device_handle::{DeviceHandle, DriverId};
-pub use crate::device_list::DeviceList;
+pub use crate::device_descriptor::DeviceDescriptor;
+pub use crate::device_handle::{DeviceHandle, DriverId, PipeAborter};
+pub use crate::device_list::{DeviceList, DeviceListBuilder, Devices};
 pub use crate::error::{Error, Result};
+pub use crate::fields::{
+    request_type, Direction, Recipient, RequestType, Speed, SyncType, TransferType, UsageType,
+    Version,
+};
 pub use crate::hotplug::{has_hotplug, Hotplug, HotplugBuilder};
+pub use crate::instance_id::InstanceId;
+pub use crate::interface::{ClaimedInterface, InterfaceSelector};
+pub use crate::iso::{IsoPacket, IsoPacketInformation, IsoTransfer};
+pub use crate::language::Language;
+pub use crate::overlapped::{OverlappedPool, Transfer};
+pub use crate::pipe_info::{PipeInfo, PipeType};
+pub use crate::pipe_policy::{PipePolicy, PipePolicyKind};
+pub use crate::power_policy::{PowerPolicy, PowerPolicyKind};
+pub use crate::stream::{Stream, StreamBuilder};
+#[cfg(feature = "tokio")]
+pub use crate::tokio_io::BulkStream;
 pub use crate::version::{version, LibraryVersion};
 
 //mod context;
+mod async_io;
+mod backend;
+mod bos_descriptor;
+mod config_descriptor;
 mod device;
+mod device_descriptor;
 mod device_handle;
 mod device_list;
 mod error;
+mod fields;
 mod hotplug;
+mod instance_id;
+mod interface;
+mod iso;
+mod language;
+mod overlapped;
+mod pipe_info;
+mod pipe_policy;
+mod power_policy;
+mod stream;
+#[cfg(feature = "tokio")]
+mod tokio_io;
 mod version;
 
-#[cfg(test)]
+#[cfg(all(test, windows))]
 mod tests {
     use super::*;
 
@@ -0,0 +1,296 @@
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use libusbk_sys::WINUSB_SETUP_PACKET;
+
+use crate::backend::{OverlappedPoolBackend, PendingTransfer};
+use crate::device_handle::DeviceHandle;
+use crate::error::{Error, ERROR_INVALID_PARAMETER};
+use crate::Result;
+
+/// A pool of overlapped contexts, allowing several transfers to be in flight on a device at
+/// once.
+///
+/// Transfers are started with [`DeviceHandle::submit_read`] and [`DeviceHandle::submit_write`]
+/// on the handle the pool was created for. The pool keeps the device open until it and all of
+/// its transfers have been dropped.
+#[derive(Clone)]
+pub struct OverlappedPool {
+    inner: Arc<dyn OverlappedPoolBackend>,
+    /// The [`DeviceHandle::id`] of the handle the pool was created for.
+    owner: usize,
+}
+
+impl OverlappedPool {
+    /// Creates a pool for up to `max_transfers` transfers in flight on `handle`.
+    pub fn new(handle: &DeviceHandle, max_transfers: usize) -> Result<Self> {
+        let inner = handle.inner().overlapped_pool(max_transfers)?;
+        Ok(Self {
+            inner,
+            owner: handle.id(),
+        })
+    }
+
+    /// Waits until the oldest transfer from this pool that has not been dropped completes, or
+    /// indefinitely if `timeout` is `None`.
+    pub fn wait_oldest(&self, timeout: Option<Duration>) -> Result<()> {
+        self.inner.wait_oldest(timeout)
+    }
+
+    /// Waits until any transfer from this pool that has not been dropped completes, or until
+    /// [`wake`](Self::wake) is called.
+    pub(crate) fn wait_any(&self) -> Result<()> {
+        self.inner.wait_any()
+    }
+
+    /// Interrupts the current or next [`wait_any`](Self::wait_any).
+    pub(crate) fn wake(&self) {
+        self.inner.wake()
+    }
+
+    pub(crate) fn check_owner(&self, handle: &DeviceHandle) -> Result<()> {
+        match self.owner == handle.id() {
+            true => Ok(()),
+            false => Err(Error::from_code(ERROR_INVALID_PARAMETER)),
+        }
+    }
+
+    /// Starts reading from `pipe_id` into `buffer`, which the transfer owns until it completes.
+    pub(crate) fn submit_read(&self, pipe_id: u8, mut buffer: Vec<u8>) -> Result<Transfer> {
+        let pending = unsafe {
+            self.inner
+                .submit_read(pipe_id, buffer.as_mut_ptr(), buffer.len())?
+        };
+        Ok(Transfer::new(pending, buffer))
+    }
+
+    /// Starts writing `buffer` to `pipe_id`. The transfer owns the buffer until it completes.
+    pub(crate) fn submit_write(&self, pipe_id: u8, buffer: Vec<u8>) -> Result<Transfer> {
+        let pending = unsafe {
+            self.inner
+                .submit_write(pipe_id, buffer.as_ptr(), buffer.len())?
+        };
+        Ok(Transfer::new(pending, buffer))
+    }
+
+    /// Starts a control transfer. `buffer` receives the data of an inbound request and holds
+    /// the data to send otherwise.
+    pub(crate) fn submit_control(
+        &self,
+        setup: WINUSB_SETUP_PACKET,
+        mut buffer: Vec<u8>,
+    ) -> Result<Transfer> {
+        let pending = unsafe {
+            self.inner
+                .submit_control(setup, buffer.as_mut_ptr(), buffer.len())?
+        };
+        Ok(Transfer::new(pending, buffer))
+    }
+}
+
+impl fmt::Debug for OverlappedPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OverlappedPool")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+/// A transfer in flight, started from an [`OverlappedPool`].
+///
+/// The transfer owns its buffer until it completes. Dropping a transfer that has not completed
+/// cancels it and waits for the cancellation to finish.
+#[derive(Debug)]
+pub struct Transfer {
+    pending: Box<dyn PendingTransfer>,
+    buffer: Vec<u8>,
+    result: Option<Result<u32>>,
+}
+
+impl Transfer {
+    fn new(pending: Box<dyn PendingTransfer>, buffer: Vec<u8>) -> Self {
+        Self {
+            pending,
+            buffer,
+            result: None,
+        }
+    }
+
+    /// Waits for the transfer to complete and returns the number of bytes transferred.
+    pub fn wait(&mut self) -> Result<u32> {
+        self.wait_with(|pending| pending.wait(None))
+    }
+
+    /// Waits up to `timeout` for the transfer to complete.
+    ///
+    /// Returns [`Error::Timeout`] if it is still in flight, in which case it can be waited on
+    /// again.
+    pub fn wait_timeout(&mut self, timeout: Duration) -> Result<u32> {
+        self.wait_with(|pending| pending.wait(Some(timeout)))
+    }
+
+    /// Waits up to `timeout` for the transfer to complete, and cancels it otherwise.
+    pub fn wait_or_cancel(&mut self, timeout: Duration) -> Result<u32> {
+        self.wait_with(|pending| pending.wait_or_cancel(timeout))
+    }
+
+    /// Cancels the transfer if it is still in flight.
+    ///
+    /// Returns the result of the transfer, which is [`Error::Cancelled`] unless it completed
+    /// before it could be cancelled.
+    pub fn cancel(&mut self) -> Result<u32> {
+        self.wait_or_cancel(Duration::ZERO)
+    }
+
+    /// Indicates if the transfer has completed, successfully or not.
+    pub fn is_complete(&self) -> bool {
+        self.result.is_some() || self.pending.is_complete()
+    }
+
+    /// Returns the bytes transferred, or `None` if the transfer has not completed successfully.
+    pub fn data(&self) -> Option<&[u8]> {
+        match self.result {
+            Some(Ok(len)) => Some(&self.buffer[..len as usize]),
+            _ => None,
+        }
+    }
+
+    /// Returns the buffer, cancelling the transfer first if it is still in flight.
+    pub fn into_buffer(mut self) -> Vec<u8> {
+        self.finish();
+        std::mem::take(&mut self.buffer)
+    }
+
+    fn wait_with(
+        &mut self,
+        wait: impl FnOnce(&mut dyn PendingTransfer) -> Result<u32>,
+    ) -> Result<u32> {
+        if let Some(result) = self.result {
+            return result;
+        }
+        let result = wait(self.pending.as_mut());
+        if !matches!(result, Err(Error::Timeout(_))) {
+            self.result = Some(result);
+        }
+        result
+    }
+
+    /// Makes sure the driver no longer uses the buffer.
+    fn finish(&mut self) {
+        if self.result.is_none() {
+            let _ = self.cancel();
+        }
+        // A cancellation that timed out may still be in progress.
+        while self.result.is_none() {
+            let _ = self.wait();
+        }
+    }
+}
+
+impl Drop for Transfer {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::backend::sim::tests::open;
+    use crate::backend::SimDevice;
+    use crate::error::{ERROR_OPERATION_ABORTED, WAIT_TIMEOUT};
+
+    /// A transfer whose cancellation times out, and which only completes when waited on
+    /// without a timeout.
+    #[derive(Debug)]
+    struct SlowCancel(Arc<AtomicUsize>);
+
+    impl PendingTransfer for SlowCancel {
+        fn wait(&mut self, timeout: Option<Duration>) -> Result<u32> {
+            match timeout {
+                Some(_) => Err(Error::from_code(WAIT_TIMEOUT)),
+                None => {
+                    self.0.fetch_add(1, Ordering::SeqCst);
+                    Err(Error::from_code(ERROR_OPERATION_ABORTED))
+                }
+            }
+        }
+
+        fn wait_or_cancel(&mut self, _timeout: Duration) -> Result<u32> {
+            Err(Error::from_code(WAIT_TIMEOUT))
+        }
+
+        fn is_complete(&self) -> bool {
+            false
+        }
+    }
+
+    #[test]
+    fn waits_for_slow_cancellations() {
+        let waits = Arc::new(AtomicUsize::new(0));
+        let mut transfer = Transfer::new(Box::new(SlowCancel(waits.clone())), vec![0; 4]);
+        assert!(matches!(transfer.cancel(), Err(Error::Timeout(_))));
+        assert!(!transfer.is_complete());
+        assert_eq!(transfer.into_buffer().len(), 4);
+        assert_eq!(waits.load(Ordering::SeqCst), 1);
+
+        drop(Transfer::new(
+            Box::new(SlowCancel(waits.clone())),
+            vec![0; 4],
+        ));
+        assert_eq!(waits.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn overlapped_transfers() {
+        let device = SimDevice::new(0x1234, 0x5678);
+        let handle = open(&device);
+        let pool = OverlappedPool::new(&handle, 3).unwrap();
+
+        let mut first = handle.submit_read(&pool, 0x81, vec![0; 64]).unwrap();
+        let mut second = handle.submit_read(&pool, 0x81, vec![0; 64]).unwrap();
+        let write = handle.submit_write(&pool, 0x01, vec![7, 8]).unwrap();
+        assert!(handle.submit_read(&pool, 0x81, vec![0; 64]).is_err());
+        drop(write);
+        assert_eq!(device.take_writes(0x01), vec![vec![7, 8]]);
+
+        assert!(!first.is_complete());
+        assert_eq!(
+            first.wait_timeout(Duration::from_millis(5)),
+            Err(Error::Timeout(258))
+        );
+        device.push_read(0x81, &[1, 2, 3]);
+        pool.wait_oldest(Some(Duration::from_secs(1))).unwrap();
+        assert!(first.is_complete());
+        assert_eq!(first.wait().unwrap(), 3);
+        assert_eq!(first.data(), Some(&[1, 2, 3][..]));
+        assert_eq!(first.into_buffer().len(), 64);
+
+        assert!(matches!(second.cancel(), Err(Error::Cancelled(_))));
+        assert_eq!(second.data(), None);
+        device.push_read(0x81, &[4]);
+        assert!(matches!(second.wait(), Err(Error::Cancelled(_))));
+
+        // The pool and its transfers keep the device open after the handle is gone.
+        drop(handle);
+        drop(pool);
+        assert_eq!(device.open_handles(), 1);
+        drop(second);
+        assert_eq!(device.open_handles(), 0);
+    }
+
+    #[test]
+    fn overlapped_pool_belongs_to_its_handle() {
+        let device = SimDevice::new(0x1234, 0x5678);
+        let handle = open(&device);
+        let other = open(&device);
+        let pool = OverlappedPool::new(&handle, 1).unwrap();
+        assert!(matches!(
+            other.submit_read(&pool, 0x81, vec![0; 8]),
+            Err(Error::InvalidParam(_))
+        ));
+    }
+}
@@ -0,0 +1,175 @@
+use libusbk_sys::{
+    _USBD_PIPE_TYPE_UsbdPipeTypeBulk, _USBD_PIPE_TYPE_UsbdPipeTypeControl,
+    _USBD_PIPE_TYPE_UsbdPipeTypeInterrupt, _USBD_PIPE_TYPE_UsbdPipeTypeIsochronous, USBD_PIPE_TYPE,
+    WINUSB_PIPE_INFORMATION_EX,
+};
+
+use crate::error::{Error, ERROR_INVALID_DATA};
+use crate::fields::{Direction, ENDPOINT_DIR_MASK, ENDPOINT_IN};
+
+/// The transfer type of a pipe, as reported by the driver.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PipeType {
+    Control,
+    Isochronous,
+    Bulk,
+    Interrupt,
+}
+
+impl TryFrom<USBD_PIPE_TYPE> for PipeType {
+    type Error = Error;
+
+    fn try_from(pipe_type: USBD_PIPE_TYPE) -> Result<Self, Error> {
+        #[allow(non_upper_case_globals)]
+        match pipe_type {
+            _USBD_PIPE_TYPE_UsbdPipeTypeControl => Ok(PipeType::Control),
+            _USBD_PIPE_TYPE_UsbdPipeTypeIsochronous => Ok(PipeType::Isochronous),
+            _USBD_PIPE_TYPE_UsbdPipeTypeBulk => Ok(PipeType::Bulk),
+            _USBD_PIPE_TYPE_UsbdPipeTypeInterrupt => Ok(PipeType::Interrupt),
+            _ => Err(Error::from_code(ERROR_INVALID_DATA)),
+        }
+    }
+}
+
+/// Describes a pipe of an interface, as returned by [`DeviceHandle::pipes`].
+///
+/// [`DeviceHandle::pipes`]: crate::DeviceHandle::pipes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PipeInfo {
+    pipe_type: PipeType,
+    pipe_id: u8,
+    max_packet_size: u16,
+    interval: u8,
+    max_bytes_per_interval: u32,
+}
+
+impl PipeInfo {
+    pub(crate) fn from_native(info: &WINUSB_PIPE_INFORMATION_EX) -> crate::Result<Self> {
+        Ok(Self {
+            pipe_type: PipeType::try_from(info.PipeType)?,
+            pipe_id: info.PipeId,
+            max_packet_size: info.MaximumPacketSize,
+            interval: info.Interval,
+            // `ULONG` is only 32 bits wide on Windows.
+            max_bytes_per_interval: info.MaximumBytesPerInterval as u32,
+        })
+    }
+
+    /// Converts the description back into the structure `libusbK` takes.
+    pub(crate) fn to_native(self) -> WINUSB_PIPE_INFORMATION_EX {
+        WINUSB_PIPE_INFORMATION_EX {
+            PipeType: match self.pipe_type {
+                PipeType::Control => _USBD_PIPE_TYPE_UsbdPipeTypeControl,
+                PipeType::Isochronous => _USBD_PIPE_TYPE_UsbdPipeTypeIsochronous,
+                PipeType::Bulk => _USBD_PIPE_TYPE_UsbdPipeTypeBulk,
+                PipeType::Interrupt => _USBD_PIPE_TYPE_UsbdPipeTypeInterrupt,
+            },
+            PipeId: self.pipe_id,
+            MaximumPacketSize: self.max_packet_size,
+            Interval: self.interval,
+            MaximumBytesPerInterval: self.max_bytes_per_interval.into(),
+        }
+    }
+
+    pub fn pipe_type(&self) -> PipeType {
+        self.pipe_type
+    }
+
+    /// Returns the pipe ID, which is the endpoint address used for transfers on the pipe.
+    pub fn pipe_id(&self) -> u8 {
+        self.pipe_id
+    }
+
+    pub fn direction(&self) -> Direction {
+        match self.pipe_id & ENDPOINT_DIR_MASK {
+            ENDPOINT_IN => Direction::In,
+            _ => Direction::Out,
+        }
+    }
+
+    /// Returns the maximum size of a packet, without the additional transactions of
+    /// high-bandwidth endpoints.
+    pub fn max_packet_size(&self) -> u16 {
+        self.max_packet_size
+    }
+
+    /// Returns the polling interval, as encoded in the endpoint's `bInterval`.
+    pub fn interval(&self) -> u8 {
+        self.interval
+    }
+
+    /// Returns the number of bytes the pipe can transfer per service interval, or zero if the
+    /// driver does not report it.
+    pub fn max_bytes_per_interval(&self) -> u32 {
+        self.max_bytes_per_interval
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::sim::tests::open;
+    use crate::backend::{SimConfigBuilder, SimDevice, SimOp};
+
+    #[test]
+    fn converts_pipe_information() {
+        let info = PipeInfo::from_native(&WINUSB_PIPE_INFORMATION_EX {
+            PipeType: _USBD_PIPE_TYPE_UsbdPipeTypeIsochronous,
+            PipeId: 0x82,
+            MaximumPacketSize: 1024,
+            Interval: 1,
+            MaximumBytesPerInterval: 3072,
+        })
+        .unwrap();
+        assert_eq!(info.pipe_type(), PipeType::Isochronous);
+        assert_eq!(info.direction(), Direction::In);
+        assert_eq!(info.max_bytes_per_interval(), 3072);
+        assert_eq!(PipeInfo::from_native(&info.to_native()).unwrap(), info);
+
+        let invalid = WINUSB_PIPE_INFORMATION_EX {
+            PipeType: 4,
+            ..Default::default()
+        };
+        assert!(PipeInfo::from_native(&invalid).is_err());
+    }
+
+    #[test]
+    fn queries_interface_settings_and_pipes() {
+        let device = SimDevice::new(0x1234, 0x5678);
+        device.set_config(
+            0,
+            SimConfigBuilder::new(1)
+                .interface(0, 0)
+                .interface(0, 1)
+                .endpoint(0x83, 0x01, 0x1400, 1) // iso in, 3 x 1024 bytes
+                .endpoint(0x02, 0x02, 512, 0), // bulk out
+        );
+        let handle = open(&device);
+
+        let settings = handle.interface_settings(1).unwrap();
+        assert_eq!(settings.setting_number(), 1);
+        assert_eq!(settings.num_endpoints(), 2);
+        assert!(handle.pipes(0).unwrap().is_empty());
+        assert!(handle.interface_settings(2).is_err());
+
+        let pipes = handle.pipes(1).unwrap();
+        assert_eq!(pipes.len(), 2);
+        assert_eq!(pipes[0].pipe_type(), PipeType::Isochronous);
+        assert_eq!(pipes[0].pipe_id(), 0x83);
+        assert_eq!(pipes[0].direction(), Direction::In);
+        assert_eq!(pipes[0].max_packet_size(), 1024);
+        assert_eq!(pipes[0].interval(), 1);
+        assert_eq!(pipes[0].max_bytes_per_interval(), 3072);
+        assert_eq!(pipes[1].pipe_type(), PipeType::Bulk);
+        assert_eq!(pipes[1].direction(), Direction::Out);
+
+        let info = handle.iso_packet_information(&pipes[0]).unwrap();
+        assert_eq!(info.packets_per_frame(), 8);
+        assert_eq!(info.polling_period_us(), 125);
+        assert_eq!(info.bytes_per_ms(), 24576);
+        assert_eq!(info.packet_size(), 3072);
+
+        device.inject_error(SimOp::QueryPipe, 259);
+        assert!(handle.pipes(1).is_err());
+    }
+}
@@ -0,0 +1,271 @@
+use std::time::Duration;
+
+use crate::error::{Error, ERROR_INVALID_DATA};
+
+/// A policy controlling how the driver handles transfers on a pipe.
+///
+/// Policies are set with [`DeviceHandle::set_pipe_policy`] and read back with
+/// [`DeviceHandle::get_pipe_policy`]. The `Iso*` and [`PipePolicy::SimulParallelRequests`]
+/// policies are only understood by the `libusbK` driver.
+///
+/// [`DeviceHandle::set_pipe_policy`]: crate::DeviceHandle::set_pipe_policy
+/// [`DeviceHandle::get_pipe_policy`]: crate::DeviceHandle::get_pipe_policy
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum PipePolicy {
+    /// Terminates writes that are a multiple of the maximum packet size with a zero-length
+    /// packet.
+    ShortPacketTerminate(bool),
+    /// Clears a stall condition on the pipe automatically.
+    AutoClearStall(bool),
+    /// Fails transfers that do not complete in time. Zero waits indefinitely.
+    TransferTimeout(Duration),
+    /// Completes reads only when the buffer is full, instead of on a short packet.
+    IgnoreShortPackets(bool),
+    /// Allows reads that are shorter than the maximum packet size, keeping the excess data for
+    /// the next read.
+    AllowPartialReads(bool),
+    /// Discards excess data left over from a partial read instead of keeping it.
+    AutoFlush(bool),
+    /// Passes reads directly to the host controller without buffering.
+    RawIo(bool),
+    /// The largest transfer the driver supports on the pipe. Read-only.
+    MaximumTransferSize(u32),
+    /// Resets the pipe when the device resumes from suspend.
+    ResetPipeOnResume(bool),
+    /// The number of frames to wait before starting the first isochronous transfer.
+    IsoStartLatency(u32),
+    /// Starts isochronous transfers as soon as possible, instead of on a fixed frame.
+    IsoAlwaysStartAsap(bool),
+    /// The number of packets every isochronous transfer on the pipe must have, or zero for any.
+    IsoNumFixedPackets(u16),
+    /// The maximum number of transfers the driver queues on the pipe at the same time.
+    SimulParallelRequests(u32),
+}
+
+/// Identifies a [`PipePolicy`] to read with
+/// [`DeviceHandle::get_pipe_policy`](crate::DeviceHandle::get_pipe_policy).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum PipePolicyKind {
+    ShortPacketTerminate,
+    AutoClearStall,
+    TransferTimeout,
+    IgnoreShortPackets,
+    AllowPartialReads,
+    AutoFlush,
+    RawIo,
+    MaximumTransferSize,
+    ResetPipeOnResume,
+    IsoStartLatency,
+    IsoAlwaysStartAsap,
+    IsoNumFixedPackets,
+    SimulParallelRequests,
+}
+
+impl PipePolicy {
+    /// Returns which policy this is.
+    pub fn kind(&self) -> PipePolicyKind {
+        match self {
+            PipePolicy::ShortPacketTerminate(_) => PipePolicyKind::ShortPacketTerminate,
+            PipePolicy::AutoClearStall(_) => PipePolicyKind::AutoClearStall,
+            PipePolicy::TransferTimeout(_) => PipePolicyKind::TransferTimeout,
+            PipePolicy::IgnoreShortPackets(_) => PipePolicyKind::IgnoreShortPackets,
+            PipePolicy::AllowPartialReads(_) => PipePolicyKind::AllowPartialReads,
+            PipePolicy::AutoFlush(_) => PipePolicyKind::AutoFlush,
+            PipePolicy::RawIo(_) => PipePolicyKind::RawIo,
+            PipePolicy::MaximumTransferSize(_) => PipePolicyKind::MaximumTransferSize,
+            PipePolicy::ResetPipeOnResume(_) => PipePolicyKind::ResetPipeOnResume,
+            PipePolicy::IsoStartLatency(_) => PipePolicyKind::IsoStartLatency,
+            PipePolicy::IsoAlwaysStartAsap(_) => PipePolicyKind::IsoAlwaysStartAsap,
+            PipePolicy::IsoNumFixedPackets(_) => PipePolicyKind::IsoNumFixedPackets,
+            PipePolicy::SimulParallelRequests(_) => PipePolicyKind::SimulParallelRequests,
+        }
+    }
+
+    /// Encodes the value as passed to `SetPipePolicy`.
+    pub(crate) fn to_bytes(self) -> Vec<u8> {
+        match self {
+            PipePolicy::ShortPacketTerminate(v)
+            | PipePolicy::AutoClearStall(v)
+            | PipePolicy::IgnoreShortPackets(v)
+            | PipePolicy::AllowPartialReads(v)
+            | PipePolicy::AutoFlush(v)
+            | PipePolicy::RawIo(v)
+            | PipePolicy::ResetPipeOnResume(v)
+            | PipePolicy::IsoAlwaysStartAsap(v) => vec![v.into()],
+            PipePolicy::TransferTimeout(timeout) => {
+                let timeout_ms = u32::try_from(timeout.as_millis()).unwrap_or(u32::MAX);
+                timeout_ms.to_ne_bytes().to_vec()
+            }
+            PipePolicy::MaximumTransferSize(v)
+            | PipePolicy::IsoStartLatency(v)
+            | PipePolicy::SimulParallelRequests(v) => v.to_ne_bytes().to_vec(),
+            PipePolicy::IsoNumFixedPackets(v) => v.to_ne_bytes().to_vec(),
+        }
+    }
+
+    /// Decodes a value returned by `GetPipePolicy`.
+    pub(crate) fn from_bytes(kind: PipePolicyKind, data: &[u8]) -> crate::Result<Self> {
+        if data.len() < kind.value_len() {
+            return Err(Error::from_code(ERROR_INVALID_DATA));
+        }
+        let flag = data[0] != 0;
+        let dword = || u32::from_ne_bytes([data[0], data[1], data[2], data[3]]);
+
+        Ok(match kind {
+            PipePolicyKind::ShortPacketTerminate => PipePolicy::ShortPacketTerminate(flag),
+            PipePolicyKind::AutoClearStall => PipePolicy::AutoClearStall(flag),
+            PipePolicyKind::TransferTimeout => {
+                PipePolicy::TransferTimeout(Duration::from_millis(dword().into()))
+            }
+            PipePolicyKind::IgnoreShortPackets => PipePolicy::IgnoreShortPackets(flag),
+            PipePolicyKind::AllowPartialReads => PipePolicy::AllowPartialReads(flag),
+            PipePolicyKind::AutoFlush => PipePolicy::AutoFlush(flag),
+            PipePolicyKind::RawIo => PipePolicy::RawIo(flag),
+            PipePolicyKind::MaximumTransferSize => PipePolicy::MaximumTransferSize(dword()),
+            PipePolicyKind::ResetPipeOnResume => PipePolicy::ResetPipeOnResume(flag),
+            PipePolicyKind::IsoStartLatency => PipePolicy::IsoStartLatency(dword()),
+            PipePolicyKind::IsoAlwaysStartAsap => PipePolicy::IsoAlwaysStartAsap(flag),
+            PipePolicyKind::IsoNumFixedPackets => {
+                PipePolicy::IsoNumFixedPackets(u16::from_ne_bytes([data[0], data[1]]))
+            }
+            PipePolicyKind::SimulParallelRequests => PipePolicy::SimulParallelRequests(dword()),
+        })
+    }
+}
+
+impl PipePolicyKind {
+    /// Returns the policy type passed to `SetPipePolicy` and `GetPipePolicy`.
+    pub(crate) fn policy_type(self) -> u32 {
+        match self {
+            PipePolicyKind::ShortPacketTerminate => 0x01,
+            PipePolicyKind::AutoClearStall => 0x02,
+            PipePolicyKind::TransferTimeout => 0x03,
+            PipePolicyKind::IgnoreShortPackets => 0x04,
+            PipePolicyKind::AllowPartialReads => 0x05,
+            PipePolicyKind::AutoFlush => 0x06,
+            PipePolicyKind::RawIo => 0x07,
+            PipePolicyKind::MaximumTransferSize => 0x08,
+            PipePolicyKind::ResetPipeOnResume => 0x09,
+            PipePolicyKind::IsoStartLatency => 0x20,
+            PipePolicyKind::IsoAlwaysStartAsap => 0x21,
+            PipePolicyKind::IsoNumFixedPackets => 0x22,
+            PipePolicyKind::SimulParallelRequests => 0x30,
+        }
+    }
+
+    /// Returns the size in bytes of the policy value.
+    pub(crate) fn value_len(self) -> usize {
+        match self {
+            PipePolicyKind::TransferTimeout
+            | PipePolicyKind::MaximumTransferSize
+            | PipePolicyKind::IsoStartLatency
+            | PipePolicyKind::SimulParallelRequests => 4,
+            PipePolicyKind::IsoNumFixedPackets => 2,
+            _ => 1,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::sim::tests::open;
+    use crate::backend::{SimDevice, SimOp};
+
+    #[test]
+    fn encodes_policy_values() {
+        assert_eq!(PipePolicy::RawIo(true).to_bytes(), [1]);
+        assert_eq!(
+            PipePolicy::TransferTimeout(Duration::from_millis(1500)).to_bytes(),
+            1500u32.to_ne_bytes()
+        );
+        assert_eq!(
+            PipePolicy::TransferTimeout(Duration::from_secs(u64::MAX)).to_bytes(),
+            u32::MAX.to_ne_bytes()
+        );
+        assert_eq!(
+            PipePolicy::IsoNumFixedPackets(8).to_bytes(),
+            8u16.to_ne_bytes()
+        );
+    }
+
+    #[test]
+    fn round_trips_policies() {
+        for policy in [
+            PipePolicy::ShortPacketTerminate(true),
+            PipePolicy::AutoClearStall(false),
+            PipePolicy::TransferTimeout(Duration::from_millis(250)),
+            PipePolicy::MaximumTransferSize(0x10_0000),
+            PipePolicy::IsoStartLatency(16),
+            PipePolicy::IsoNumFixedPackets(32),
+            PipePolicy::SimulParallelRequests(4),
+        ] {
+            let kind = policy.kind();
+            assert_eq!(kind.value_len(), policy.to_bytes().len());
+            assert_eq!(
+                PipePolicy::from_bytes(kind, &policy.to_bytes()).unwrap(),
+                policy
+            );
+        }
+        assert!(PipePolicy::from_bytes(PipePolicyKind::TransferTimeout, &[0; 2]).is_err());
+    }
+
+    #[test]
+    fn pipe_timeouts_and_policies() {
+        let device = SimDevice::new(0x1234, 0x5678);
+        let handle = open(&device);
+        let mut buf = [0; 64];
+
+        assert_eq!(
+            handle.read_pipe_timeout(0x81, &mut buf, Duration::from_millis(100)),
+            Err(Error::Timeout(121))
+        );
+        assert_eq!(
+            handle
+                .get_pipe_policy(0x81, PipePolicyKind::TransferTimeout)
+                .unwrap(),
+            PipePolicy::TransferTimeout(Duration::from_millis(100))
+        );
+        assert_eq!(
+            handle
+                .write_pipe_timeout(0x01, &[1], Duration::from_secs(2))
+                .unwrap(),
+            1
+        );
+        assert_eq!(
+            device.pipe_policy(0x01, 0x03),
+            Some(2000u32.to_ne_bytes().to_vec())
+        );
+
+        // An unchanged timeout is not sent to the driver again.
+        device.inject_error(SimOp::SetPipePolicy, 31);
+        assert_eq!(
+            handle.write_pipe_timeout(0x01, &[2], Duration::from_secs(2)),
+            Ok(1)
+        );
+        assert_eq!(
+            handle.write_pipe_timeout(0x01, &[3], Duration::from_secs(1)),
+            Err(Error::Pipe(31))
+        );
+        assert_eq!(
+            handle.write_pipe_timeout(0x01, &[3], Duration::from_secs(1)),
+            Ok(1)
+        );
+
+        handle
+            .set_pipe_policy(0x81, PipePolicy::AllowPartialReads(true))
+            .unwrap();
+        assert_eq!(device.pipe_policy(0x81, 0x05), Some(vec![1]));
+        assert_eq!(
+            handle.get_pipe_policy(0x81, PipePolicyKind::RawIo).unwrap(),
+            PipePolicy::RawIo(false)
+        );
+
+        device.inject_error(SimOp::SetPipePolicy, 87);
+        assert!(handle
+            .set_pipe_policy(0x81, PipePolicy::AutoClearStall(true))
+            .is_err());
+    }
+}
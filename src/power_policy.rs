@@ -0,0 +1,148 @@
+use std::time::Duration;
+
+use crate::error::{Error, ERROR_INVALID_DATA};
+
+/// A policy controlling how the driver manages the power state of a device.
+///
+/// Policies are set with [`DeviceHandle::set_power_policy`] and read back with
+/// [`DeviceHandle::get_power_policy`].
+///
+/// [`DeviceHandle::set_power_policy`]: crate::DeviceHandle::set_power_policy
+/// [`DeviceHandle::get_power_policy`]: crate::DeviceHandle::get_power_policy
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum PowerPolicy {
+    /// Suspends the device when it has been idle for the [`PowerPolicy::SuspendDelay`]
+    /// (selective suspend).
+    AutoSuspend(bool),
+    /// How long the device must be idle before it is suspended.
+    SuspendDelay(Duration),
+}
+
+/// Identifies a [`PowerPolicy`] to read with
+/// [`DeviceHandle::get_power_policy`](crate::DeviceHandle::get_power_policy).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum PowerPolicyKind {
+    AutoSuspend,
+    SuspendDelay,
+}
+
+impl PowerPolicy {
+    /// Returns which policy this is.
+    pub fn kind(&self) -> PowerPolicyKind {
+        match self {
+            PowerPolicy::AutoSuspend(_) => PowerPolicyKind::AutoSuspend,
+            PowerPolicy::SuspendDelay(_) => PowerPolicyKind::SuspendDelay,
+        }
+    }
+
+    /// Encodes the value as passed to `SetPowerPolicy`.
+    pub(crate) fn to_bytes(self) -> Vec<u8> {
+        match self {
+            PowerPolicy::AutoSuspend(v) => vec![v.into()],
+            PowerPolicy::SuspendDelay(delay) => {
+                let delay_ms = u32::try_from(delay.as_millis()).unwrap_or(u32::MAX);
+                delay_ms.to_ne_bytes().to_vec()
+            }
+        }
+    }
+
+    /// Decodes a value returned by `GetPowerPolicy`.
+    pub(crate) fn from_bytes(kind: PowerPolicyKind, data: &[u8]) -> crate::Result<Self> {
+        if data.len() < kind.value_len() {
+            return Err(Error::from_code(ERROR_INVALID_DATA));
+        }
+        Ok(match kind {
+            PowerPolicyKind::AutoSuspend => PowerPolicy::AutoSuspend(data[0] != 0),
+            PowerPolicyKind::SuspendDelay => {
+                let delay_ms = u32::from_ne_bytes([data[0], data[1], data[2], data[3]]);
+                PowerPolicy::SuspendDelay(Duration::from_millis(delay_ms.into()))
+            }
+        })
+    }
+}
+
+impl PowerPolicyKind {
+    /// Returns the policy type passed to `SetPowerPolicy` and `GetPowerPolicy`.
+    pub(crate) fn policy_type(self) -> u32 {
+        match self {
+            PowerPolicyKind::AutoSuspend => 0x81,
+            PowerPolicyKind::SuspendDelay => 0x83,
+        }
+    }
+
+    /// Returns the size in bytes of the policy value.
+    pub(crate) fn value_len(self) -> usize {
+        match self {
+            PowerPolicyKind::AutoSuspend => 1,
+            PowerPolicyKind::SuspendDelay => 4,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::sim::tests::open;
+    use crate::backend::{SimDevice, SimOp};
+    use crate::{PipePolicy, PipePolicyKind};
+
+    #[test]
+    fn round_trips_policies() {
+        for policy in [
+            PowerPolicy::AutoSuspend(true),
+            PowerPolicy::AutoSuspend(false),
+            PowerPolicy::SuspendDelay(Duration::from_millis(5000)),
+        ] {
+            let kind = policy.kind();
+            assert_eq!(kind.value_len(), policy.to_bytes().len());
+            assert_eq!(
+                PowerPolicy::from_bytes(kind, &policy.to_bytes()).unwrap(),
+                policy
+            );
+        }
+        assert!(PowerPolicy::from_bytes(PowerPolicyKind::SuspendDelay, &[0; 1]).is_err());
+    }
+
+    #[test]
+    fn power_policies_and_remote_wakeup() {
+        let device = SimDevice::new(0x1234, 0x5678);
+        let handle = open(&device);
+
+        assert_eq!(
+            handle.get_power_policy(PowerPolicyKind::AutoSuspend),
+            Ok(PowerPolicy::AutoSuspend(false))
+        );
+        handle
+            .set_power_policy(PowerPolicy::AutoSuspend(true))
+            .unwrap();
+        handle
+            .set_power_policy(PowerPolicy::SuspendDelay(Duration::from_secs(2)))
+            .unwrap();
+        assert_eq!(device.power_policy(0x81), Some(vec![1]));
+        assert_eq!(
+            handle.get_power_policy(PowerPolicyKind::SuspendDelay),
+            Ok(PowerPolicy::SuspendDelay(Duration::from_secs(2)))
+        );
+
+        handle.set_remote_wakeup(true, Duration::ZERO).unwrap();
+        assert!(device.remote_wakeup());
+        handle
+            .set_remote_wakeup(false, Duration::from_millis(500))
+            .unwrap();
+        assert!(!device.remote_wakeup());
+        assert_eq!(
+            handle.get_pipe_policy(0x00, PipePolicyKind::TransferTimeout),
+            Ok(PipePolicy::TransferTimeout(Duration::from_millis(500)))
+        );
+        let requests: Vec<_> = device.control_log().iter().map(|c| c.request).collect();
+        assert_eq!(requests, [0x03, 0x01]);
+
+        device.inject_error(SimOp::SetPowerPolicy, 50);
+        assert!(matches!(
+            handle.set_power_policy(PowerPolicy::AutoSuspend(false)),
+            Err(Error::NotSupported(_))
+        ));
+    }
+}
@@ -0,0 +1,382 @@
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+use libusbk_sys::{
+    _KSTM_FLAG_KSTM_FLAG_NONE, _KSTM_FLAG_KSTM_FLAG_NO_PARTIAL_XFERS,
+    _KSTM_FLAG_KSTM_FLAG_TIMEOUT_MASK, _KSTM_FLAG_KSTM_FLAG_USE_TIMEOUT, KSTM_FLAG,
+};
+
+use crate::backend::StreamBackend;
+use crate::device_handle::DeviceHandle;
+use crate::error::{Error, ERROR_INVALID_PARAMETER};
+use crate::fields::{ENDPOINT_DIR_MASK, ENDPOINT_IN, ENDPOINT_OUT};
+use crate::Result;
+
+type Callback<F> = Option<Arc<F>>;
+type CompleteFn = dyn Fn(usize, Result<&[u8]>) + Send + Sync;
+
+/// Closures called by the stream as its transfers progress.
+///
+/// Every closure receives the index of the transfer context, in `0..max_pending_transfers`.
+#[derive(Clone, Default)]
+pub(crate) struct StreamCallbacks {
+    pub(crate) submit: Callback<dyn Fn(usize, usize) + Send + Sync>,
+    pub(crate) complete: Callback<CompleteFn>,
+    pub(crate) error: Callback<dyn Fn(usize, Error) + Send + Sync>,
+    pub(crate) started: Callback<dyn Fn(usize) + Send + Sync>,
+    pub(crate) stopped: Callback<dyn Fn(usize) + Send + Sync>,
+}
+
+impl fmt::Debug for StreamCallbacks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StreamCallbacks").finish_non_exhaustive()
+    }
+}
+
+impl StreamCallbacks {
+    pub(crate) fn submit(&self, index: usize, len: usize) {
+        if let Some(f) = &self.submit {
+            f(index, len);
+        }
+    }
+
+    pub(crate) fn complete(&self, index: usize, result: Result<&[u8]>) {
+        if let Some(f) = &self.complete {
+            f(index, result);
+        }
+    }
+
+    pub(crate) fn error(&self, index: usize, err: Error) {
+        if let Some(f) = &self.error {
+            f(index, err);
+        }
+    }
+
+    pub(crate) fn started(&self, index: usize) {
+        if let Some(f) = &self.started {
+            f(index);
+        }
+    }
+
+    pub(crate) fn stopped(&self, index: usize) {
+        if let Some(f) = &self.stopped {
+            f(index);
+        }
+    }
+}
+
+/// Creates a [`Stream`], which keeps a ring of transfers in flight on one pipe.
+///
+/// Callbacks run on the thread that drives the stream and must not panic.
+#[derive(Clone)]
+pub struct StreamBuilder {
+    pub(crate) max_transfer_size: usize,
+    pub(crate) max_pending_transfers: usize,
+    pub(crate) max_pending_io: usize,
+    timeout: Option<Duration>,
+    no_partial_transfers: bool,
+    pub(crate) callbacks: StreamCallbacks,
+}
+
+impl Default for StreamBuilder {
+    fn default() -> Self {
+        StreamBuilder {
+            max_transfer_size: 64 * 1024,
+            max_pending_transfers: 16,
+            max_pending_io: 4,
+            timeout: None,
+            no_partial_transfers: false,
+            callbacks: StreamCallbacks::default(),
+        }
+    }
+}
+
+impl StreamBuilder {
+    pub fn new() -> Self {
+        StreamBuilder::default()
+    }
+
+    /// The size in bytes of every transfer, 64 KiB by default. For an IN pipe this should be a
+    /// multiple of its maximum packet size.
+    pub fn max_transfer_size(&mut self, len: usize) -> &mut Self {
+        self.max_transfer_size = len;
+        self
+    }
+
+    /// The number of transfer contexts in the ring, 16 by default.
+    pub fn max_pending_transfers(&mut self, count: usize) -> &mut Self {
+        self.max_pending_transfers = count;
+        self
+    }
+
+    /// The number of transfers submitted to the driver at the same time, 4 by default.
+    pub fn max_pending_io(&mut self, count: usize) -> &mut Self {
+        self.max_pending_io = count;
+        self
+    }
+
+    /// Fails reads and writes with [`Error::Timeout`] if they cannot be served in time, instead
+    /// of waiting indefinitely. Timeouts are limited to 131 seconds.
+    pub fn timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Only hands out data from transfers that filled their buffer.
+    pub fn no_partial_transfers(&mut self, enable: bool) -> &mut Self {
+        self.no_partial_transfers = enable;
+        self
+    }
+
+    /// Called before a transfer is submitted, with the number of bytes it requests or sends.
+    pub fn on_submit(&mut self, f: impl Fn(usize, usize) + Send + Sync + 'static) -> &mut Self {
+        self.callbacks.submit = Some(Arc::new(f));
+        self
+    }
+
+    /// Called when a transfer completes, with the bytes it transferred.
+    pub fn on_complete(
+        &mut self,
+        f: impl Fn(usize, Result<&[u8]>) + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.callbacks.complete = Some(Arc::new(f));
+        self
+    }
+
+    /// Called when a transfer cannot be submitted.
+    pub fn on_error(&mut self, f: impl Fn(usize, Error) + Send + Sync + 'static) -> &mut Self {
+        self.callbacks.error = Some(Arc::new(f));
+        self
+    }
+
+    /// Called for every transfer context when the stream starts.
+    pub fn on_started(&mut self, f: impl Fn(usize) + Send + Sync + 'static) -> &mut Self {
+        self.callbacks.started = Some(Arc::new(f));
+        self
+    }
+
+    /// Called for every transfer context when the stream stops.
+    pub fn on_stopped(&mut self, f: impl Fn(usize) + Send + Sync + 'static) -> &mut Self {
+        self.callbacks.stopped = Some(Arc::new(f));
+        self
+    }
+
+    /// Creates a stream on the pipe with the given endpoint address. The stream must be started
+    /// before it transfers any data.
+    pub fn build(&self, handle: &DeviceHandle, pipe_id: u8) -> Result<Stream> {
+        if self.max_transfer_size == 0 || self.max_pending_transfers == 0 {
+            return Err(Error::from_code(ERROR_INVALID_PARAMETER));
+        }
+        handle.check_claimed(pipe_id)?;
+        Ok(Stream {
+            inner: handle.inner().stream(pipe_id, self)?,
+            pipe_id,
+        })
+    }
+
+    /// Returns the flags passed to `StmK_Init`.
+    pub(crate) fn flags(&self) -> KSTM_FLAG {
+        let mut flags = _KSTM_FLAG_KSTM_FLAG_NONE;
+        if let Some(timeout) = self.timeout {
+            let timeout_ms = timeout
+                .as_millis()
+                .min(_KSTM_FLAG_KSTM_FLAG_TIMEOUT_MASK as u128);
+            flags |= _KSTM_FLAG_KSTM_FLAG_USE_TIMEOUT | timeout_ms as KSTM_FLAG;
+        }
+        if self.no_partial_transfers {
+            flags |= _KSTM_FLAG_KSTM_FLAG_NO_PARTIAL_XFERS;
+        }
+        flags
+    }
+}
+
+impl fmt::Debug for StreamBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StreamBuilder")
+            .field("max_transfer_size", &self.max_transfer_size)
+            .field("max_pending_transfers", &self.max_pending_transfers)
+            .field("max_pending_io", &self.max_pending_io)
+            .field("timeout", &self.timeout)
+            .field("no_partial_transfers", &self.no_partial_transfers)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A ring of transfers kept in flight on one pipe, for sustained throughput.
+///
+/// A stream on an IN pipe implements [`io::Read`] and one on an OUT pipe implements
+/// [`io::Write`]; using the other trait fails with [`io::ErrorKind::InvalidInput`]. Reads return
+/// data from transfers that already completed and writes queue data for the next free
+/// transfers, so neither waits for a round trip to the device. The stream keeps the device open
+/// until it is dropped.
+#[derive(Debug)]
+pub struct Stream {
+    inner: Box<dyn StreamBackend>,
+    pipe_id: u8,
+}
+
+impl Stream {
+    /// Returns the endpoint address of the pipe.
+    pub fn pipe_id(&self) -> u8 {
+        self.pipe_id
+    }
+
+    /// Starts submitting transfers.
+    pub fn start(&mut self) -> Result<()> {
+        self.inner.start()
+    }
+
+    /// Stops the stream, cancelling transfers that are still in flight after `timeout`.
+    pub fn stop(&mut self, timeout: Duration) -> Result<()> {
+        self.inner.stop(timeout)
+    }
+
+    fn check_direction(&self, direction: u8) -> io::Result<()> {
+        match self.pipe_id & ENDPOINT_DIR_MASK == direction {
+            true => Ok(()),
+            false => Err(Error::from_code(ERROR_INVALID_PARAMETER).into()),
+        }
+    }
+}
+
+impl io::Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.check_direction(ENDPOINT_IN)?;
+        Ok(self.inner.read(buf)? as usize)
+    }
+}
+
+impl io::Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.check_direction(ENDPOINT_OUT)?;
+        Ok(self.inner.write(buf)? as usize)
+    }
+
+    /// Does nothing: queued data is sent as transfers become free. [`Stream::stop`] waits for
+    /// it to be sent.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::backend::sim::tests::open;
+    use crate::backend::{SimDevice, SimOp};
+
+    #[test]
+    fn encodes_flags() {
+        assert_eq!(StreamBuilder::new().flags(), _KSTM_FLAG_KSTM_FLAG_NONE);
+        let flags = StreamBuilder::new()
+            .timeout(Duration::from_millis(1500))
+            .no_partial_transfers(true)
+            .flags();
+        assert_eq!(
+            flags,
+            _KSTM_FLAG_KSTM_FLAG_USE_TIMEOUT | _KSTM_FLAG_KSTM_FLAG_NO_PARTIAL_XFERS | 1500
+        );
+        let flags = StreamBuilder::new()
+            .timeout(Duration::from_secs(3600))
+            .flags();
+        assert_eq!(flags & _KSTM_FLAG_KSTM_FLAG_TIMEOUT_MASK, 0x1FFFF);
+    }
+
+    #[test]
+    fn reads_through_callbacks() {
+        let device = SimDevice::new(0x1234, 0x5678);
+        let handle = open(&device);
+        let events = Arc::new(Mutex::new(Vec::new()));
+
+        let log = |events: &Arc<Mutex<Vec<String>>>| {
+            let events = events.clone();
+            move |event: String| events.lock().unwrap().push(event)
+        };
+        let (started, submit, complete, stopped) =
+            (log(&events), log(&events), log(&events), log(&events));
+        let mut stream = StreamBuilder::new()
+            .max_transfer_size(4)
+            .max_pending_transfers(2)
+            .on_started(move |i| started(format!("started {i}")))
+            .on_submit(move |i, len| submit(format!("submit {i} {len}")))
+            .on_complete(move |i, data| complete(format!("complete {i} {:?}", data.unwrap())))
+            .on_stopped(move |i| stopped(format!("stopped {i}")))
+            .build(&handle, 0x81)
+            .unwrap();
+        assert_eq!(device.open_handles(), 2);
+
+        stream.start().unwrap();
+        device.push_read(0x81, &[1, 2, 3, 4, 5]);
+        device.push_read(0x81, &[6]);
+        let mut buf = [0; 3];
+        assert_eq!(stream.read(&mut buf).unwrap(), 3);
+        assert_eq!(buf, [1, 2, 3]);
+        assert_eq!(stream.read(&mut buf).unwrap(), 1);
+        assert_eq!(stream.read(&mut buf).unwrap(), 1);
+        assert_eq!(buf[0], 6);
+        let err = stream.read(&mut buf).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert_eq!(
+            stream.write(&[0]).unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+        stream.stop(Duration::ZERO).unwrap();
+
+        assert_eq!(
+            *events.lock().unwrap(),
+            [
+                "started 0",
+                "started 1",
+                "submit 0 4",
+                "complete 0 [1, 2, 3, 4]",
+                "submit 1 4",
+                "complete 1 [6]",
+                "stopped 0",
+                "stopped 1",
+            ]
+        );
+
+        drop(handle);
+        drop(stream);
+        assert_eq!(device.open_handles(), 0);
+    }
+
+    #[test]
+    fn writes_in_transfer_sized_chunks() {
+        let device = SimDevice::new(0x1234, 0x5678);
+        let handle = open(&device);
+        let errors = Arc::new(Mutex::new(Vec::new()));
+        let reported = errors.clone();
+        let mut stream = StreamBuilder::new()
+            .max_transfer_size(2)
+            .on_error(move |i, err| reported.lock().unwrap().push((i, err)))
+            .build(&handle, 0x01)
+            .unwrap();
+        stream.start().unwrap();
+
+        stream.write_all(&[1, 2, 3]).unwrap();
+        stream.flush().unwrap();
+        assert_eq!(device.take_writes(0x01), vec![vec![1, 2], vec![3]]);
+        assert_eq!(
+            stream.read(&mut [0; 4]).unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+
+        device.inject_error(SimOp::WritePipe, 31);
+        assert_eq!(
+            stream.write(&[4]).unwrap_err().kind(),
+            io::ErrorKind::BrokenPipe
+        );
+        assert_eq!(*errors.lock().unwrap(), [(2, Error::Pipe(31))]);
+
+        assert!(StreamBuilder::new()
+            .max_pending_transfers(0)
+            .build(&handle, 0x01)
+            .is_err());
+    }
+}
@@ -0,0 +1,167 @@
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use crate::async_io::{AsyncIo, TransferFuture};
+use crate::device_handle::DeviceHandle;
+use crate::error::{Error, ERROR_INVALID_PARAMETER};
+use crate::fields::{ENDPOINT_DIR_MASK, ENDPOINT_IN, ENDPOINT_OUT};
+use crate::Result;
+
+/// The default number of bytes requested by each read, see [`BulkStream::set_transfer_size`].
+const DEFAULT_TRANSFER_SIZE: usize = 16 * 1024;
+
+/// A pair of bulk endpoints used as a tokio byte stream.
+///
+/// Reads are served from the IN endpoint and writes go to the OUT endpoint, each through the
+/// futures of the [`DeviceHandle`] it was created from. The stream does not borrow the handle
+/// and keeps the device open until it is dropped, even if the handle is closed first.
+///
+/// A write that returns [`Poll::Pending`] has already been started; polling again, or flushing,
+/// waits for it to finish instead of sending the data twice. Zero-length packets are skipped
+/// rather than reported as the end of the stream.
+#[derive(Debug)]
+pub struct BulkStream {
+    io: AsyncIo,
+    in_endpoint: u8,
+    out_endpoint: u8,
+    transfer_size: usize,
+    read: Option<TransferFuture<Vec<u8>>>,
+    /// Data received beyond what the last read asked for.
+    buffered: Vec<u8>,
+    consumed: usize,
+    write: Option<TransferFuture<usize>>,
+}
+
+impl BulkStream {
+    /// Creates a stream reading from `in_endpoint` and writing to `out_endpoint`.
+    pub fn new(handle: &DeviceHandle, in_endpoint: u8, out_endpoint: u8) -> Result<Self> {
+        if in_endpoint & ENDPOINT_DIR_MASK != ENDPOINT_IN
+            || out_endpoint & ENDPOINT_DIR_MASK != ENDPOINT_OUT
+        {
+            return Err(Error::from_code(ERROR_INVALID_PARAMETER));
+        }
+        handle.check_claimed(in_endpoint)?;
+        handle.check_claimed(out_endpoint)?;
+        Ok(Self {
+            io: handle.async_io()?.clone(),
+            in_endpoint,
+            out_endpoint,
+            transfer_size: DEFAULT_TRANSFER_SIZE,
+            read: None,
+            buffered: Vec::new(),
+            consumed: 0,
+            write: None,
+        })
+    }
+
+    /// Sets the number of bytes requested from the IN endpoint at a time, which should be a
+    /// multiple of its maximum packet size.
+    pub fn set_transfer_size(&mut self, len: usize) {
+        self.transfer_size = len;
+    }
+}
+
+impl AsyncRead for BulkStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if buf.remaining() == 0 {
+            return Poll::Ready(Ok(()));
+        }
+        loop {
+            if this.consumed < this.buffered.len() {
+                let data = &this.buffered[this.consumed..];
+                let len = data.len().min(buf.remaining());
+                buf.put_slice(&data[..len]);
+                this.consumed += len;
+                return Poll::Ready(Ok(()));
+            }
+
+            let read = this
+                .read
+                .get_or_insert_with(|| this.io.read(this.in_endpoint, this.transfer_size));
+            let result = ready!(Pin::new(read).poll(cx));
+            this.read = None;
+            this.buffered = result?;
+            this.consumed = 0;
+        }
+    }
+}
+
+impl AsyncWrite for BulkStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if this.write.is_none() && buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        let write = this
+            .write
+            .get_or_insert_with(|| this.io.write(this.out_endpoint, buf.to_vec()));
+        let result = ready!(Pin::new(write).poll(cx));
+        this.write = None;
+        Poll::Ready(Ok(result?))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if let Some(write) = &mut this.write {
+            let result = ready!(Pin::new(write).poll(cx));
+            this.write = None;
+            result?;
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_flush(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::poll_fn;
+
+    use super::*;
+    use crate::async_io::tests::block_on;
+    use crate::backend::sim::tests::open;
+    use crate::backend::SimDevice;
+
+    #[test]
+    fn reads_and_writes_bulk_endpoints() {
+        let device = SimDevice::new(0x1234, 0x5678);
+        let handle = open(&device);
+        let mut stream = BulkStream::new(&handle, 0x81, 0x01).unwrap();
+        assert!(BulkStream::new(&handle, 0x01, 0x81).is_err());
+
+        device.push_read(0x81, &[]);
+        device.push_read(0x81, &[1, 2, 3, 4, 5]);
+        let mut buf = [0; 3];
+        let mut read = |stream: &mut BulkStream| {
+            let mut buf = ReadBuf::new(&mut buf);
+            block_on(poll_fn(|cx| Pin::new(&mut *stream).poll_read(cx, &mut buf))).unwrap();
+            buf.filled().to_vec()
+        };
+        assert_eq!(read(&mut stream), [1, 2, 3]);
+        assert_eq!(read(&mut stream), [4, 5]);
+
+        let written = block_on(poll_fn(|cx| Pin::new(&mut stream).poll_write(cx, &[6, 7])));
+        assert_eq!(written.unwrap(), 2);
+        block_on(poll_fn(|cx| Pin::new(&mut stream).poll_shutdown(cx))).unwrap();
+        assert_eq!(device.take_writes(0x01), vec![vec![6, 7]]);
+
+        drop(handle);
+        device.push_read(0x81, &[8]);
+        assert_eq!(read(&mut stream), [8]);
+    }
+}
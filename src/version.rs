@@ -55,7 +55,7 @@ pub fn version() -> LibraryVersion {
     LibraryVersion::new()
 }
 
-#[cfg(test)]
+#[cfg(all(test, windows))]
 mod tests {
     use super::*;
 